@@ -0,0 +1,602 @@
+use crate::{
+    Colums,
+    keys::{self, Cursor},
+    playback::DEFAULT_VELOCITY,
+    tracks::{MidiNote, Step, TrackerCmd},
+};
+use std::ops::RangeInclusive;
+
+/// the columns of the section grid, left to right.
+pub const COLUMNS: [Colums; 4] = [Colums::Note, Colums::Velocity, Colums::Cmd1, Colums::Cmd2];
+
+fn col_index(col: Colums) -> usize {
+    COLUMNS.iter().position(|other| *other == col).unwrap_or(0)
+}
+
+/// a rectangle of the section grid, from where the selection started to where the cursor is now.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Cursor,
+    pub head: Cursor,
+}
+
+impl Selection {
+    /// a selection of just the cell under `cursor`.
+    pub fn at(cursor: Cursor) -> Self {
+        Self {
+            anchor: cursor,
+            head: cursor,
+        }
+    }
+
+    pub fn rows(&self) -> RangeInclusive<usize> {
+        self.anchor.row.min(self.head.row)..=self.anchor.row.max(self.head.row)
+    }
+
+    /// the selected columns, left to right.
+    pub fn cols(&self) -> Vec<Colums> {
+        let (a, b) = (col_index(self.anchor.col), col_index(self.head.col));
+
+        COLUMNS[a.min(b)..=a.max(b)].to_vec()
+    }
+
+    pub fn contains(&self, row: usize, col: Colums) -> bool {
+        self.rows().contains(&row) && self.cols().contains(&col)
+    }
+}
+
+/// what gets copied. only `cols` of each step are meant to be pasted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub cols: Vec<Colums>,
+    pub steps: Vec<Step>,
+}
+
+/// copy the selected cells of `steps`.
+pub fn copy(steps: &[Step], selection: &Selection) -> Block {
+    let rows = selection.rows();
+    let end = (*rows.end() + 1).min(steps.len());
+
+    Block {
+        cols: selection.cols(),
+        steps: steps.get(*rows.start()..end).unwrap_or_default().to_vec(),
+    }
+}
+
+/// copy the selected cells of `steps`, then clear them.
+pub fn cut(steps: &mut [Step], selection: &Selection) -> Block {
+    let block = copy(steps, selection);
+
+    for row in selection.rows() {
+        if let Some(step) = steps.get_mut(row) {
+            block.cols.iter().for_each(|col| keys::clear(step, *col));
+        }
+    }
+
+    block
+}
+
+/// paste `block` with its top left corner at `at`. columns are only pasted onto columns of the
+/// same kind (either command column takes either command), and anything hanging past the bottom
+/// or the right edge of the grid is dropped. commands that count steps are cut down to fit `steps`.
+pub fn paste(steps: &mut [Step], block: &Block, at: Cursor) {
+    let first_col = col_index(at.col);
+    let len = steps.len();
+
+    for (i, from) in block.steps.iter().enumerate() {
+        let Some(to) = steps.get_mut(at.row + i) else {
+            break;
+        };
+
+        for (j, from_col) in block.cols.iter().enumerate() {
+            let Some(to_col) = COLUMNS.get(first_col + j) else {
+                break;
+            };
+
+            match (from_col, to_col) {
+                (Colums::Note, Colums::Note) => {
+                    to.note = from.note;
+                    to.velocity = from.velocity.or(to.velocity);
+                }
+                (Colums::Velocity, Colums::Velocity) => to.velocity = from.velocity,
+                (Colums::Cmd1 | Colums::Cmd2, Colums::Cmd1 | Colums::Cmd2) => {
+                    let mut cmd = match from_col {
+                        Colums::Cmd1 => from.cmds.0.clone(),
+                        _ => from.cmds.1.clone(),
+                    };
+                    cmd.clamp_to(len);
+
+                    match to_col {
+                        Colums::Cmd1 => to.cmds.0 = cmd,
+                        _ => to.cmds.1 = cmd,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// whether `cols` has a note's velocity in it. the note column takes its velocity along, the same
+/// way clearing a note clears its velocity.
+fn has_velocity(cols: &[Colums]) -> bool {
+    cols.contains(&Colums::Note) || cols.contains(&Colums::Velocity)
+}
+
+/// copy the `cols` cells of `from` onto `to`, leaving the rest of `to` as it was.
+fn set_cols(to: &mut Step, from: &Step, cols: &[Colums]) {
+    for col in cols {
+        match col {
+            Colums::Note => {
+                to.note = from.note;
+                to.velocity = from.velocity;
+            }
+            Colums::Velocity => to.velocity = from.velocity,
+            Colums::Cmd1 => to.cmds.0 = from.cmds.0.clone(),
+            Colums::Cmd2 => to.cmds.1 = from.cmds.1.clone(),
+        }
+    }
+}
+
+/// move rows around with `change`, but only the `cols` cells of them.
+fn rearrange(steps: &mut [Step], cols: &[Colums], change: impl FnOnce(&mut [Step])) {
+    let mut moved = steps.to_vec();
+    change(&mut moved);
+
+    for (step, from) in steps.iter_mut().zip(moved.iter()) {
+        set_cols(step, from, cols);
+    }
+}
+
+/// move every note by `semitones`, keeping them inside the midi range. only when the note column
+/// is in `cols`.
+pub fn transpose(steps: &mut [Step], cols: &[Colums], semitones: i16) {
+    if !cols.contains(&Colums::Note) {
+        return;
+    }
+
+    for note in steps.iter_mut().filter_map(|step| step.note.as_mut()) {
+        *note = (*note as i16 + semitones).clamp(0, 127) as MidiNote;
+    }
+}
+
+/// multiply the velocity of every note by `factor`. notes without a velocity are scaled from the
+/// default. only when the note or velocity column is in `cols`.
+pub fn scale_velocity(steps: &mut [Step], cols: &[Colums], factor: f32) {
+    if !has_velocity(cols) {
+        return;
+    }
+
+    for step in steps.iter_mut().filter(|step| step.note.is_some()) {
+        let velocity = step.velocity.unwrap_or(DEFAULT_VELOCITY) as f32 * factor;
+        step.velocity = Some(velocity.round().clamp(1.0, 127.0) as u8);
+    }
+}
+
+/// play the `cols` of the rows backwards.
+pub fn reverse(steps: &mut [Step], cols: &[Colums]) {
+    rearrange(steps, cols, |steps| steps.reverse());
+}
+
+/// move the `cols` of the rows down by `by` (up if negative), filling the gap left behind with
+/// empty cells.
+pub fn shift(steps: &mut [Step], cols: &[Colums], by: isize) {
+    rearrange(steps, cols, |steps| {
+        let len = steps.len();
+        let n = by.unsigned_abs().min(len);
+
+        if by >= 0 {
+            steps.rotate_right(n);
+            steps[..n].fill(Step::default());
+        } else {
+            steps.rotate_left(n);
+            steps[len - n..].fill(Step::default());
+        }
+    });
+}
+
+/// move the `cols` of the rows down by `by` (up if negative), wrapping around at the ends.
+pub fn rotate(steps: &mut [Step], cols: &[Colums], by: isize) {
+    if steps.is_empty() {
+        return;
+    }
+
+    let n = by.rem_euclid(steps.len() as isize) as usize;
+    rearrange(steps, cols, |steps| steps.rotate_right(n));
+}
+
+/// ramp velocity in a straight line from the first note to the last one, across every note in
+/// between. only when the note or velocity column is in `cols`.
+pub fn interpolate_velocity(steps: &mut [Step], cols: &[Colums]) {
+    if !has_velocity(cols) {
+        return;
+    }
+
+    let notes: Vec<usize> = (0..steps.len())
+        .filter(|i| steps[*i].note.is_some())
+        .collect();
+    let (Some(&first), Some(&last)) = (notes.first(), notes.last()) else {
+        return;
+    };
+    let velocity = |i: usize| steps[i].velocity.unwrap_or(DEFAULT_VELOCITY) as f32;
+    let (from, to) = (velocity(first), velocity(last));
+
+    for i in notes {
+        let t = if last == first {
+            0.0
+        } else {
+            (i - first) as f32 / (last - first) as f32
+        };
+
+        steps[i].velocity = Some((from + (to - from) * t).round() as u8);
+    }
+}
+
+/// ramp a CC from the first row to the last, in the command slot `col`. both ends have to
+/// already send the same CC, every row in between gets a value on the line between them.
+pub fn interpolate_cc(steps: &mut [Step], col: Colums) {
+    fn slot(step: &mut Step, col: Colums) -> Option<&mut TrackerCmd> {
+        match col {
+            Colums::Cmd1 => Some(&mut step.cmds.0),
+            Colums::Cmd2 => Some(&mut step.cmds.1),
+            _ => None,
+        }
+    }
+
+    let len = steps.len();
+
+    if len < 2 {
+        return;
+    }
+
+    let (
+        Some(TrackerCmd::MidiCmd {
+            cc_param,
+            arg: from,
+        }),
+        Some(TrackerCmd::MidiCmd {
+            cc_param: to_param,
+            arg: to,
+        }),
+    ) = (
+        slot(&mut steps[0], col).cloned(),
+        slot(&mut steps[len - 1], col).cloned(),
+    )
+    else {
+        return;
+    };
+
+    if cc_param != to_param {
+        return;
+    }
+
+    for (i, step) in steps.iter_mut().enumerate() {
+        let t = i as f32 / (len - 1) as f32;
+        let arg = (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+
+        if let Some(cmd) = slot(step, col) {
+            *cmd = TrackerCmd::MidiCmd { cc_param, arg };
+        }
+    }
+}
+
+/// copy the `cols` of the first row onto every `n`th row after it.
+pub fn fill_every(steps: &mut [Step], cols: &[Colums], n: usize) {
+    let Some(first) = steps.first().cloned() else {
+        return;
+    };
+
+    for step in steps.iter_mut().step_by(n.max(1)).skip(1) {
+        set_cols(step, &first, cols);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::less_then::UsizeLessThan;
+
+    fn notes(notes: &[Option<MidiNote>]) -> Vec<Step> {
+        notes
+            .iter()
+            .map(|note| Step {
+                note: *note,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn get_notes(steps: &[Step]) -> Vec<Option<MidiNote>> {
+        steps.iter().map(|step| step.note).collect()
+    }
+
+    fn cc(cc_param: u8, arg: u8) -> TrackerCmd {
+        TrackerCmd::MidiCmd { cc_param, arg }
+    }
+
+    #[test]
+    fn selection() {
+        let selection = Selection {
+            anchor: Cursor {
+                row: 9,
+                col: Colums::Cmd1,
+            },
+            head: Cursor {
+                row: 3,
+                col: Colums::Velocity,
+            },
+        };
+
+        assert_eq!(selection.rows(), 3..=9);
+        assert_eq!(selection.cols(), vec![Colums::Velocity, Colums::Cmd1]);
+        assert!(selection.contains(5, Colums::Cmd1));
+        assert!(!selection.contains(5, Colums::Note));
+        assert!(!selection.contains(10, Colums::Velocity));
+    }
+
+    #[test]
+    fn copy_cut_and_paste() {
+        let mut from = notes(&[Some(60), Some(62), Some(64), None]);
+        from[1].velocity = Some(100);
+        from[1].cmds = (cc(1, 2), TrackerCmd::Panic);
+        let note_and_velocity = Selection {
+            anchor: Cursor {
+                row: 0,
+                col: Colums::Note,
+            },
+            head: Cursor {
+                row: 1,
+                col: Colums::Velocity,
+            },
+        };
+
+        let block = cut(&mut from, &note_and_velocity);
+        assert_eq!(block.steps.len(), 2);
+        assert_eq!(get_notes(&from), vec![None, None, Some(64), None]);
+        // the commands weren't selected, so they stay
+        assert_eq!(from[1].cmds, (cc(1, 2), TrackerCmd::Panic));
+
+        let mut to = notes(&[None; 4]);
+        paste(
+            &mut to,
+            &block,
+            Cursor {
+                row: 3,
+                col: Colums::Note,
+            },
+        );
+        // only the first row fits
+        assert_eq!(get_notes(&to), vec![None, None, None, Some(60)]);
+
+        paste(
+            &mut to,
+            &block,
+            Cursor {
+                row: 0,
+                col: Colums::Note,
+            },
+        );
+        assert_eq!(to[1].note, Some(62));
+        assert_eq!(to[1].velocity, Some(100));
+    }
+
+    #[test]
+    fn paste_only_onto_matching_columns() {
+        let mut from = notes(&[Some(60)]);
+        from[0].cmds.0 = cc(7, 100);
+        let cmd_1 = Selection::at(Cursor {
+            row: 0,
+            col: Colums::Cmd1,
+        });
+        let block = copy(&from, &cmd_1);
+
+        let mut to = notes(&[None]);
+        paste(
+            &mut to,
+            &block,
+            Cursor {
+                row: 0,
+                col: Colums::Cmd2,
+            },
+        );
+        assert_eq!(to[0].cmds, (TrackerCmd::None, cc(7, 100)));
+
+        paste(
+            &mut to,
+            &block,
+            Cursor {
+                row: 0,
+                col: Colums::Note,
+            },
+        );
+        assert_eq!(to[0].note, None);
+    }
+
+    #[test]
+    fn paste_into_a_shorter_section() {
+        let mut from = notes(&[None; 64]);
+        from[0].cmds = (
+            TrackerCmd::Roll {
+                times: UsizeLessThan(100),
+            },
+            TrackerCmd::HoldFor {
+                notes: UsizeLessThan(40),
+            },
+        );
+        let cmds = Selection {
+            anchor: Cursor {
+                row: 0,
+                col: Colums::Cmd1,
+            },
+            head: Cursor {
+                row: 0,
+                col: Colums::Cmd2,
+            },
+        };
+        let block = copy(&from, &cmds);
+
+        let mut to = notes(&[None; 16]);
+        paste(
+            &mut to,
+            &block,
+            Cursor {
+                row: 0,
+                col: Colums::Cmd1,
+            },
+        );
+        assert_eq!(
+            to[0].cmds,
+            (
+                TrackerCmd::Roll {
+                    times: UsizeLessThan(30),
+                },
+                TrackerCmd::HoldFor {
+                    notes: UsizeLessThan(15),
+                },
+            )
+        );
+        assert!(to[0].cmds.0.fits(to.len()) && to[0].cmds.1.fits(to.len()));
+    }
+
+    #[test]
+    fn transposing() {
+        let mut steps = notes(&[Some(60), None, Some(2), Some(125)]);
+
+        transpose(&mut steps, &COLUMNS, 12);
+        assert_eq!(get_notes(&steps), vec![Some(72), None, Some(14), Some(127)]);
+
+        transpose(&mut steps, &COLUMNS, -13);
+        assert_eq!(get_notes(&steps), vec![Some(59), None, Some(1), Some(114)]);
+    }
+
+    #[test]
+    fn scaling_velocity() {
+        let mut steps = notes(&[Some(60), None, Some(60), Some(60)]);
+        steps[2].velocity = Some(100);
+        steps[3].velocity = Some(1);
+
+        scale_velocity(&mut steps, &COLUMNS, 2.0);
+        assert_eq!(
+            steps.iter().map(|step| step.velocity).collect::<Vec<_>>(),
+            vec![Some(127), None, Some(127), Some(2)]
+        );
+
+        scale_velocity(&mut steps, &COLUMNS, 0.0);
+        assert_eq!(steps[3].velocity, Some(1));
+    }
+
+    #[test]
+    fn reversing() {
+        let mut steps = notes(&[Some(1), None, Some(3)]);
+        reverse(&mut steps, &COLUMNS);
+
+        assert_eq!(get_notes(&steps), vec![Some(3), None, Some(1)]);
+    }
+
+    #[test]
+    fn shifting_and_rotating() {
+        let start = notes(&[Some(1), Some(2), Some(3), Some(4)]);
+
+        let mut steps = start.clone();
+        shift(&mut steps, &COLUMNS, 1);
+        assert_eq!(get_notes(&steps), vec![None, Some(1), Some(2), Some(3)]);
+
+        let mut steps = start.clone();
+        shift(&mut steps, &COLUMNS, -2);
+        assert_eq!(get_notes(&steps), vec![Some(3), Some(4), None, None]);
+
+        let mut steps = start.clone();
+        shift(&mut steps, &COLUMNS, 10);
+        assert_eq!(get_notes(&steps), vec![None; 4]);
+
+        let mut steps = start.clone();
+        rotate(&mut steps, &COLUMNS, 1);
+        assert_eq!(get_notes(&steps), vec![Some(4), Some(1), Some(2), Some(3)]);
+
+        let mut steps = start.clone();
+        rotate(&mut steps, &COLUMNS, -5);
+        assert_eq!(get_notes(&steps), vec![Some(2), Some(3), Some(4), Some(1)]);
+    }
+
+    #[test]
+    fn interpolating_velocity() {
+        let mut steps = notes(&[None, Some(60), Some(60), None, Some(60), Some(60), None]);
+        steps[1].velocity = Some(10);
+        steps[5].velocity = Some(110);
+
+        interpolate_velocity(&mut steps, &COLUMNS);
+
+        assert_eq!(
+            steps.iter().map(|step| step.velocity).collect::<Vec<_>>(),
+            vec![None, Some(10), Some(35), None, Some(85), Some(110), None]
+        );
+    }
+
+    #[test]
+    fn interpolating_cc() {
+        let mut steps = notes(&[None; 5]);
+        steps[0].cmds.1 = cc(74, 0);
+        steps[4].cmds.1 = cc(74, 100);
+
+        interpolate_cc(&mut steps, Colums::Cmd2);
+        assert_eq!(
+            steps
+                .iter()
+                .map(|step| step.cmds.1.clone())
+                .collect::<Vec<_>>(),
+            vec![cc(74, 0), cc(74, 25), cc(74, 50), cc(74, 75), cc(74, 100)]
+        );
+
+        // the ends have to agree on the CC
+        let mut steps = notes(&[None; 3]);
+        steps[0].cmds.0 = cc(74, 0);
+        steps[2].cmds.0 = cc(1, 100);
+        interpolate_cc(&mut steps, Colums::Cmd1);
+        assert_eq!(steps[1].cmds.0, TrackerCmd::None);
+    }
+
+    #[test]
+    fn filling() {
+        let mut steps = notes(&[Some(36), None, None, Some(40), None, None, None]);
+        steps[0].velocity = Some(99);
+
+        fill_every(&mut steps, &COLUMNS, 2);
+        assert_eq!(
+            get_notes(&steps),
+            vec![Some(36), None, Some(36), Some(40), Some(36), None, Some(36)]
+        );
+        assert_eq!(steps[6].velocity, Some(99));
+    }
+
+    #[test]
+    fn only_the_selected_columns() {
+        let mut steps = notes(&[Some(60), None, Some(62)]);
+        steps[0].velocity = Some(10);
+        steps[0].cmds.0 = cc(1, 2);
+        let cmds = [Colums::Cmd1, Colums::Cmd2];
+
+        let before = steps.clone();
+        transpose(&mut steps, &cmds, 12);
+        scale_velocity(&mut steps, &cmds, 2.0);
+        interpolate_velocity(&mut steps, &cmds);
+        assert_eq!(steps, before);
+
+        // the commands move, the notes stay
+        reverse(&mut steps, &cmds);
+        assert_eq!(get_notes(&steps), vec![Some(60), None, Some(62)]);
+        assert_eq!(steps[0].cmds.0, TrackerCmd::None);
+        assert_eq!(steps[2].cmds.0, cc(1, 2));
+
+        // and a note takes its velocity along
+        shift(&mut steps, &[Colums::Note], 1);
+        assert_eq!(get_notes(&steps), vec![None, Some(60), None]);
+        assert_eq!(steps[1].velocity, Some(10));
+        assert_eq!(steps[2].cmds.0, cc(1, 2));
+
+        rotate(&mut steps, &[Colums::Cmd1], 1);
+        fill_every(&mut steps, &[Colums::Cmd1], 2);
+        assert_eq!(steps[0].cmds.0, cc(1, 2));
+        assert_eq!(steps[2].cmds.0, cc(1, 2));
+        assert_eq!(get_notes(&steps), vec![None, Some(60), None]);
+    }
+}
@@ -0,0 +1,565 @@
+use crate::tracks::{Step, Track};
+use std::time::{Duration, Instant};
+
+/// edits to the same step made closer together than this are undone together, so typing a
+/// velocity or dragging a slider doesn't fill the history up.
+pub const COALESCE_WINDOW: Duration = Duration::from_millis(500);
+
+/// a change to the sections that knows how to take itself back. sections are addressed by their
+/// index.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Step {
+        section: usize,
+        row: usize,
+        before: Step,
+        after: Step,
+    },
+    /// every step of a section at once, for block operations.
+    Steps {
+        section: usize,
+        before: Vec<Step>,
+        after: Vec<Step>,
+    },
+    /// anything else about a section, like its length or time signature.
+    Section {
+        section: usize,
+        before: Track,
+        after: Track,
+    },
+    Rename {
+        section: usize,
+        before: String,
+        after: String,
+    },
+    Insert {
+        index: usize,
+        track: Track,
+    },
+    Remove {
+        index: usize,
+        track: Track,
+    },
+    /// move a section from one place in the list to another.
+    Move {
+        from: usize,
+        to: usize,
+    },
+}
+
+impl Edit {
+    fn apply(&self, sections: &mut Vec<Track>) {
+        match self {
+            Self::Step {
+                section,
+                row,
+                after,
+                ..
+            } => {
+                if let Some(step) = sections
+                    .get_mut(*section)
+                    .and_then(|track| track.steps.get_mut(*row))
+                {
+                    *step = after.clone();
+                }
+            }
+            Self::Steps { section, after, .. } => {
+                if let Some(track) = sections.get_mut(*section) {
+                    track.steps = after.clone();
+                }
+            }
+            Self::Section { section, after, .. } => {
+                if let Some(track) = sections.get_mut(*section) {
+                    *track = after.clone();
+                }
+            }
+            Self::Rename { section, after, .. } => {
+                if let Some(track) = sections.get_mut(*section) {
+                    track.name = after.clone();
+                }
+            }
+            Self::Insert { index, track } => {
+                sections.insert((*index).min(sections.len()), track.clone())
+            }
+            Self::Remove { index, .. } => {
+                if *index < sections.len() {
+                    sections.remove(*index);
+                }
+            }
+            Self::Move { from, to } => {
+                if *from < sections.len() && *to < sections.len() {
+                    let track = sections.remove(*from);
+                    sections.insert(*to, track);
+                }
+            }
+        }
+    }
+
+    /// the edit that undoes this one.
+    fn inverse(&self) -> Self {
+        match self.clone() {
+            Self::Step {
+                section,
+                row,
+                before,
+                after,
+            } => Self::Step {
+                section,
+                row,
+                before: after,
+                after: before,
+            },
+            Self::Steps {
+                section,
+                before,
+                after,
+            } => Self::Steps {
+                section,
+                before: after,
+                after: before,
+            },
+            Self::Section {
+                section,
+                before,
+                after,
+            } => Self::Section {
+                section,
+                before: after,
+                after: before,
+            },
+            Self::Rename {
+                section,
+                before,
+                after,
+            } => Self::Rename {
+                section,
+                before: after,
+                after: before,
+            },
+            Self::Insert { index, track } => Self::Remove { index, track },
+            Self::Remove { index, track } => Self::Insert { index, track },
+            Self::Move { from, to } => Self::Move { from: to, to: from },
+        }
+    }
+
+    /// the whole tracks this edit keeps a copy of.
+    fn tracks_mut(&mut self) -> Vec<&mut Track> {
+        match self {
+            Self::Section { before, after, .. } => vec![before, after],
+            Self::Insert { track, .. } | Self::Remove { track, .. } => vec![track],
+            _ => Vec::new(),
+        }
+    }
+
+    /// fold `next` into this edit, if they both change the same step.
+    fn coalesce(&mut self, next: &Self) -> bool {
+        match (self, next) {
+            (
+                Self::Step {
+                    section,
+                    row,
+                    after,
+                    ..
+                },
+                Self::Step {
+                    section: next_section,
+                    row: next_row,
+                    after: next_after,
+                    ..
+                },
+            ) if (section, row) == (next_section, next_row) => {
+                *after = next_after.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// every edit made to the sections, for undo and redo.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// when the last edit was made, `None` right after an undo or redo so that the next edit
+    /// always starts a new entry.
+    last_edit: Option<Instant>,
+}
+
+impl History {
+    /// make `edit` and remember it.
+    pub fn apply(&mut self, sections: &mut Vec<Track>, edit: Edit) {
+        self.apply_at(sections, edit, Instant::now());
+    }
+
+    /// `apply`, as if it happened at `now`.
+    pub fn apply_at(&mut self, sections: &mut Vec<Track>, edit: Edit, now: Instant) {
+        edit.apply(sections);
+        self.redo.clear();
+
+        let rapid = self
+            .last_edit
+            .is_some_and(|last| now.saturating_duration_since(last) < COALESCE_WINDOW);
+        self.last_edit = Some(now);
+
+        if rapid && let Some(last) = self.undo.last_mut() && last.coalesce(&edit) {
+            return;
+        }
+
+        self.undo.push(edit);
+    }
+
+    /// change a single step, doing nothing if `change` leaves it as it was.
+    pub fn edit_step(
+        &mut self,
+        sections: &mut Vec<Track>,
+        section: usize,
+        row: usize,
+        change: impl FnOnce(&mut Step),
+    ) {
+        let Some(before) = sections
+            .get(section)
+            .and_then(|track| track.steps.get(row))
+            .cloned()
+        else {
+            return;
+        };
+        let mut after = before.clone();
+        change(&mut after);
+
+        if after != before {
+            self.apply(
+                sections,
+                Edit::Step {
+                    section,
+                    row,
+                    before,
+                    after,
+                },
+            );
+        }
+    }
+
+    /// change any of the steps of a section in one go, doing nothing if they all stay the same.
+    pub fn edit_steps(
+        &mut self,
+        sections: &mut Vec<Track>,
+        section: usize,
+        change: impl FnOnce(&mut Vec<Step>),
+    ) {
+        let Some(before) = sections.get(section).map(|track| track.steps.clone()) else {
+            return;
+        };
+        let mut after = before.clone();
+        change(&mut after);
+
+        if after != before {
+            self.apply(
+                sections,
+                Edit::Steps {
+                    section,
+                    before,
+                    after,
+                },
+            );
+        }
+    }
+
+    /// change anything about a section in one go, doing nothing if it stays the same.
+    pub fn edit_section(
+        &mut self,
+        sections: &mut Vec<Track>,
+        section: usize,
+        change: impl FnOnce(&mut Track),
+    ) {
+        let Some(before) = sections.get(section).cloned() else {
+            return;
+        };
+        let mut after = before.clone();
+        change(&mut after);
+
+        if after != before {
+            self.apply(
+                sections,
+                Edit::Section {
+                    section,
+                    before,
+                    after,
+                },
+            );
+        }
+    }
+
+    pub fn rename(&mut self, sections: &mut Vec<Track>, section: usize, name: String) {
+        let Some(before) = sections.get(section).map(|track| track.name.clone()) else {
+            return;
+        };
+
+        if before != name {
+            self.apply(
+                sections,
+                Edit::Rename {
+                    section,
+                    before,
+                    after: name,
+                },
+            );
+        }
+    }
+
+    pub fn insert(&mut self, sections: &mut Vec<Track>, index: usize, track: Track) {
+        let index = index.min(sections.len());
+        self.apply(sections, Edit::Insert { index, track });
+    }
+
+    pub fn remove(&mut self, sections: &mut Vec<Track>, index: usize) {
+        if let Some(track) = sections.get(index).cloned() {
+            self.apply(sections, Edit::Remove { index, track });
+        }
+    }
+
+    /// move the section at `from` so that it ends up at `to`.
+    pub fn move_section(&mut self, sections: &mut Vec<Track>, from: usize, to: usize) {
+        if from != to && from < sections.len() && to < sections.len() {
+            self.apply(sections, Edit::Move { from, to });
+        }
+    }
+
+    /// take back the last edit. returns false if there was nothing to undo.
+    pub fn undo(&mut self, sections: &mut Vec<Track>) -> bool {
+        let Some(edit) = self.undo.pop() else {
+            return false;
+        };
+
+        edit.inverse().apply(sections);
+        self.redo.push(edit);
+        self.last_edit = None;
+
+        true
+    }
+
+    /// make the last undone edit again. returns false if there was nothing to redo.
+    pub fn redo(&mut self, sections: &mut Vec<Track>) -> bool {
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+
+        edit.apply(sections);
+        self.undo.push(edit);
+        self.last_edit = None;
+
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// point the copies of sections kept for undo and redo at the synth `to` instead of `from`,
+    /// after the synth is renamed or removed, so that taking an edit back doesn't bring the old
+    /// name back.
+    pub fn rename_dev(&mut self, from: &str, to: &str) {
+        self.undo
+            .iter_mut()
+            .chain(self.redo.iter_mut())
+            .flat_map(Edit::tracks_mut)
+            .filter(|track| track.dev == from)
+            .for_each(|track| track.dev = to.to_string());
+    }
+
+    /// forget everything, as when a project is loaded.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracks::TrackerCmd;
+
+    fn sections() -> Vec<Track> {
+        vec![
+            Track::new(None, 0, "Default".into()),
+            Track::new(None, 1, "Default".into()),
+        ]
+    }
+
+    fn note(note: u8) -> Step {
+        Step {
+            note: Some(note),
+            ..Default::default()
+        }
+    }
+
+    fn set_step(section: usize, row: usize, before: Step, after: Step) -> Edit {
+        Edit::Step {
+            section,
+            row,
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_everything() {
+        let original = sections();
+        let mut tracks = original.clone();
+        let mut history = History::default();
+        let start = Instant::now();
+        let edits = [
+            set_step(0, 0, Step::default(), note(60)),
+            set_step(1, 5, Step::default(), note(62)),
+            Edit::Rename {
+                section: 0,
+                before: "UNNAMED-0".into(),
+                after: "Verse".into(),
+            },
+            Edit::Insert {
+                index: 1,
+                track: Track::new(Some("Chorus".into()), 2, "Bass".into()),
+            },
+            Edit::Remove {
+                index: 0,
+                track: original[0].clone(),
+            },
+            Edit::Move { from: 1, to: 0 },
+        ];
+        let mut states = vec![tracks.clone()];
+
+        // a second apart, so nothing coalesces
+        for (i, edit) in edits.into_iter().enumerate() {
+            history.apply_at(&mut tracks, edit, start + Duration::from_secs(i as u64));
+            states.push(tracks.clone());
+        }
+
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[1].name, "Chorus");
+
+        for state in states.iter().rev().skip(1) {
+            assert!(history.undo(&mut tracks));
+            assert_eq!(&tracks, state);
+        }
+
+        assert!(!history.undo(&mut tracks));
+        assert_eq!(tracks, original);
+
+        for state in states.iter().skip(1) {
+            assert!(history.redo(&mut tracks));
+            assert_eq!(&tracks, state);
+        }
+
+        assert!(!history.redo(&mut tracks));
+    }
+
+    #[test]
+    fn rapid_edits_to_one_step_coalesce() {
+        let mut tracks = sections();
+        let mut history = History::default();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let velocity = |velocity| Step {
+            note: Some(60),
+            velocity: Some(velocity),
+            ..Default::default()
+        };
+
+        history.apply_at(&mut tracks, set_step(0, 0, Step::default(), velocity(5)), ms(0));
+        history.apply_at(&mut tracks, set_step(0, 0, velocity(5), velocity(0x56)), ms(100));
+        history.apply_at(&mut tracks, set_step(0, 0, velocity(0x56), velocity(0x64)), ms(200));
+        // a different step doesn't coalesce, even when it's quick
+        history.apply_at(&mut tracks, set_step(0, 1, Step::default(), note(1)), ms(300));
+        // and neither does a slow edit
+        history.apply_at(&mut tracks, set_step(0, 1, note(1), note(2)), ms(2000));
+
+        history.undo(&mut tracks);
+        assert_eq!(tracks[0].steps[1], note(1));
+        history.undo(&mut tracks);
+        assert_eq!(tracks[0].steps[1], Step::default());
+        assert_eq!(tracks[0].steps[0], velocity(0x64));
+        history.undo(&mut tracks);
+        assert_eq!(tracks, sections());
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn block_edits_undo_in_one_go() {
+        let mut tracks = sections();
+        let mut history = History::default();
+
+        history.edit_steps(&mut tracks, 1, |steps| {
+            steps[0].note = Some(60);
+            steps[100].note = Some(61);
+            steps.reverse();
+        });
+        assert_eq!(tracks[1].steps[127].note, Some(60));
+        assert_eq!(tracks[1].steps[27].note, Some(61));
+
+        history.undo(&mut tracks);
+        assert_eq!(tracks, sections());
+
+        history.edit_section(&mut tracks, 0, |track| {
+            track.set_len(16);
+            track.lpb = 3;
+        });
+        assert_eq!((tracks[0].steps.len(), tracks[0].lpb), (16, 3));
+
+        history.undo(&mut tracks);
+        assert_eq!(tracks, sections());
+    }
+
+    #[test]
+    fn renamed_devs_stay_renamed() {
+        let mut tracks = sections();
+        let mut history = History::default();
+
+        history.edit_section(&mut tracks, 0, |track| track.dev = "Bass".into());
+        history.edit_section(&mut tracks, 1, |track| track.lpb = 3);
+        history.undo(&mut tracks);
+        // what the synth rename does to the sections themselves
+        crate::tracks::rename_dev(&mut tracks, "Bass", "Sub");
+        history.rename_dev("Bass", "Sub");
+
+        history.redo(&mut tracks);
+        assert_eq!(tracks[0].dev, "Sub");
+        history.undo(&mut tracks);
+        history.undo(&mut tracks);
+        assert_eq!(tracks, sections());
+        history.redo(&mut tracks);
+        assert_eq!(tracks[0].dev, "Sub");
+    }
+
+    #[test]
+    fn new_edits_drop_the_redo_stack() {
+        let mut tracks = sections();
+        let mut history = History::default();
+
+        history.edit_step(&mut tracks, 0, 0, |step| step.note = Some(60));
+        history.undo(&mut tracks);
+        assert!(history.can_redo());
+
+        history.edit_step(&mut tracks, 0, 0, |step| step.cmds.0 = TrackerCmd::Panic);
+        assert!(!history.can_redo());
+        assert_eq!(tracks[0].steps[0].note, None);
+    }
+
+    #[test]
+    fn no_op_edits_are_not_recorded() {
+        let mut tracks = sections();
+        let mut history = History::default();
+
+        history.edit_step(&mut tracks, 0, 0, |_step| {});
+        history.edit_step(&mut tracks, 9, 0, |step| step.note = Some(1));
+        history.rename(&mut tracks, 0, "UNNAMED-0".into());
+        history.remove(&mut tracks, 9);
+        history.move_section(&mut tracks, 1, 1);
+        history.move_section(&mut tracks, 0, 2);
+
+        assert!(!history.can_undo());
+        assert_eq!(tracks, sections());
+    }
+}
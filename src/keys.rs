@@ -0,0 +1,370 @@
+use crate::{
+    Colums,
+    block::COLUMNS,
+    tracks::{MidiNote, Step, TrackerCmd},
+};
+use dioxus::prelude::{Key, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+
+/// where custom key bindings are read from, in RON. any key left out keeps its default.
+pub const KEY_BINDINGS_FILE: &str = "keys.ron";
+/// the octave notes are entered in when the app starts. (C-5 is middle C)
+pub const DEFAULT_OCTAVE: u8 = 5;
+pub const MAX_OCTAVE: u8 = 9;
+/// how many rows PageUp and PageDown jump.
+const PAGE: usize = 16;
+
+/// something a key can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    OctaveUp,
+    OctaveDown,
+    /// enter a note, this many semi-tones above C of the current octave.
+    Note(u8),
+    /// clear the cell under the cursor.
+    Delete,
+    /// open the edit menu on the cell under the cursor.
+    Edit,
+    PlayStop,
+    NextSection,
+    PrevSection,
+    Undo,
+    Redo,
+    /// grow or shrink the selection, starting one at the cursor if there isn't one.
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    Copy,
+    Cut,
+    Paste,
+    /// move the selected notes a semi-tone.
+    TransposeUp,
+    TransposeDown,
+}
+
+/// what a key press means, given the column the cursor is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyInput {
+    Action(Action),
+    /// a hex digit typed into the velocity column.
+    Hex(u8),
+}
+
+/// maps key names (as made by `key_name`) to actions.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    /// work in every column.
+    pub global: HashMap<String, Action>,
+    /// only work in the note column, so that they can share keys with hex entry.
+    pub notes: HashMap<String, Action>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let global = [
+            ("ArrowUp", Action::Up),
+            ("ArrowDown", Action::Down),
+            ("ArrowLeft", Action::Left),
+            ("ArrowRight", Action::Right),
+            ("PageUp", Action::PageUp),
+            ("PageDown", Action::PageDown),
+            ("Home", Action::Top),
+            ("End", Action::Bottom),
+            ("Ctrl+ArrowUp", Action::OctaveUp),
+            ("Ctrl+ArrowDown", Action::OctaveDown),
+            ("Delete", Action::Delete),
+            ("Backspace", Action::Delete),
+            ("Enter", Action::Edit),
+            ("Space", Action::PlayStop),
+            ("Ctrl+ArrowRight", Action::NextSection),
+            ("Ctrl+ArrowLeft", Action::PrevSection),
+            ("Ctrl+z", Action::Undo),
+            ("Ctrl+y", Action::Redo),
+            ("Shift+ArrowUp", Action::SelectUp),
+            ("Shift+ArrowDown", Action::SelectDown),
+            ("Shift+ArrowLeft", Action::SelectLeft),
+            ("Shift+ArrowRight", Action::SelectRight),
+            ("Ctrl+c", Action::Copy),
+            ("Ctrl+x", Action::Cut),
+            ("Ctrl+v", Action::Paste),
+            ("Alt+ArrowUp", Action::TransposeUp),
+            ("Alt+ArrowDown", Action::TransposeDown),
+        ];
+        // the usual tracker piano layout, two octaves over the bottom and top letter rows.
+        let lower = ["z", "s", "x", "d", "c", "v", "g", "b", "h", "n", "j", "m", ",", "l", "."];
+        let upper = [
+            "q", "2", "w", "3", "e", "r", "5", "t", "6", "y", "7", "u", "i", "9", "o", "0", "p",
+        ];
+        let notes = lower
+            .iter()
+            .enumerate()
+            .chain(upper.iter().enumerate().map(|(i, key)| (i + 12, key)))
+            .map(|(semitone, key)| (key.to_string(), Action::Note(semitone as u8)));
+
+        Self {
+            global: global
+                .into_iter()
+                .map(|(key, action)| (key.to_string(), action))
+                .collect(),
+            notes: notes.collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// read bindings from `path`. bindings in the file replace the defaults for the same keys.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        let custom: Self =
+            ron::from_str(&text).map_err(|e| format!("failed to read key bindings: {e}"))?;
+        let mut bindings = Self::default();
+        bindings.global.extend(custom.global);
+        bindings.notes.extend(custom.notes);
+
+        Ok(bindings)
+    }
+
+    /// what pressing `key` does with the cursor in `col`.
+    pub fn input(&self, key: &str, col: Colums) -> Option<KeyInput> {
+        if let Some(action) = self.global.get(key) {
+            return Some(KeyInput::Action(*action));
+        }
+
+        match col {
+            Colums::Note => self.notes.get(key).copied().map(KeyInput::Action),
+            Colums::Velocity => {
+                let mut chars = key.chars();
+
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c.to_digit(16).map(|digit| KeyInput::Hex(digit as u8)),
+                    _ => None,
+                }
+            }
+            Colums::Cmd1 | Colums::Cmd2 => None,
+        }
+    }
+}
+
+/// the name a key press is bound by, like "a", "Space", "ArrowUp", "Ctrl+z" or "Shift+ArrowUp".
+/// shift is left out for characters, since it's already in the character itself.
+pub fn key_name(key: &Key, modifiers: Modifiers) -> String {
+    let mut name = String::new();
+
+    if modifiers.ctrl() {
+        name.push_str("Ctrl+");
+    }
+
+    if modifiers.alt() {
+        name.push_str("Alt+");
+    }
+
+    if modifiers.shift() && !matches!(key, Key::Character(_)) {
+        name.push_str("Shift+");
+    }
+
+    match key {
+        Key::Character(c) if c == " " => name.push_str("Space"),
+        Key::Character(c) => name.push_str(&c.to_lowercase()),
+        other => name.push_str(&other.to_string()),
+    }
+
+    name
+}
+
+/// where the keyboard cursor is in the section grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: Colums,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            row: 0,
+            col: Colums::Note,
+        }
+    }
+}
+
+impl Cursor {
+    /// the cursor after a movement `action`, in a grid of `n_rows`. moving past the top or bottom
+    /// wraps around, like the section does when it plays.
+    pub fn moved(self, action: Action, n_rows: usize) -> Self {
+        let n_rows = n_rows.max(1);
+        let col = COLUMNS.iter().position(|col| *col == self.col).unwrap_or(0);
+
+        match action {
+            Action::Up => Self {
+                row: (self.row + n_rows - 1) % n_rows,
+                ..self
+            },
+            Action::Down => Self {
+                row: (self.row + 1) % n_rows,
+                ..self
+            },
+            Action::PageUp => Self {
+                row: self.row.saturating_sub(PAGE),
+                ..self
+            },
+            Action::PageDown => Self {
+                row: (self.row + PAGE).min(n_rows - 1),
+                ..self
+            },
+            Action::Top => Self { row: 0, ..self },
+            Action::Bottom => Self {
+                row: n_rows - 1,
+                ..self
+            },
+            // selections stop at the edges instead of wrapping
+            Action::SelectUp => Self {
+                row: self.row.saturating_sub(1),
+                ..self
+            },
+            Action::SelectDown => Self {
+                row: (self.row + 1).min(n_rows - 1),
+                ..self
+            },
+            Action::SelectLeft => self.moved(Action::Left, n_rows),
+            Action::SelectRight => self.moved(Action::Right, n_rows),
+            Action::Left => Self {
+                col: COLUMNS[col.saturating_sub(1)],
+                ..self
+            },
+            Action::Right => Self {
+                col: COLUMNS[(col + 1).min(COLUMNS.len() - 1)],
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+/// the note `semitone` keys up from C in `octave`, numbered the same way as `display_midi_note`.
+pub fn note_in_octave(octave: u8, semitone: u8) -> Option<MidiNote> {
+    let note = octave as usize * 12 + semitone as usize;
+
+    (note <= 127).then_some(note as MidiNote)
+}
+
+/// shift a hex digit into a velocity from the right, so typing "6" then "4" gives 0x64.
+pub fn enter_hex(velocity: u8, digit: u8) -> u8 {
+    ((((velocity as u16) << 4) | (digit as u16 & 0xF)) & 0xFF).min(127) as u8
+}
+
+/// empty the `col` cell of `step`. clearing a note clears its velocity with it.
+pub fn clear(step: &mut Step, col: Colums) {
+    match col {
+        Colums::Note => {
+            step.note = None;
+            step.velocity = None;
+        }
+        Colums::Velocity => step.velocity = None,
+        Colums::Cmd1 => step.cmds.0 = TrackerCmd::None,
+        Colums::Cmd2 => step.cmds.1 = TrackerCmd::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piano_keys_only_in_the_note_column() {
+        let keys = KeyBindings::default();
+
+        assert_eq!(keys.input("z", Colums::Note), Some(KeyInput::Action(Action::Note(0))));
+        assert_eq!(keys.input("q", Colums::Note), Some(KeyInput::Action(Action::Note(12))));
+        assert_eq!(keys.input("p", Colums::Note), Some(KeyInput::Action(Action::Note(28))));
+        assert_eq!(keys.input("c", Colums::Velocity), Some(KeyInput::Hex(0xC)));
+        assert_eq!(keys.input("2", Colums::Velocity), Some(KeyInput::Hex(2)));
+        assert_eq!(keys.input("z", Colums::Velocity), None);
+        assert_eq!(keys.input("z", Colums::Cmd1), None);
+        assert_eq!(
+            keys.input("Delete", Colums::Cmd2),
+            Some(KeyInput::Action(Action::Delete))
+        );
+    }
+
+    #[test]
+    fn key_names() {
+        assert_eq!(key_name(&Key::Character("A".into()), Modifiers::empty()), "a");
+        assert_eq!(key_name(&Key::Character(" ".into()), Modifiers::empty()), "Space");
+        assert_eq!(key_name(&Key::ArrowUp, Modifiers::CONTROL), "Ctrl+ArrowUp");
+        assert_eq!(key_name(&Key::ArrowDown, Modifiers::SHIFT), "Shift+ArrowDown");
+        assert_eq!(key_name(&Key::Character("Z".into()), Modifiers::SHIFT), "z");
+    }
+
+    #[test]
+    fn custom_bindings_extend_the_defaults() {
+        let path = std::env::temp_dir().join("tracker-synth-test-keys.ron");
+        fs::write(&path, r#"(global: { "k": PlayStop, "Space": Edit })"#).unwrap();
+        let keys = KeyBindings::load(&path);
+        let _ = fs::remove_file(&path);
+        let keys = keys.unwrap();
+
+        assert_eq!(keys.input("k", Colums::Cmd1), Some(KeyInput::Action(Action::PlayStop)));
+        assert_eq!(keys.input("Space", Colums::Note), Some(KeyInput::Action(Action::Edit)));
+        assert_eq!(keys.input("z", Colums::Note), Some(KeyInput::Action(Action::Note(0))));
+    }
+
+    #[test]
+    fn cursor_movement() {
+        let start = Cursor::default();
+
+        assert_eq!(start.moved(Action::Up, 128).row, 127);
+        assert_eq!(start.moved(Action::Bottom, 128).moved(Action::Down, 128).row, 0);
+        assert_eq!(start.moved(Action::PageDown, 128).row, PAGE);
+        assert_eq!(start.moved(Action::PageUp, 128).row, 0);
+        assert_eq!(start.moved(Action::SelectUp, 128).row, 0);
+        assert_eq!(start.moved(Action::Left, 128).col, Colums::Note);
+        assert_eq!(
+            start
+                .moved(Action::Right, 128)
+                .moved(Action::Right, 128)
+                .moved(Action::Right, 128)
+                .moved(Action::Right, 128)
+                .col,
+            Colums::Cmd2
+        );
+    }
+
+    #[test]
+    fn entry() {
+        assert_eq!(note_in_octave(DEFAULT_OCTAVE, 0), Some(60));
+        assert_eq!(note_in_octave(9, 19), Some(127));
+        assert_eq!(note_in_octave(9, 20), None);
+
+        // the octave typed in is the one shown
+        for octave in 0..=MAX_OCTAVE {
+            let note = note_in_octave(octave, 0).unwrap();
+            assert_eq!(crate::display_midi_note(note), format!("C-{octave:X}"));
+        }
+
+        assert_eq!(enter_hex(85, 6), 0x56);
+        assert_eq!(enter_hex(enter_hex(85, 6), 4), 0x64);
+        assert_eq!(enter_hex(0x7F, 0xF), 127);
+
+        let mut step = Step {
+            note: Some(60),
+            velocity: Some(10),
+            cmds: (TrackerCmd::Panic, TrackerCmd::Panic),
+        };
+        clear(&mut step, Colums::Cmd2);
+        assert_eq!(step.cmds, (TrackerCmd::Panic, TrackerCmd::None));
+        clear(&mut step, Colums::Note);
+        assert_eq!((step.note, step.velocity), (None, None));
+    }
+}
@@ -1,8 +1,28 @@
 // TODO: Make a macro to build a "LessThan" type for any given numeric type
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+use serde::{Deserialize, Serialize};
+
+/// (de)serializes as a plain number, and refuses to load values that are out of range.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "usize", into = "usize")]
 pub struct UsizeLessThan<const LT: usize>(pub usize);
 
+impl<const LT: usize> UsizeLessThan<LT> {
+    /// the biggest value that fits.
+    pub const MAX: usize = LT - 1;
+
+    /// `value`, clamped to `MAX` if it's too big.
+    pub fn saturating(value: usize) -> Self {
+        Self(value.min(Self::MAX))
+    }
+}
+
+impl<const LT: usize> From<UsizeLessThan<LT>> for usize {
+    fn from(value: UsizeLessThan<LT>) -> Self {
+        value.0
+    }
+}
+
 impl<const LT: usize> TryFrom<usize> for UsizeLessThan<LT> {
     type Error = String;
 
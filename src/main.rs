@@ -1,11 +1,30 @@
-use crate::tracks::{Track, TrackerCmd};
-use android_usbser::usb;
+use crate::{
+    block::{Block, Selection},
+    history::History,
+    keys::{Action, Cursor, DEFAULT_OCTAVE, KEY_BINDINGS_FILE, KeyBindings, KeyInput, MAX_OCTAVE},
+    less_then::UsizeLessThan,
+    playback::{
+        DEFAULT_BPM, DEFAULT_LPB, DEFAULT_VELOCITY, Playhead, row_at_lpb,
+        clock::{ClockMode, ClockMsg},
+        groove::{Groove, MAX_SWING},
+        tempo::{MAX_BPM, MIN_BPM, TapTempo},
+    },
+    project::Project,
+    record::{MAX_EDIT_STEP, MAX_QUANTIZE, NotePlayed, RecordMode, Recorder},
+    render::{Bounce, WavFormat},
+    tracks::{
+        ENVELOPE_MAX, Pattern, Sf2Cmd, Song, Step, Track, TrackerCmd, next_pattern_uuid,
+        next_section_uuid, pattern_index, section_index,
+    },
+};
 use crossbeam::channel::{Receiver, Sender, unbounded};
 use dioxus::prelude::*;
 use lazy_static::lazy_static;
 use midi_control::{ControlEvent, KeyEvent, MidiMessage};
 use std::{
     io::{self, BufRead, BufReader, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Mutex,
     sync::{
@@ -13,16 +32,32 @@ use std::{
         atomic::{AtomicBool, AtomicU32, Ordering},
     },
     thread::{JoinHandle, spawn},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 use stepper_synth_backend::{
     CHANNEL_SIZE, KnobCtrl, MidiControlled, SAMPLE_RATE, SampleGen,
     synth_engines::{Synth, SynthEngine, SynthModule},
 };
-use synth::{TabSynth, make_synth};
+use strum::IntoEnumIterator;
+use midi::{MidiDevices, transport::Transport};
+use synth::{
+    EngineKind, TabSynth, make_synth,
+    mixer::Mixer,
+    router,
+    sampler::{Pad, SamplerConf, SamplerMode},
+};
 use tracing::*;
 
+pub mod block;
+pub mod keys;
+pub mod history;
 pub mod less_then;
+pub mod midi;
+pub mod playback;
+pub mod project;
+pub mod record;
+pub mod render;
+pub mod smf;
 pub mod synth;
 pub mod tracks;
 
@@ -32,12 +67,19 @@ pub type SynthId = String;
 const FAVICON: Asset = asset!("/assets/favicon.ico");
 const MAIN_CSS: Asset = asset!("/assets/main.css");
 const HEADER_SVG: Asset = asset!("/assets/header.svg");
+/// how long a new section is.
 const N_STEPS: usize = 128;
+/// the longest a section can be.
+const MAX_STEPS: usize = 256;
 
 lazy_static! {
     pub static ref CBEAM_CHANNELS: (Sender<MidiMessage>, Receiver<MidiMessage>) = unbounded();
     pub static ref MIDI_SEND: Sender<MidiMessage> = CBEAM_CHANNELS.0.clone();
     pub static ref MIDI_RECV: Receiver<MidiMessage> = CBEAM_CHANNELS.1.clone();
+    /// clock from connected devices, kept apart from the midi they play.
+    pub static ref CLOCK_CHANNELS: (Sender<ClockMsg>, Receiver<ClockMsg>) = unbounded();
+    pub static ref CLOCK_SEND: Sender<ClockMsg> = CLOCK_CHANNELS.0.clone();
+    pub static ref CLOCK_RECV: Receiver<ClockMsg> = CLOCK_CHANNELS.1.clone();
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -60,66 +102,31 @@ fn main() {
 
     // needed bc audio output will fail if its started too soon.
     // let (synth, output_dev) = make_synth();
-    let synth = make_synth();
-
-    // let _jh = spawn(move || {
-    //         while let Ok(msg) = MIDI_RECV.recv() {
-    //             if let Ok(ref mut synth) = synth.synth.write() {
-    //                 match msg {
-    //                     MidiMessage::Invalid => {
-    //                         // error!("system received an invalid MIDI message.");
-    //                     }
-    //                     MidiMessage::NoteOn(_, KeyEvent { key, value }) => {
-    //                         synth.engine.play(key, value)
-    //                     }
-    //                     MidiMessage::NoteOff(_, KeyEvent { key, value: _ }) => {
-    //                         synth.engine.stop(key)
-    //                     }
-    //                     MidiMessage::PitchBend(_, lsb, msb) => {
-    //                         let bend =
-    //                             i16::from_le_bytes([lsb, msb]) as f32 / (32_000.0 * 0.5) - 1.0;
-
-    //                         if bend > 0.02 || bend < -0.020 {
-    //                             synth.engine.bend(bend);
-    //                         } else {
-    //                             synth.engine.unbend();
-    //                         }
-    //                     }
-    //                     MidiMessage::ControlChange(_, ControlEvent { control, value }) => {
-    //                         let value = value as f32 / 127.0;
-    //                         // let effects = self.target_effects;
-
-    //                         match synth.engine {
-    //                             SynthModule::WaveTable(ref mut wt) => {
-    //                                 wt.synth.midi_input(&msg);
-    //                             }
-    //                             ref mut engine => {
-    //                                 match control {
-    //                                     70 => engine.knob_1(value),
-    //                                     71 => engine.knob_2(value),
-    //                                     72 => engine.knob_3(value),
-    //                                     73 => engine.knob_4(value),
-    //                                     74 => engine.knob_5(value),
-    //                                     75 => engine.knob_6(value),
-    //                                     76 => engine.knob_7(value),
-    //                                     77 => engine.knob_8(value),
-    //                                     1 => engine.volume_swell(value),
-    //                                     _ => {
-    //                                         // info!("CC message => {control}-{value}");
-    //                                         false
-    //                                     }
-    //                                 };
-    //                             }
-    //                         }
-    //                     }
-    //                     _ => {}
-    //                 }
-    //             }
-    //         }
-    //     }
-    // );
-
-    dioxus::launch(App);
+    let (synth, _output_dev) = make_synth();
+
+    // plays the midi that comes in from `MIDI_SEND`.
+    let _router = router::spawn_router(MIDI_RECV.clone(), {
+        let synth = synth.clone();
+
+        move |msg| synth.midi_input(&msg)
+    });
+    // and follows the clock from `CLOCK_SEND`, on a thread of its own so notes can't hold up ticks.
+    let _clock_router = router::spawn_router(CLOCK_RECV.clone(), {
+        let synth = synth.clone();
+
+        move |msg| synth.clock_input(msg, Instant::now())
+    });
+
+    let key_bindings = KeyBindings::load(Path::new(KEY_BINDINGS_FILE)).unwrap_or_else(|e| {
+        info!("using the default key bindings ({e})");
+        KeyBindings::default()
+    });
+
+    dioxus::LaunchBuilder::new()
+        .with_context(synth)
+        .with_context(MidiDevices::default())
+        .with_context(key_bindings)
+        .launch(App);
 }
 
 #[component]
@@ -131,9 +138,52 @@ fn App() -> Element {
             Track::new(Some("Another-Section".into()), 1, "Default".into()),
         ]
     });
-    let displaying_uuid = use_signal(|| 0usize);
+    let patterns = use_signal(|| {
+        let mut pattern = Pattern::new(Some("PATTERN-1".into()), 0);
+        pattern.columns = vec![Some(0), Some(1)];
+
+        vec![pattern]
+    });
+    let song = use_signal(|| Song { chain: vec![0] });
+    let synth = use_context::<TabSynth>();
+    // mirror `TabSynth::list_synths` and `TabSynth::mixer`, so that everything showing them
+    // re-renders when they change.
+    let instruments = use_signal(|| synth.list_synths());
+    let mixer = use_signal(|| synth.mixer());
+    let bpm = use_signal(|| DEFAULT_BPM);
+    let lpb = use_signal(|| DEFAULT_LPB);
+    // the uuids of the section and the pattern the middle column shows, each kept while the other
+    // is on show
+    let displayed_section = use_signal(|| 0usize);
+    let displayed_pattern = use_signal(|| 0usize);
     // used to give context to the edit note/velcity/cmd-1/cmd-2
     let edit_cell = use_signal(|| None);
+    let history = use_signal(History::default);
+    // kept here so blocks can be pasted into other sections
+    let clipboard = use_signal(|| None);
+    // the grid cursor and selection live here so the transport can play from them
+    let cursor = use_signal(Cursor::default);
+    let selection = use_signal(|| None);
+    // written from the thread that listens to the audio thread, so it has to be sync
+    let mut playhead = use_signal_sync(|| None);
+
+    use_hook({
+        let updates = synth.playhead_updates();
+
+        move || {
+            spawn(move || {
+                for update in updates.iter() {
+                    playhead.set(update);
+                }
+            });
+        }
+    });
+
+    use_effect({
+        let synth = synth.clone();
+
+        move || synth.set_tempo(bpm(), lpb())
+    });
 
     rsx! {
         document::Link { rel: "icon", href: FAVICON }
@@ -142,19 +192,25 @@ fn App() -> Element {
         main {
             div {
                 id: "left-col",
-                LeftCol { middle_view, sections, displaying: displaying_uuid, edit_cell }
+                LeftCol { middle_view, sections, patterns, song, history, displayed_section, displayed_pattern, edit_cell }
+                InstrumentList { middle_view, sections, history, instruments, displayed_section }
+                MidiDeviceList { middle_view, sections, history, displayed_section }
             }
             div {
                 id: "middle-col",
-                MiddleCol { middle_view, sections, displaying: displaying_uuid, edit_cell }
+                MiddleCol { middle_view, sections, patterns, song, history, clipboard, cursor, selection, playhead, displayed_section, displayed_pattern, edit_cell }
 
                 if edit_cell.read().is_some() && middle_view() == MiddleColView::Section {
-                    EditSectionMenu { sections, displaying: displaying_uuid, edit_cell }
+                    EditSectionMenu { sections, history, displayed_section, edit_cell }
                 }
             }
             div {
                 id: "right-col",
+                Transport { middle_view, sections, patterns, song, cursor, selection, playhead, bpm, lpb, displayed_section, displayed_pattern }
+                RecordPanel { middle_view, sections, history, cursor, displayed_section }
                 PlayTone {  }
+                MixerPanel { instruments, mixer }
+                ProjectMenu { middle_view, sections, patterns, song, history, instruments, mixer, bpm, lpb, displayed_section, displayed_pattern, edit_cell }
             }
         }
     }
@@ -163,20 +219,30 @@ fn App() -> Element {
 #[component]
 fn EditSectionMenu(
     sections: Signal<Vec<Track>>,
-    displaying: Signal<usize>,
+    history: Signal<History>,
+    displayed_section: Signal<usize>,
     edit_cell: Signal<Option<(usize, Colums)>>,
 ) -> Element {
+    let index = move || section_index(&sections.read(), displayed_section());
     let note = use_signal(|| {
         if let Some((row, cell)) = edit_cell() {
-            sections.read()[displaying()].steps[row]
+            sections.read()[index()].steps[row]
                 .note
                 .unwrap_or(12u8)
         } else {
             0u8
         }
     });
-    let velocity = use_signal(|| 85u8);
-    let cmd = use_signal(|| TrackerCmd::None);
+    let velocity = use_signal(|| {
+        edit_cell()
+            .and_then(|(row, _cell)| sections.read()[index()].steps[row].velocity)
+            .unwrap_or(DEFAULT_VELOCITY)
+    });
+    let cmd = use_signal(|| match edit_cell() {
+        Some((row, Colums::Cmd1)) => sections.read()[index()].steps[row].cmds.0.clone(),
+        Some((row, Colums::Cmd2)) => sections.read()[index()].steps[row].cmds.1.clone(),
+        _ => TrackerCmd::None,
+    });
 
     rsx! {
         div {
@@ -202,25 +268,14 @@ fn EditSectionMenu(
                     onclick: move |_| {
                         if let Some((row, cell)) = edit_cell() {
                             info!("{row} => {cell:?}");
-
-                            match cell {
-                                Colums::Note => {
-                                    // set note
-                                    sections.write()[displaying()].steps[row].note = None;
-                                }
-                                Colums::Velocity => {
-                                    // set velocity
-                                    sections.write()[displaying()].steps[row].velocity = None;
-                                }
-                                Colums::Cmd1 => {
-                                    // set cmd
-                                    sections.write()[displaying()].steps[row].cmds.0 = TrackerCmd::None;
-                                }
-                                Colums::Cmd2 => {
-                                    // set cmd
-                                    sections.write()[displaying()].steps[row].cmds.1 = TrackerCmd::None;
-                                }
-                            }
+                            let section = index();
+
+                            history.write().edit_step(&mut sections.write(), section, row, |step| match cell {
+                                Colums::Note => step.note = None,
+                                Colums::Velocity => step.velocity = None,
+                                Colums::Cmd1 => step.cmds.0 = TrackerCmd::None,
+                                Colums::Cmd2 => step.cmds.1 = TrackerCmd::None,
+                            });
                         }
 
                         edit_cell.set(None);
@@ -235,32 +290,19 @@ fn EditSectionMenu(
                     onclick: move |_| {
                         if let Some((row, cell)) = edit_cell() {
                             info!("{row} => {cell:?}");
+                            let section = index();
 
-                            match cell {
+                            history.write().edit_step(&mut sections.write(), section, row, |step| match cell {
                                 Colums::Note => {
-                                    // set note
-                                    sections.write()[displaying()].steps[row].note = Some(note());
+                                    step.note = Some(note());
 
                                     // set velocity if not yet set
-                                    if sections()[displaying()].steps[row].velocity.is_none() {
-                                        sections.write()[displaying()].steps[row].velocity = Some(85);
-                                    }
-
-                                    info!("set note to {:?}", sections()[displaying()].steps[row].note);
-                                }
-                                Colums::Velocity => {
-                                    // set velocity
-                                    sections.write()[displaying()].steps[row].velocity = Some(velocity())
+                                    step.velocity.get_or_insert(DEFAULT_VELOCITY);
                                 }
-                                Colums::Cmd1 => {
-                                    // set cmd
-                                    sections.write()[displaying()].steps[row].cmds.0 = cmd();
-                                }
-                                Colums::Cmd2 => {
-                                    // set cmd
-                                    sections.write()[displaying()].steps[row].cmds.1 = cmd();
-                                }
-                            }
+                                Colums::Velocity => step.velocity = Some(velocity()),
+                                Colums::Cmd1 => step.cmds.0 = cmd(),
+                                Colums::Cmd2 => step.cmds.1 = cmd(),
+                            });
                         }
 
                         edit_cell.set(None);
@@ -274,7 +316,8 @@ fn EditSectionMenu(
             if let Some((row, cell)) = edit_cell() {
                 match cell {
                     Colums::Note => rsx! { EditNote { note } },
-                    _ => { rsx! { } }
+                    Colums::Velocity => rsx! { EditVelocity { velocity } },
+                    Colums::Cmd1 | Colums::Cmd2 => rsx! { EditCmd { cmd, len: sections.read()[index()].steps.len() } },
                 }
             }
         }
@@ -355,119 +398,171 @@ fn EditNote(note: Signal<u8>) -> Element {
 }
 
 #[component]
-fn MiddleCol(
-    middle_view: Signal<MiddleColView>,
-    sections: Signal<Vec<Track>>,
-    displaying: Signal<usize>,
-    edit_cell: Signal<Option<(usize, Colums)>>,
-) -> Element {
+fn EditVelocity(velocity: Signal<u8>) -> Element {
     rsx! {
-        div {
-            id: "middle-main",
-            if middle_view() == MiddleColView::Section {
-                SectionDisplay { middle_view, sections, displaying, edit_cell }
-            } else if middle_view() == MiddleColView::Pattern {}
+        NumberEditor {
+            label: "Velocity",
+            value: velocity() as usize,
+            max: 127,
+            onchange: move |value: usize| velocity.set(value as u8),
         }
     }
 }
 
+/// picks a command, then edits its args.
 #[component]
-fn SectionDisplay(
-    middle_view: Signal<MiddleColView>,
-    sections: Signal<Vec<Track>>,
-    displaying: Signal<usize>,
-    edit_cell: Signal<Option<(usize, Colums)>>,
-) -> Element {
+fn EditCmd(cmd: Signal<TrackerCmd>, len: usize) -> Element {
     rsx! {
         div {
-            id: "section-display-header",
-            div { "Line" }
-            div { "Note" }
-            div { "Vel" }
-            div { "Cmd1" }
-            div { "Cmd2" }
-        }
+            class: "row space-around",
 
-        div {
-            id: "section-scroll-list",
+            for kind in TrackerCmd::kinds() {
+                div {
+                    class: "button normal-text",
+                    id: if cmd().same_kind(&kind) { "displaying-sp" },
+                    onclick: {
+                        let kind = kind.clone();
+
+                        move |_| {
+                            if !cmd().same_kind(&kind) {
+                                cmd.set(kind.clone());
+                            }
+                        }
+                    },
 
-            div {
-                id: "section-scroll-div",
+                    "{kind}"
+                }
+            }
+        }
 
-                for (i, step) in sections()[displaying()].steps.iter().enumerate() {
-                    div {
-                        class: "section-scroll-item",
+        match cmd() {
+            TrackerCmd::None | TrackerCmd::Panic => rsx! {
+                div { class: "large super-center", "(no args)" }
+            },
+            TrackerCmd::Chord { chord } => rsx! {
+                div { class: "xx-large super-center", "Intervals" }
+                div {
+                    class: "row space-around",
 
+                    for (i, interval) in chord.iter().copied().enumerate() {
                         div {
-                            class: "section-row",
-                            id: {
-                                if i % 2 == 0 {
-                                    "row-light"
-                                } else {
-                                    "row-dark"
-                                }
-                            },
+                            class: "col",
 
-                            // Line Number
-                            div {
-                                class: "lin-number",
-                                // "{i:->2X}"
-                                "{i + 1:->3}"
-                            }
-                            // Note
                             div {
+                                class: "button large",
                                 onclick: move |_| {
-                                    // open edit menu with context
-                                    if edit_cell.read().is_none() {
-                                        edit_cell.set(Some((i, Colums::Note)));
+                                    if let TrackerCmd::Chord { chord } = &mut *cmd.write() {
+                                        chord[i] = interval.saturating_add(1);
                                     }
                                 },
-                                class: "button super-center",
-
-                                "{step.note.map(display_midi_note).unwrap_or(\"---\".into())}"
+                                "+"
                             }
-                            // Velocity
+                            div { class: "large", "{interval:+}" }
                             div {
+                                class: "button large",
                                 onclick: move |_| {
-                                    // open edit menu with context
-                                    if edit_cell.read().is_none() {
-                                        edit_cell.set(Some((i, Colums::Velocity)));
+                                    if let TrackerCmd::Chord { chord } = &mut *cmd.write() {
+                                        chord[i] = interval.saturating_sub(1);
                                     }
                                 },
-                                class: "button super-center",
-
-                                if sections()[displaying()].steps[i].note.is_some() {
-                                    // "{step.velocity.unwrap_or(85):->3X}"
-                                    "{step.velocity.unwrap_or(85):->3}"
-                                } else {
-                                    "---"
-                                }
+                                "-"
                             }
-                            // CMD 1
                             div {
+                                class: "button normal-text",
                                 onclick: move |_| {
-                                    // open edit menu with context
-                                    if edit_cell.read().is_none() {
-                                        edit_cell.set(Some((i, Colums::Cmd1)));
+                                    if let TrackerCmd::Chord { chord } = &mut *cmd.write() {
+                                        chord.remove(i);
                                     }
                                 },
-                                class: "button super-center",
-
-                                "{step.cmds.0}"
+                                "X"
                             }
-                            // CMD 2
-                            div {
-                                onclick: move |_| {
-                                    // open edit menu with context
-                                    if edit_cell.read().is_none() {
-                                        edit_cell.set(Some((i, Colums::Cmd2)));
-                                    }
-                                },
-                                class: "button super-center",
+                        }
+                    }
 
-                                "{step.cmds.1}"
+                    div {
+                        class: "button large",
+                        onclick: move |_| {
+                            if let TrackerCmd::Chord { chord } = &mut *cmd.write() {
+                                // stack another third on top
+                                let next = chord.last().map_or(4, |last| last.saturating_add(3));
+                                chord.push(next);
                             }
-                        }
+                        },
+                        "ADD"
+                    }
+                }
+            },
+            TrackerCmd::Roll { times } => rsx! {
+                NumberEditor {
+                    label: "Rolls",
+                    value: times.0,
+                    max: cmd().max_steps(len).unwrap_or_default(),
+                    onchange: move |value: usize| cmd.set(TrackerCmd::Roll { times: UsizeLessThan::saturating(value) }),
+                }
+            },
+            TrackerCmd::Repeat { times } => rsx! {
+                NumberEditor {
+                    label: "Repeats",
+                    value: times.0,
+                    max: cmd().max_steps(len).unwrap_or_default(),
+                    onchange: move |value: usize| cmd.set(TrackerCmd::Repeat { times: UsizeLessThan::saturating(value) }),
+                }
+            },
+            TrackerCmd::HoldFor { notes } => rsx! {
+                NumberEditor {
+                    label: "Hold Steps",
+                    value: notes.0,
+                    max: cmd().max_steps(len).unwrap_or_default(),
+                    onchange: move |value: usize| cmd.set(TrackerCmd::HoldFor { notes: UsizeLessThan::saturating(value) }),
+                }
+            },
+            TrackerCmd::Tempo { bpm } => rsx! {
+                NumberEditor {
+                    label: "BPM",
+                    value: bpm as usize,
+                    max: MAX_BPM,
+                    onchange: move |value: usize| cmd.set(TrackerCmd::Tempo { bpm: value.clamp(MIN_BPM, MAX_BPM) as u16 }),
+                }
+            },
+            TrackerCmd::MidiCmd { cc_param, arg } => rsx! {
+                NumberEditor {
+                    label: "CC",
+                    value: cc_param as usize,
+                    max: 127,
+                    onchange: move |value: usize| cmd.set(TrackerCmd::MidiCmd { cc_param: value as u8, arg }),
+                }
+                NumberEditor {
+                    label: "Value",
+                    value: arg as usize,
+                    max: 127,
+                    onchange: move |value: usize| cmd.set(TrackerCmd::MidiCmd { cc_param, arg: value as u8 }),
+                }
+            },
+            TrackerCmd::Custom(Sf2Cmd::Volume(volume)) => rsx! {
+                NumberEditor {
+                    label: "Volume %",
+                    value: (volume * 100.0).round() as usize,
+                    max: 100,
+                    onchange: move |value: usize| cmd.set(TrackerCmd::Custom(Sf2Cmd::Volume(value as f32 / 100.0))),
+                }
+            },
+            TrackerCmd::Custom(sf2_cmd) => {
+                let (label, value, set): (&str, usize, fn(usize) -> Sf2Cmd) = match sf2_cmd {
+                    Sf2Cmd::Atk(value) => ("Attack", value, Sf2Cmd::Atk),
+                    Sf2Cmd::Dcy(value) => ("Decay", value, Sf2Cmd::Dcy),
+                    Sf2Cmd::Dcy2(value) => ("Decay 2", value, Sf2Cmd::Dcy2),
+                    Sf2Cmd::Sus(value) => ("Sustain", value, Sf2Cmd::Sus),
+                    Sf2Cmd::Rel(value) => ("Release", value, Sf2Cmd::Rel),
+                    Sf2Cmd::Offset(value) => ("Offset", value, Sf2Cmd::Offset),
+                    Sf2Cmd::Volume(_) => unreachable!("volume has its own editor"),
+                };
+
+                rsx! {
+                    NumberEditor {
+                        label,
+                        value,
+                        max: ENVELOPE_MAX,
+                        onchange: move |value: usize| cmd.set(TrackerCmd::Custom(set(value.min(ENVELOPE_MAX)))),
                     }
                 }
             }
@@ -475,101 +570,1815 @@ fn SectionDisplay(
     }
 }
 
+/// a small labeled number box, for settings that sit above the grid.
 #[component]
-fn LeftCol(
+fn NumberField(label: String, value: usize, min: usize, max: usize, onchange: EventHandler<usize>) -> Element {
+    rsx! {
+        div {
+            class: "row number-field normal-text",
+
+            div { "{label}" }
+            input {
+                r#type: "number",
+                min: "{min}",
+                max: "{max}",
+                value: "{value}",
+                onchange: move |event: FormEvent| {
+                    if let Ok(value) = event.value().parse::<usize>() {
+                        onchange.call(value.clamp(min, max));
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// a labeled slider, with buttons to nudge it by one.
+#[component]
+fn NumberEditor(label: String, value: usize, max: usize, onchange: EventHandler<usize>) -> Element {
+    rsx! {
+        div {
+            class: "xx-large super-center",
+
+            "{label}"
+        }
+        div {
+            class: "row space-around",
+
+            div {
+                class: "button large",
+                onclick: move |_| onchange.call(value.saturating_sub(1)),
+                "<-"
+            }
+            input {
+                class: "number-slider",
+                r#type: "range",
+                min: "0",
+                max: "{max}",
+                value: "{value}",
+                oninput: move |event: FormEvent| {
+                    if let Ok(value) = event.value().parse::<usize>() {
+                        onchange.call(value.min(max));
+                    }
+                },
+            }
+            div {
+                class: "large",
+                "{value}"
+            }
+            div {
+                class: "button large",
+                onclick: move |_| onchange.call((value + 1).min(max)),
+                "->"
+            }
+        }
+    }
+}
+
+#[component]
+fn MiddleCol(
     middle_view: Signal<MiddleColView>,
     sections: Signal<Vec<Track>>,
-    displaying: Signal<usize>,
+    patterns: Signal<Vec<Pattern>>,
+    song: Signal<Song>,
+    history: Signal<History>,
+    clipboard: Signal<Option<Block>>,
+    cursor: Signal<Cursor>,
+    selection: Signal<Option<Selection>>,
+    playhead: SyncSignal<Option<Playhead>>,
+    displayed_section: Signal<usize>,
+    displayed_pattern: Signal<usize>,
     edit_cell: Signal<Option<(usize, Colums)>>,
 ) -> Element {
-    let mut listing = use_signal(|| MiddleColView::Section);
-    let view_sections = || listing() == MiddleColView::Section;
+    rsx! {
+        div {
+            id: "middle-main",
+            if middle_view() == MiddleColView::Section {
+                SectionDisplay { middle_view, sections, history, clipboard, cursor, selection, playhead, displayed_section, edit_cell }
+            } else if middle_view() == MiddleColView::Pattern {
+                PatternDisplay { sections, patterns, song, displayed_pattern }
+            }
+        }
+    }
+}
+
+#[component]
+fn SectionDisplay(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    history: Signal<History>,
+    clipboard: Signal<Option<Block>>,
+    cursor: Signal<Cursor>,
+    selection: Signal<Option<Selection>>,
+    playhead: SyncSignal<Option<Playhead>>,
+    displayed_section: Signal<usize>,
+    edit_cell: Signal<Option<(usize, Colums)>>,
+) -> Element {
+    let synth = use_context::<TabSynth>();
+    let key_bindings = use_context::<KeyBindings>();
+    let mut octave = use_signal(|| DEFAULT_OCTAVE);
+    let index = move || section_index(&sections.read(), displayed_section());
+    // the row of this section that's sounding, if it's playing. worked out once a render, since
+    // asking which sections are playing locks the sequencer
+    let playing_row = playhead()
+        .filter(|playhead| synth.block_sections(playhead.block).contains(&displayed_section()))
+        .map(|playhead| {
+            let section = &sections.read()[index()];
+            // the block can step at other lines per beat than this section
+            let (row, _) = row_at_lpb(playhead.row, 0.0, synth.block_lpb(playhead.block), section.lpb);
+
+            row % section.steps.len()
+        });
+
+    // keep the playhead in view
+    use_effect(move || {
+        if playhead().is_some() {
+            document::eval(
+                "document.querySelector('#section-scroll-list .playhead')?.scrollIntoView({ block: 'center' });",
+            );
+        }
+    });
+    // block operations work on the selection, or on the cell under the cursor without one
+    let target = move || selection().unwrap_or(Selection::at(cursor()));
+    let cell_class = move |row: usize, col: Colums| {
+        let mut class = String::from("button super-center");
+
+        if cursor() == (Cursor { row, col }) {
+            class.push_str(" cursor");
+        }
+
+        if selection().is_some_and(|selected| selected.contains(row, col)) {
+            class.push_str(" selected");
+        }
+
+        class
+    };
+    // run `change` on the selected rows, as one undo step
+    let mut transform = move |change: fn(&mut [Step], &[Colums])| {
+        let target = target();
+        let rows = target.rows();
+        let section = index();
+
+        history.write().edit_steps(&mut sections.write(), section, |steps| {
+            let end = (*rows.end() + 1).min(steps.len());
+
+            if let Some(steps) = steps.get_mut(*rows.start()..end) {
+                change(steps, &target.cols());
+            }
+        });
+    };
+    let mut copy = move || clipboard.set(Some(block::copy(&sections.read()[index()].steps, &target())));
+    let mut cut = move || {
+        let mut cut = None;
+        let section = index();
+        history
+            .write()
+            .edit_steps(&mut sections.write(), section, |steps| cut = Some(block::cut(steps, &target())));
+        clipboard.set(cut);
+    };
+    // pastes with the top left corner of the block at the top left of the selection
+    let mut paste = move || {
+        let Some(pasted) = clipboard() else {
+            return;
+        };
+        let target = target();
+        let at = Cursor {
+            row: *target.rows().start(),
+            col: target.cols()[0],
+        };
+        let section = index();
+
+        history
+            .write()
+            .edit_steps(&mut sections.write(), section, |steps| block::paste(steps, &pasted, at));
+    };
+    let mut click = move |event: MouseEvent, row: usize, col: Colums| {
+        let clicked = Cursor { row, col };
+
+        // shift-click grows a selection instead of editing
+        if event.modifiers().shift() {
+            let anchor = selection().map(|selected| selected.anchor).unwrap_or(cursor());
+            selection.set(Some(Selection { anchor, head: clicked }));
+            cursor.set(clicked);
+
+            return;
+        }
+
+        selection.set(None);
+        cursor.set(clicked);
+
+        // open edit menu with context
+        if edit_cell.read().is_none() {
+            edit_cell.set(Some((row, col)));
+        }
+    };
+    let transforms: [(&str, fn(&mut [Step], &[Colums])); 14] = [
+        ("-12", |steps, cols| block::transpose(steps, cols, -12)),
+        ("-1", |steps, cols| block::transpose(steps, cols, -1)),
+        ("+1", |steps, cols| block::transpose(steps, cols, 1)),
+        ("+12", |steps, cols| block::transpose(steps, cols, 12)),
+        ("VEL-", |steps, cols| block::scale_velocity(steps, cols, 0.8)),
+        ("VEL+", |steps, cols| block::scale_velocity(steps, cols, 1.25)),
+        ("REV", |steps, cols| block::reverse(steps, cols)),
+        ("SHIFT UP", |steps, cols| block::shift(steps, cols, -1)),
+        ("SHIFT DN", |steps, cols| block::shift(steps, cols, 1)),
+        ("ROT UP", |steps, cols| block::rotate(steps, cols, -1)),
+        ("ROT DN", |steps, cols| block::rotate(steps, cols, 1)),
+        ("LERP VEL", |steps, cols| block::interpolate_velocity(steps, cols)),
+        ("LERP CC", |steps, cols| cols.iter().for_each(|col| block::interpolate_cc(steps, *col))),
+        ("FILL 4", |steps, cols| block::fill_every(steps, cols, 4)),
+    ];
+
+    let onkeydown = move |event: KeyboardEvent| {
+        // the edit menu has the keyboard while it's open
+        if edit_cell.read().is_some() {
+            return;
+        }
+
+        let key = keys::key_name(&event.key(), event.modifiers());
+        let Cursor { row, col } = cursor();
+        let Some(input) = key_bindings.input(&key, col) else {
+            return;
+        };
+        let section = index();
+        let n_rows = sections.read()[section].steps.len();
+        event.prevent_default();
+
+        match input {
+            KeyInput::Hex(digit) => history.write().edit_step(&mut sections.write(), section, row, |step| {
+                if step.note.is_some() {
+                    step.velocity = Some(keys::enter_hex(step.velocity.unwrap_or(DEFAULT_VELOCITY), digit));
+                }
+            }),
+            KeyInput::Action(Action::Note(semitone)) => {
+                if let Some(note) = keys::note_in_octave(octave(), semitone) {
+                    history.write().edit_step(&mut sections.write(), section, row, |step| step.note = Some(note));
+                    cursor.set(cursor().moved(Action::Down, n_rows));
+                }
+            }
+            KeyInput::Action(Action::Delete) if selection().is_some() => {
+                transform(|steps, cols| {
+                    steps.iter_mut().for_each(|step| cols.iter().for_each(|col| keys::clear(step, *col)))
+                });
+            }
+            KeyInput::Action(Action::Delete) => {
+                history.write().edit_step(&mut sections.write(), section, row, |step| keys::clear(step, col))
+            }
+            KeyInput::Action(Action::Copy) => copy(),
+            KeyInput::Action(Action::Cut) => cut(),
+            KeyInput::Action(Action::Paste) => paste(),
+            KeyInput::Action(Action::TransposeUp) => transform(|steps, cols| block::transpose(steps, cols, 1)),
+            KeyInput::Action(Action::TransposeDown) => transform(|steps, cols| block::transpose(steps, cols, -1)),
+            KeyInput::Action(
+                select @ (Action::SelectUp | Action::SelectDown | Action::SelectLeft | Action::SelectRight),
+            ) => {
+                let anchor = selection().map(|selected| selected.anchor).unwrap_or(cursor());
+                let head = cursor().moved(select, n_rows);
+                selection.set(Some(Selection { anchor, head }));
+                cursor.set(head);
+            }
+            KeyInput::Action(Action::Undo) => {
+                history.write().undo(&mut sections.write());
+            }
+            KeyInput::Action(Action::Redo) => {
+                history.write().redo(&mut sections.write());
+            }
+            KeyInput::Action(Action::Edit) => edit_cell.set(Some((row, col))),
+            KeyInput::Action(Action::OctaveUp) => octave.set((octave() + 1).min(MAX_OCTAVE)),
+            KeyInput::Action(Action::OctaveDown) => octave.set(octave().saturating_sub(1)),
+            KeyInput::Action(Action::PlayStop) => {
+                if synth.is_playing() {
+                    synth.stop();
+                } else {
+                    synth.set_loop_range(None);
+                    synth.play(vec![sections()[section].clone()]);
+                }
+            }
+            KeyInput::Action(Action::NextSection) => {
+                let next = sections.read()[(section + 1) % sections.read().len()].uuid;
+                displayed_section.set(next);
+            }
+            KeyInput::Action(Action::PrevSection) => {
+                let n = sections.read().len();
+                let prev = sections.read()[(section + n - 1) % n].uuid;
+                displayed_section.set(prev);
+            }
+            KeyInput::Action(movement) => {
+                selection.set(None);
+                cursor.set(cursor().moved(movement, n_rows));
+            }
+        }
+    };
+    // the rows to draw, copied once a render rather than once a row
+    let track = sections.read()[index()].clone();
 
     rsx! {
         div {
-            id: "section-pattern-select",
+            id: "section-display",
+            tabindex: "0",
+            onkeydown,
 
             div {
-                class: "button col normal-text",
-                onclick: move |_| listing.set(MiddleColView::Section),
+                id: "history-buttons",
+                class: "row",
 
-                div { "Section" }
                 div {
-                    class: {
-                        let mut classes = vec!["led"];
-                        if view_sections() { classes.push("led-on") }
-                        classes.join(" ")
+                    class: if history.read().can_undo() { "button normal-text" } else { "button normal-text disabled" },
+                    onclick: move |_| {
+                        history.write().undo(&mut sections.write());
+                    },
+
+                    "UNDO"
+                }
+                div {
+                    class: if history.read().can_redo() { "button normal-text" } else { "button normal-text disabled" },
+                    onclick: move |_| {
+                        history.write().redo(&mut sections.write());
                     },
+
+                    "REDO"
                 }
             }
+
             div {
-                class: "button col normal-text",
-                onclick: move |_| listing.set(MiddleColView::Pattern),
+                id: "block-buttons",
+                class: "row",
 
-                div { "Pattern" }
                 div {
-                    class: {
-                        let mut classes = vec!["led"];
+                    class: "button normal-text",
+                    onclick: move |_| copy(),
 
-                        if !view_sections() { classes.push("led-on") }
+                    "COPY"
+                }
+                div {
+                    class: "button normal-text",
+                    onclick: move |_| cut(),
 
-                        classes.join(" ")
-                    },
+                    "CUT"
+                }
+                div {
+                    class: if clipboard.read().is_some() { "button normal-text" } else { "button normal-text disabled" },
+                    onclick: move |_| paste(),
+
+                    "PASTE"
+                }
+
+                for (label, change) in transforms {
+                    div {
+                        class: "button normal-text",
+                        onclick: move |_| transform(change),
+
+                        "{label}"
+                    }
                 }
             }
-        }
 
-        div {
-            id: "nav-list",
+            div {
+                id: "section-settings",
+                class: "row",
+
+                NumberField {
+                    label: "LEN",
+                    value: sections.read()[index()].steps.len(),
+                    min: 1,
+                    max: MAX_STEPS,
+                    onchange: move |len: usize| {
+                        let section = index();
+                        history.write().edit_section(&mut sections.write(), section, |track| track.set_len(len));
+
+                        // don't leave anything pointing past the new end
+                        if cursor().row >= len {
+                            cursor.set(Cursor { row: len - 1, ..cursor() });
+                        }
 
-            for (i, (name, uuid)) in match listing() {
-                MiddleColView::Section => {
-                    sections().iter().map(|section| (section.name.clone(), section.uuid)).enumerate().collect::<Vec<_>>()
+                        selection.set(None);
+                        edit_cell.set(None);
+                    },
                 }
-                MiddleColView::Pattern => {
-                    [].iter().map(|pattern: &(String, usize)| pattern.to_owned()).enumerate().collect::<Vec<_>>()
+                NumberField {
+                    label: "LPB",
+                    value: sections.read()[index()].lpb,
+                    min: 1,
+                    max: MAX_STEPS,
+                    onchange: move |lpb: usize| {
+                        let section = index();
+                        history.write().edit_section(&mut sections.write(), section, |track| track.lpb = lpb);
+                    },
                 }
-            } {
-                // TODO: add edit-name button here
-                div {
-                    id: {
-                        if (listing() == middle_view()) && (uuid == displaying()) {
-                            "displaying-sp".to_string()
-                        } else {
-                            "".into()
-                        }
+                NumberField {
+                    label: "BEATS",
+                    value: sections.read()[index()].beats,
+                    min: 1,
+                    max: MAX_STEPS,
+                    onchange: move |beats: usize| {
+                        let section = index();
+                        history.write().edit_section(&mut sections.write(), section, |track| track.beats = beats);
                     },
-                    class: "button nav-item",
+                }
+                NumberField {
+                    label: "SWING",
+                    value: sections.read()[index()].swing as usize,
+                    min: 0,
+                    max: MAX_SWING as usize,
+                    onchange: move |swing: usize| {
+                        let section = index();
+                        history.write().edit_section(&mut sections.write(), section, |track| track.swing = swing as u8);
+                    },
+                }
+                div {
+                    class: "button normal-text",
                     onclick: move |_| {
-                        middle_view.set(listing());
-                        displaying.set(uuid);
-                        edit_cell.set(None);
+                        let section = index();
+                        let groove = sections.read()[section].groove;
+                        let next = Groove::iter().cycle().skip_while(|g| *g != groove).nth(1).unwrap_or_default();
+                        history.write().edit_section(&mut sections.write(), section, |track| track.groove = next);
                     },
-                    "{name}"
+                    "GROOVE {sections.read()[index()].groove}"
                 }
-                // TODO: add deleat track button here
             }
-        }
-    }
-}
 
-pub fn display_midi_note(midi_note: u8) -> String {
-    let note_name_i = midi_note % 12;
-    let octave = midi_note / 12;
+            div {
+                id: "section-display-header",
+                div { "Line" }
+                div { "Note {octave}" }
+                div { "Vel" }
+                div { "Cmd1" }
+                div { "Cmd2" }
+            }
 
-    let note_names = [
-        "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
-    ];
-    let note_name = note_names[note_name_i as usize];
+            div {
+                id: "section-scroll-list",
 
-    format!("{note_name}{octave:X}")
-}
+                div {
+                    id: "section-scroll-div",
 
-#[component]
-fn PlayTone() -> Element {
-    let mut playing = false;
+                    for (i, step) in track.steps.iter().enumerate() {
+                        div {
+                            class: "section-scroll-item",
 
-    rsx! {
+                            div {
+                                class: if playing_row == Some(i) { "section-row playhead" } else { "section-row" },
+                                id: {
+                                    if track.is_bar(i) {
+                                        "row-bar"
+                                    } else if track.is_beat(i) {
+                                        "row-beat"
+                                    } else {
+                                        "row-dark"
+                                    }
+                                },
+
+                                // Line Number
+                                div {
+                                    class: "lin-number",
+                                    // "{i:->2X}"
+                                    "{i + 1:->3}"
+                                }
+                                // Note
+                                div {
+                                    onclick: move |event| click(event, i, Colums::Note),
+                                    class: cell_class(i, Colums::Note),
+
+                                    "{step.note.map(display_midi_note).unwrap_or(\"---\".into())}"
+                                }
+                                // Velocity
+                                div {
+                                    onclick: move |event| click(event, i, Colums::Velocity),
+                                    class: cell_class(i, Colums::Velocity),
+
+                                    if step.note.is_some() {
+                                        // hex, to match what gets typed in
+                                        "{step.velocity.unwrap_or(DEFAULT_VELOCITY):02X}"
+                                    } else {
+                                        "--"
+                                    }
+                                }
+                                // CMD 1
+                                div {
+                                    onclick: move |event| click(event, i, Colums::Cmd1),
+                                    class: cell_class(i, Colums::Cmd1),
+
+                                    "{step.cmds.0}"
+                                }
+                                // CMD 2
+                                div {
+                                    onclick: move |event| click(event, i, Colums::Cmd2),
+                                    class: cell_class(i, Colums::Cmd2),
+
+                                    "{step.cmds.1}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn PatternDisplay(
+    sections: Signal<Vec<Track>>,
+    patterns: Signal<Vec<Pattern>>,
+    song: Signal<Song>,
+    displayed_pattern: Signal<usize>,
+) -> Element {
+    let index = move || pattern_index(&patterns.read(), displayed_pattern());
+
+    if patterns.read().is_empty() {
+        return rsx! {
+            div { class: "normal-text", "no patterns yet, make one with + on the left" }
+        };
+    }
+
+    let columns = patterns.read()[index()].columns.clone();
+    // the section in each column, looked up once a render rather than once a cell
+    let column_sections: Vec<Option<Track>> = {
+        let sections = sections.read();
+
+        columns
+            .iter()
+            .map(|uuid| uuid.and_then(|uuid| sections.iter().find(|section| section.uuid == uuid)).cloned())
+            .collect()
+    };
+    // as long as the longest section, the same as when it plays
+    let n_rows = column_sections
+        .iter()
+        .flatten()
+        .map(|track| track.steps.len())
+        .max()
+        .unwrap_or(N_STEPS);
+    let pattern_name = move |uuid: usize| {
+        patterns()
+            .iter()
+            .find(|pattern| pattern.uuid == uuid)
+            .map(|pattern| pattern.name.clone())
+            .unwrap_or("----".into())
+    };
+
+    rsx! {
+        div {
+            id: "song-chain",
+            class: "row",
+
+            div { class: "normal-text", "Song:" }
+
+            for (i, uuid) in song().chain.into_iter().enumerate() {
+                div {
+                    class: "button chain-item normal-text",
+                    // remove from the song on click
+                    onclick: move |_| {
+                        song.write().chain.remove(i);
+                    },
+
+                    "{pattern_name(uuid)}"
+                }
+            }
+
+            div {
+                class: "button chain-item normal-text",
+                // the pattern on show, as long as it's still there
+                onclick: move |_| {
+                    if patterns.read().iter().any(|pattern| pattern.uuid == displayed_pattern()) {
+                        song.write().chain.push(displayed_pattern());
+                    }
+                },
+
+                "+"
+            }
+        }
+
+        div {
+            id: "pattern-display-header",
+            div { "Line" }
+
+            for (col, uuid) in columns.iter().copied().enumerate() {
+                div {
+                    class: "button",
+                    // cycle through the sections, with a silent column after the last one
+                    onclick: move |_| {
+                        let uuids: Vec<usize> = sections().iter().map(|section| section.uuid).collect();
+                        let next = match uuid.and_then(|uuid| uuids.iter().position(|other| *other == uuid)) {
+                            Some(i) => uuids.get(i + 1).copied(),
+                            None => uuids.first().copied(),
+                        };
+
+                        let pattern = index();
+                        patterns.write()[pattern].columns[col] = next;
+                    },
+
+                    "{column_sections[col].as_ref().map(|section| section.name.clone()).unwrap_or(\"----\".into())}"
+                }
+            }
+
+            div {
+                class: "button",
+                onclick: move |_| {
+                    let pattern = index();
+                    patterns.write()[pattern].columns.push(None);
+                },
+
+                "+"
+            }
+            div {
+                class: "button",
+                onclick: move |_| {
+                    let pattern = index();
+
+                    if patterns()[pattern].columns.len() > 1 {
+                        patterns.write()[pattern].columns.pop();
+                    }
+                },
+
+                "-"
+            }
+        }
+
+        div {
+            id: "section-scroll-list",
+
+            div {
+                id: "section-scroll-div",
+
+                for i in 0..n_rows {
+                    div {
+                        class: "section-scroll-item",
+
+                        div {
+                            class: "section-row pattern-row",
+                            id: {
+                                if i % 2 == 0 {
+                                    "row-light"
+                                } else {
+                                    "row-dark"
+                                }
+                            },
+
+                            div {
+                                class: "lin-number",
+                                "{i + 1:->3}"
+                            }
+
+                            for section in column_sections.iter() {
+                                div {
+                                    class: "super-center",
+
+                                    "{section.as_ref().and_then(|section| section.steps.get(i % section.steps.len()).and_then(|step| step.note)).map(display_midi_note).unwrap_or(\"---\".into())}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn LeftCol(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    patterns: Signal<Vec<Pattern>>,
+    song: Signal<Song>,
+    history: Signal<History>,
+    displayed_section: Signal<usize>,
+    displayed_pattern: Signal<usize>,
+    edit_cell: Signal<Option<(usize, Colums)>>,
+) -> Element {
+    let mut listing = use_signal(|| MiddleColView::Section);
+    let view_sections = move || listing() == MiddleColView::Section;
+    // the uuid of what the middle column shows, or last showed, of the kind being listed.
+    let displayed = move || if view_sections() { displayed_section() } else { displayed_pattern() };
+    // the uuid of the section whose name is being edited.
+    let mut renaming = use_signal(|| None::<usize>);
+    // the uuid of the section waiting for its delete to be confirmed.
+    let mut deleting = use_signal(|| None::<usize>);
+    // the same for patterns, whose uuids can be the same as a section's.
+    let mut deleting_pattern = use_signal(|| None::<usize>);
+    let mut show = move |uuid: usize| {
+        middle_view.set(MiddleColView::Section);
+        displayed_section.set(uuid);
+        edit_cell.set(None);
+    };
+    let mut show_pattern = move |uuid: usize| {
+        middle_view.set(MiddleColView::Pattern);
+        displayed_pattern.set(uuid);
+        edit_cell.set(None);
+    };
+
+    rsx! {
+        div {
+            id: "section-pattern-select",
+
+            div {
+                class: "button col normal-text",
+                onclick: move |_| listing.set(MiddleColView::Section),
+
+                div { "Section" }
+                div {
+                    class: {
+                        let mut classes = vec!["led"];
+                        if view_sections() { classes.push("led-on") }
+                        classes.join(" ")
+                    },
+                }
+            }
+            div {
+                class: "button col normal-text",
+                onclick: move |_| listing.set(MiddleColView::Pattern),
+
+                div { "Pattern" }
+                div {
+                    class: {
+                        let mut classes = vec!["led"];
+
+                        if !view_sections() { classes.push("led-on") }
+
+                        classes.join(" ")
+                    },
+                }
+            }
+        }
+
+        if view_sections() {
+            div {
+                id: "section-actions",
+                class: "row",
+
+                div {
+                    class: "button nav-item",
+                    // a new section after the one being shown, on the same instrument
+                    onclick: move |_| {
+                        let index = section_index(&sections.read(), displayed_section());
+                        let uuid = next_section_uuid(&sections.read(), &patterns.read());
+                        let dev = sections.read().get(index).map(|section| section.dev.clone()).unwrap_or("Default".into());
+                        let at = (index + 1).min(sections.read().len());
+
+                        history.write().insert(&mut sections.write(), at, Track::new(None, uuid, dev));
+                        show(uuid);
+                    },
+
+                    "+"
+                }
+            }
+        } else {
+            div {
+                id: "section-actions",
+                class: "row",
+
+                div {
+                    class: "button nav-item",
+                    // an empty pattern after the one being shown
+                    onclick: move |_| {
+                        let shown = patterns.read().iter().position(|pattern| pattern.uuid == displayed_pattern());
+                        let uuid = next_pattern_uuid(&patterns.read(), &song.read());
+                        let at = shown.map_or(patterns.read().len(), |index| index + 1);
+
+                        patterns.write().insert(at, Pattern::new(None, uuid));
+                        show_pattern(uuid);
+                    },
+
+                    "+"
+                }
+            }
+        }
+
+        div {
+            id: "nav-list",
+
+            for (i, (name, uuid)) in match listing() {
+                MiddleColView::Section => {
+                    sections().iter().map(|section| (section.name.clone(), section.uuid)).enumerate().collect::<Vec<_>>()
+                }
+                MiddleColView::Pattern => {
+                    patterns().iter().map(|pattern| (pattern.name.clone(), pattern.uuid)).enumerate().collect::<Vec<_>>()
+                }
+            } {
+                div {
+                    class: "row nav-row",
+
+                    if view_sections() && renaming().is_some_and(|renaming| renaming == uuid) {
+                        input {
+                            class: "nav-item",
+                            value: "{name}",
+                            onchange: move |event: FormEvent| {
+                                history.write().rename(&mut sections.write(), i, event.value());
+                                renaming.set(None);
+                            },
+                        }
+                    } else {
+                        div {
+                            id: {
+                                if (listing() == middle_view()) && (uuid == displayed()) {
+                                    "displaying-sp".to_string()
+                                } else {
+                                    "".into()
+                                }
+                            },
+                            class: "button nav-item",
+                            onclick: move |_| {
+                                if view_sections() {
+                                    show(uuid);
+                                } else {
+                                    show_pattern(uuid);
+                                }
+                            },
+                            "{name}"
+                        }
+                    }
+
+                    if view_sections() {
+                        if deleting().is_some_and(|deleting| deleting == uuid) {
+                            div {
+                                class: "button nav-item",
+                                onclick: move |_| {
+                                    history.write().remove(&mut sections.write(), i);
+                                    deleting.set(None);
+
+                                    // show whatever took its place
+                                    if uuid == displayed_section() {
+                                        let next = sections.read()[i.min(sections.read().len() - 1)].uuid;
+                                        show(next);
+                                    }
+                                },
+
+                                "DELETE"
+                            }
+                            div {
+                                class: "button nav-item",
+                                onclick: move |_| deleting.set(None),
+
+                                "KEEP"
+                            }
+                        } else {
+                            div {
+                                class: "button nav-item",
+                                onclick: move |_| renaming.set(Some(uuid)),
+
+                                "R"
+                            }
+                            div {
+                                class: "button nav-item",
+                                // a copy right after the original
+                                onclick: move |_| {
+                                    let mut copy = sections.read()[i].clone();
+                                    copy.uuid = next_section_uuid(&sections.read(), &patterns.read());
+                                    copy.name = format!("{}-COPY", copy.name);
+                                    let uuid = copy.uuid;
+
+                                    history.write().insert(&mut sections.write(), i + 1, copy);
+                                    show(uuid);
+                                },
+
+                                "D"
+                            }
+                            div {
+                                class: "button nav-item",
+                                onclick: move |_| {
+                                    history.write().move_section(&mut sections.write(), i, i.saturating_sub(1));
+                                },
+
+                                "^"
+                            }
+                            div {
+                                class: "button nav-item",
+                                onclick: move |_| {
+                                    let last = sections.read().len() - 1;
+                                    history.write().move_section(&mut sections.write(), i, (i + 1).min(last));
+                                },
+
+                                "v"
+                            }
+                            // there's always a section to show
+                            if sections.read().len() > 1 {
+                                div {
+                                    class: "button nav-item",
+                                    onclick: move |_| deleting.set(Some(uuid)),
+
+                                    "X"
+                                }
+                            }
+                        }
+                    } else if deleting_pattern().is_some_and(|deleting| deleting == uuid) {
+                        div {
+                            class: "button nav-item",
+                            // the song stops playing it too
+                            onclick: move |_| {
+                                patterns.write().remove(i);
+                                song.write().chain.retain(|other| *other != uuid);
+                                deleting_pattern.set(None);
+
+                                if uuid == displayed_pattern() {
+                                    let next = patterns.read()[i.min(patterns.read().len() - 1)].uuid;
+                                    displayed_pattern.set(next);
+                                }
+                            },
+
+                            "DELETE"
+                        }
+                        div {
+                            class: "button nav-item",
+                            onclick: move |_| deleting_pattern.set(None),
+
+                            "KEEP"
+                        }
+                    } else {
+                        div {
+                            class: "button nav-item",
+                            // a copy right after the original
+                            onclick: move |_| {
+                                let mut copy = patterns.read()[i].clone();
+                                copy.uuid = next_pattern_uuid(&patterns.read(), &song.read());
+                                copy.name = format!("{}-COPY", copy.name);
+                                let uuid = copy.uuid;
+
+                                patterns.write().insert(i + 1, copy);
+                                show_pattern(uuid);
+                            },
+
+                            "D"
+                        }
+                        // there's always a pattern to show
+                        if patterns.read().len() > 1 {
+                            div {
+                                class: "button nav-item",
+                                onclick: move |_| deleting_pattern.set(Some(uuid)),
+
+                                "X"
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the synths that sections can play through. clicking one points the displayed section at it.
+#[component]
+fn InstrumentList(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    history: Signal<History>,
+    instruments: Signal<Vec<(SynthId, EngineKind)>>,
+    displayed_section: Signal<usize>,
+) -> Element {
+    let synth = use_context::<TabSynth>();
+    // the instrument whose name is being edited.
+    let mut renaming = use_signal(|| None::<SynthId>);
+    let index = move || section_index(&sections.read(), displayed_section());
+    let section_dev = move || {
+        (middle_view() == MiddleColView::Section)
+            .then(|| sections().get(index()).map(|section| section.dev.clone()))
+            .flatten()
+    };
+
+    rsx! {
+        div {
+            id: "instrument-header",
+            class: "row normal-text",
+
+            div { "Instruments" }
+            div {
+                class: "button",
+                onclick: {
+                    let synth = synth.clone();
+
+                    move |_| {
+                        let id = (1..)
+                            .map(|i| format!("SYNTH-{i}"))
+                            .find(|id| !synth.has_synth(id))
+                            .unwrap_or_default();
+
+                        if let Err(e) = synth.add_synth(&id, EngineKind::default()) {
+                            error!("{e}");
+                        }
+
+                        instruments.set(synth.list_synths());
+                    }
+                },
+
+                "+"
+            }
+        }
+
+        div {
+            id: "instrument-list",
+
+            for (id, engine) in instruments() {
+                div {
+                    class: "row instrument-item",
+                    id: {
+                        if section_dev().is_some_and(|dev| dev == id) {
+                            "displaying-sp"
+                        } else {
+                            ""
+                        }
+                    },
+
+                    if renaming().is_some_and(|renaming| renaming == id) {
+                        input {
+                            class: "nav-item",
+                            value: "{id}",
+                            onchange: {
+                                let synth = synth.clone();
+                                let id = id.clone();
+
+                                move |event: FormEvent| {
+                                    match synth.rename(&id, event.value(), &mut sections.write()) {
+                                        Ok(()) => history.write().rename_dev(&id, &event.value()),
+                                        Err(e) => error!("{e}"),
+                                    }
+
+                                    renaming.set(None);
+                                    instruments.set(synth.list_synths());
+                                }
+                            },
+                        }
+                    } else {
+                        div {
+                            class: "button nav-item",
+                            onclick: {
+                                let id = id.clone();
+
+                                move |_| {
+                                    if middle_view() == MiddleColView::Section {
+                                        let section = index();
+                                        history.write().edit_section(&mut sections.write(), section, |track| track.dev = id.clone());
+                                    }
+                                }
+                            },
+
+                            "{id}"
+                        }
+                    }
+                    div {
+                        class: "button nav-item",
+                        // cycle through the engine kinds
+                        onclick: {
+                            let synth = synth.clone();
+                            let id = id.clone();
+
+                            move |_| {
+                                let next = EngineKind::iter()
+                                    .cycle()
+                                    .skip_while(|kind| *kind != engine)
+                                    .nth(1)
+                                    .unwrap_or_default();
+
+                                if let Err(e) = synth.set_engine(&id, next) {
+                                    error!("{e}");
+                                }
+
+                                instruments.set(synth.list_synths());
+                            }
+                        },
+
+                        "{engine}"
+                    }
+                    div {
+                        class: "button nav-item",
+                        onclick: {
+                            let id = id.clone();
+
+                            move |_| renaming.set(Some(id.clone()))
+                        },
+
+                        "R"
+                    }
+                    div {
+                        class: "button nav-item",
+                        onclick: {
+                            let synth = synth.clone();
+                            let id = id.clone();
+
+                            move |_| {
+                                match synth.remove_synth(&id, &mut sections.write()) {
+                                    Ok(Some(to)) => history.write().rename_dev(&id, &to),
+                                    Ok(None) => {}
+                                    Err(e) => error!("{e}"),
+                                }
+
+                                instruments.set(synth.list_synths());
+                            }
+                        },
+
+                        "X"
+                    }
+                }
+                if engine == EngineKind::Sf2 {
+                    Sf2Settings { key: "{id}", id: id.clone() }
+                }
+                if engine == EngineKind::Sampler {
+                    SamplerSettings { key: "{id}", id: id.clone() }
+                }
+            }
+        }
+    }
+}
+
+/// which SoundFont an SF2 instrument plays, and which of its presets.
+#[component]
+fn Sf2Settings(id: SynthId) -> Element {
+    let synth = use_context::<TabSynth>();
+    let mut conf = use_signal({
+        let synth = synth.clone();
+        let id = id.clone();
+
+        move || synth.sf2(&id)
+    });
+    let presets = synth.sf2_presets(&id);
+    let selected = conf().and_then(|conf| {
+        presets
+            .iter()
+            .position(|(bank, preset, _name)| (*bank, *preset) == (conf.bank, conf.preset))
+    });
+    let path = conf().map(|conf| conf.path.display().to_string()).unwrap_or_default();
+    let preset_name = match selected.and_then(|i| presets.get(i)) {
+        Some((bank, preset, name)) => format!("{bank:03}:{preset:03} {name}"),
+        None => "NO FONT".to_string(),
+    };
+
+    rsx! {
+        div {
+            class: "row instrument-item normal-text",
+
+            input {
+                class: "nav-item",
+                placeholder: "SoundFont path",
+                value: "{path}",
+                onchange: {
+                    let synth = synth.clone();
+                    let id = id.clone();
+
+                    move |event: FormEvent| match synth.open_sf2(&id, event.value().into()) {
+                        Ok(loaded) => conf.set(Some(loaded)),
+                        Err(e) => error!("{e}"),
+                    }
+                },
+            }
+            div {
+                class: "button nav-item",
+                // the next preset in the font
+                onclick: {
+                    let synth = synth.clone();
+                    let id = id.clone();
+
+                    move |_| {
+                        let Some(i) = selected else {
+                            return;
+                        };
+                        let (bank, preset, _name) = &presets[(i + 1) % presets.len()];
+
+                        match synth.select_preset(&id, *bank, *preset) {
+                            Ok(()) => conf.set(synth.sf2(&id)),
+                            Err(e) => error!("{e}"),
+                        }
+                    }
+                },
+
+                "{preset_name}"
+            }
+        }
+    }
+}
+
+/// the midi devices that are connected. sections can play them like an instrument, and what's
+/// played on them goes to the synths.
+#[component]
+fn MidiDeviceList(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    history: Signal<History>,
+    displayed_section: Signal<usize>,
+) -> Element {
+    let synth = use_context::<TabSynth>();
+    let devices = use_context::<MidiDevices>();
+    let mut names = use_signal({
+        let devices = devices.clone();
+
+        move || devices.names()
+    });
+    let index = move || section_index(&sections.read(), displayed_section());
+    let section_dev = move || {
+        (middle_view() == MiddleColView::Section)
+            .then(|| sections().get(index()).map(|section| section.dev.clone()))
+            .flatten()
+    };
+
+    rsx! {
+        div {
+            id: "midi-header",
+            class: "row normal-text",
+
+            div { "MIDI Devices" }
+            div {
+                class: "button",
+                // connect whatever's been plugged in since
+                onclick: {
+                    let synth = synth.clone();
+                    let devices = devices.clone();
+
+                    move |_| {
+                        let connected = devices.names();
+
+                        for transport in midi::scan() {
+                            if !connected.iter().any(|name| name == transport.name()) {
+                                devices.connect(transport, MIDI_SEND.clone(), CLOCK_SEND.clone(), &synth);
+                            }
+                        }
+
+                        names.set(devices.names());
+                    }
+                },
+
+                "SCAN"
+            }
+        }
+
+        div {
+            id: "midi-list",
+
+            for name in names() {
+                div {
+                    class: "row instrument-item",
+                    id: {
+                        if section_dev().is_some_and(|dev| dev == name) {
+                            "displaying-sp"
+                        } else {
+                            ""
+                        }
+                    },
+
+                    div {
+                        class: "button nav-item",
+                        onclick: {
+                            let devices = devices.clone();
+                            let name = name.clone();
+
+                            move |_| {
+                                // it could have been unplugged since the list was drawn
+                                if !devices.names().contains(&name) {
+                                    names.set(devices.names());
+                                    return;
+                                }
+
+                                if middle_view() == MiddleColView::Section {
+                                    let section = index();
+                                    history.write().edit_section(&mut sections.write(), section, |track| track.dev = name.clone());
+                                }
+                            }
+                        },
+
+                        "{name}"
+                    }
+                    div {
+                        class: "button nav-item",
+                        onclick: {
+                            let synth = synth.clone();
+                            let devices = devices.clone();
+                            let name = name.clone();
+
+                            move |_| {
+                                devices.disconnect(&name, &synth);
+                                names.set(devices.names());
+                            }
+                        },
+
+                        "X"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the pads of a sampler: which wav each plays, on which note, and how.
+#[component]
+fn SamplerSettings(id: SynthId) -> Element {
+    let synth = use_context::<TabSynth>();
+    let conf = use_signal({
+        let synth = synth.clone();
+        let id = id.clone();
+
+        move || synth.sampler(&id).unwrap_or_default()
+    });
+    let mode = conf.read().mode;
+
+    rsx! {
+        div {
+            class: "row instrument-item normal-text",
+
+            div {
+                class: "button nav-item",
+                onclick: {
+                    let synth = synth.clone();
+                    let id = id.clone();
+
+                    move |_| {
+                        let mode = match mode {
+                            SamplerMode::Kit => SamplerMode::Chromatic,
+                            SamplerMode::Chromatic => SamplerMode::Kit,
+                        };
+
+                        save_sampler(&synth, &id, conf, SamplerConf { mode, ..conf() });
+                    }
+                },
+
+                "{mode}"
+            }
+            input {
+                class: "nav-item",
+                placeholder: "add a wav file",
+                onchange: {
+                    let synth = synth.clone();
+                    let id = id.clone();
+
+                    move |event: FormEvent| {
+                        let mut new = conf();
+                        // kits count up from the kick drum of general midi
+                        let note = new.pads.last().map_or(36, |pad| pad.note.saturating_add(1).min(127));
+                        new.pads.push(Pad::new(event.value().into(), note));
+
+                        save_sampler(&synth, &id, conf, new);
+                    }
+                },
+            }
+        }
+
+        for (i, pad) in conf().pads.into_iter().enumerate() {
+            div {
+                class: "row instrument-item sampler-pad normal-text",
+
+                div {
+                    class: "nav-item",
+                    {pad.path.file_name().unwrap_or_default().to_string_lossy().to_string()}
+                }
+                NumberField {
+                    label: "NOTE",
+                    value: pad.note as usize,
+                    min: 0,
+                    max: 127,
+                    onchange: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |note: usize| edit_pad(&synth, &id, conf, i, |pad| pad.note = note as u8)
+                    },
+                }
+                NumberField {
+                    label: "START",
+                    value: pad.start,
+                    min: 0,
+                    max: usize::MAX,
+                    onchange: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |start: usize| edit_pad(&synth, &id, conf, i, |pad| pad.start = start)
+                    },
+                }
+                // 0 plays to the end of the file
+                NumberField {
+                    label: "END",
+                    value: pad.end.unwrap_or_default(),
+                    min: 0,
+                    max: usize::MAX,
+                    onchange: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |end: usize| edit_pad(&synth, &id, conf, i, |pad| pad.end = (end > 0).then_some(end))
+                    },
+                }
+                div {
+                    class: "button nav-item",
+                    // loops everything from start to end, or nothing
+                    onclick: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |_| {
+                            edit_pad(&synth, &id, conf, i, |pad| {
+                                pad.looping = match pad.looping {
+                                    Some(_) => None,
+                                    None => Some(pad.start..pad.end.unwrap_or(usize::MAX)),
+                                };
+                            })
+                        }
+                    },
+
+                    if pad.looping.is_some() { "LOOP" } else { "ONCE" }
+                }
+                // 0 is no choke group
+                NumberField {
+                    label: "CHOKE",
+                    value: pad.choke.unwrap_or_default() as usize,
+                    min: 0,
+                    max: 16,
+                    onchange: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |group: usize| edit_pad(&synth, &id, conf, i, |pad| pad.choke = (group > 0).then_some(group as u8))
+                    },
+                }
+                NumberField {
+                    label: "GAIN %",
+                    value: (pad.gain * 100.0).round() as usize,
+                    min: 0,
+                    max: 200,
+                    onchange: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |gain: usize| edit_pad(&synth, &id, conf, i, |pad| pad.gain = gain as f32 / 100.0)
+                    },
+                }
+                div {
+                    class: "button nav-item",
+                    onclick: {
+                        let synth = synth.clone();
+                        let id = id.clone();
+
+                        move |_| {
+                            let mut new = conf();
+                            new.pads.remove(i);
+
+                            save_sampler(&synth, &id, conf, new);
+                        }
+                    },
+
+                    "X"
+                }
+            }
+        }
+    }
+}
+
+/// hand `new` to the sampler `id`, and keep it in `conf` if the sampler could load it.
+fn save_sampler(synth: &TabSynth, id: &str, mut conf: Signal<SamplerConf>, new: SamplerConf) {
+    match synth.set_sampler(id, new.clone()) {
+        Ok(()) => conf.set(new),
+        Err(e) => error!("{e}"),
+    }
+}
+
+/// change pad `i` of the sampler `id` with `edit`.
+fn edit_pad(synth: &TabSynth, id: &str, conf: Signal<SamplerConf>, i: usize, edit: impl FnOnce(&mut Pad)) {
+    let mut new = conf();
+
+    if let Some(pad) = new.pads.get_mut(i) {
+        edit(pad);
+        save_sampler(synth, id, conf, new);
+    }
+}
+
+pub fn display_midi_note(midi_note: u8) -> String {
+    let note_name_i = midi_note % 12;
+    let octave = midi_note / 12;
+
+    let note_names = [
+        "C-", "C#", "D-", "D#", "E-", "F-", "F#", "G-", "G#", "A-", "A#", "B-",
+    ];
+    let note_name = note_names[note_name_i as usize];
+
+    format!("{note_name}{octave:X}")
+}
+
+/// play, stop, pause and loop buttons, and the tempo and where the playhead is.
+#[component]
+fn Transport(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    patterns: Signal<Vec<Pattern>>,
+    song: Signal<Song>,
+    cursor: Signal<Cursor>,
+    selection: Signal<Option<Selection>>,
+    playhead: SyncSignal<Option<Playhead>>,
+    bpm: Signal<f64>,
+    lpb: Signal<usize>,
+    displayed_section: Signal<usize>,
+    displayed_pattern: Signal<usize>,
+) -> Element {
+    let synth = use_context::<TabSynth>();
+    let mut paused = use_signal(|| false);
+    let mut tap = use_signal(TapTempo::default);
+    let mut clock = use_signal(|| synth.clock_mode());
+    // `None` in pattern view, where the cursor isn't on a section
+    let shown_section = move || {
+        (middle_view() == MiddleColView::Section)
+            .then(|| sections.read().iter().find(|section| section.uuid == displayed_section()).cloned())
+            .flatten()
+    };
+    let section_class = move || if shown_section().is_some() { "button" } else { "button disabled" };
+    // start the sequencer, looping `rows` (or the whole thing) and starting from `row` of `block`
+    let start = {
+        let synth = synth.clone();
+
+        move |blocks: Vec<Vec<Track>>, block: usize, row: usize, rows: Option<Range<usize>>| {
+            // a tempo command from last time round shouldn't carry over
+            synth.set_tempo(bpm(), lpb());
+            synth.set_loop_range(rows);
+            synth.set_looping(true);
+            synth.play_song_from(blocks, block, row);
+            // following a clock, it waits for that to start
+            paused.set(synth.is_paused());
+        }
+    };
+    // pattern:line, both counting from 1
+    let position = match playhead() {
+        Some(Playhead { block, row }) => format!("{:02}:{:03}", block + 1, row + 1),
+        None => "--:---".to_string(),
+    };
+
+    rsx! {
+        div {
+            id: "transport",
+            class: "col normal-text",
+
+            div {
+                class: "row space-around",
+
+                div {
+                    class: "button",
+                    // the whole song, from the top
+                    onclick: {
+                        let mut start = start.clone();
+
+                        move |_| start(song().blocks(&patterns(), &sections()), 0, 0, None)
+                    },
+
+                    "PLAY"
+                }
+                div {
+                    class: "button",
+                    onclick: {
+                        let synth = synth.clone();
+
+                        move |_| {
+                            if paused() {
+                                synth.resume();
+                            } else {
+                                synth.pause();
+                            }
+
+                            paused.set(synth.is_paused());
+                        }
+                    },
+
+                    if paused() { "RESUME" } else { "PAUSE" }
+                }
+                div {
+                    class: "button",
+                    onclick: {
+                        let synth = synth.clone();
+
+                        move |_| {
+                            synth.stop();
+                            paused.set(false);
+                        }
+                    },
+
+                    "STOP"
+                }
+            }
+
+            div {
+                class: "row space-around",
+
+                div {
+                    class: "button",
+                    // the shown section from the cursor, or the song from the shown pattern
+                    onclick: {
+                        let mut start = start.clone();
+
+                        move |_| match middle_view() {
+                            MiddleColView::Section => {
+                                if let Some(section) = shown_section() {
+                                    start(vec![vec![section]], 0, cursor().row, None);
+                                }
+                            }
+                            MiddleColView::Pattern => {
+                                // from the top, if the song doesn't play it
+                                let block = song().chain.iter().position(|uuid| *uuid == displayed_pattern()).unwrap_or(0);
+                                start(song().blocks(&patterns(), &sections()), block, 0, None);
+                            }
+                        }
+                    },
+
+                    "CUE"
+                }
+                div {
+                    class: section_class(),
+                    onclick: {
+                        let mut start = start.clone();
+
+                        move |_| {
+                            if let Some(section) = shown_section() {
+                                start(vec![vec![section]], 0, 0, None);
+                            }
+                        }
+                    },
+
+                    "LOOP SEC"
+                }
+                div {
+                    class: section_class(),
+                    // the selected rows, or just the cursor's row
+                    onclick: {
+                        let mut start = start.clone();
+
+                        move |_| {
+                            let rows = selection().unwrap_or(Selection::at(cursor())).rows();
+                            let (first, last) = (*rows.start(), *rows.end());
+
+                            if let Some(section) = shown_section() {
+                                start(vec![vec![section]], 0, first, Some(first..last + 1));
+                            }
+                        }
+                    },
+
+                    "LOOP RANGE"
+                }
+            }
+
+            div {
+                class: "row space-around",
+
+                NumberField {
+                    label: "BPM",
+                    value: bpm().round() as usize,
+                    min: MIN_BPM,
+                    max: MAX_BPM,
+                    onchange: move |value: usize| bpm.set(value as f64),
+                }
+                div {
+                    class: "button",
+                    onclick: move |_| {
+                        if let Some(tapped) = tap.write().tap(Instant::now()) {
+                            bpm.set(tapped);
+                        }
+                    },
+
+                    "TAP"
+                }
+                div {
+                    class: "button",
+                    // keep our tempo to ourselves, send it out, or follow the one coming in
+                    onclick: {
+                        let synth = synth.clone();
+                        let mut start = start.clone();
+
+                        move |_| {
+                            let next = ClockMode::iter().cycle().skip_while(|mode| *mode != clock()).nth(1).unwrap_or_default();
+                            synth.set_clock_mode(next);
+                            clock.set(next);
+
+                            // ready the song, so the clock can start it without pressing play here
+                            if next == ClockMode::Follow && !synth.is_playing() && !synth.is_paused() {
+                                start(song().blocks(&patterns(), &sections()), 0, 0, None);
+                            }
+                        }
+                    },
+
+                    "CLK {clock}"
+                }
+                div { "{lpb} LPB" }
+                div { "{position}" }
+            }
+        }
+    }
+}
+
+/// recording notes played on a midi keyboard into the shown section, at the cursor or as it plays.
+#[component]
+fn RecordPanel(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    history: Signal<History>,
+    cursor: Signal<Cursor>,
+    displayed_section: Signal<usize>,
+) -> Element {
+    let synth = use_context::<TabSynth>();
+    let mut recorder = use_signal(Recorder::default);
+    // written from the thread that listens for notes, so it has to be sync
+    let mut incoming = use_signal_sync(Vec::<NotePlayed>::new);
+
+    use_hook({
+        let played = synth.notes_played();
+
+        move || {
+            spawn(move || {
+                for note in played.iter() {
+                    incoming.write().push(note);
+                }
+            });
+        }
+    });
+
+    use_effect({
+        let synth = synth.clone();
+
+        move || {
+            if incoming.read().is_empty() {
+                return;
+            }
+
+            // taken even when not recording, so old notes don't turn up once it is
+            let notes = std::mem::take(&mut *incoming.write());
+
+            if *middle_view.peek() != MiddleColView::Section {
+                return;
+            }
+
+            let shown = *displayed_section.peek();
+            let section = section_index(&sections.peek(), shown);
+
+            for note in notes {
+                // live takes only go in the section that's playing
+                if recorder.peek().mode == RecordMode::Live
+                    && let Some((playhead, _)) = note.position
+                    && !synth.block_sections(playhead.block).contains(&shown)
+                {
+                    continue;
+                }
+
+                let (len, lpb) = {
+                    let sections = sections.peek();
+                    (sections[section].steps.len(), sections[section].lpb)
+                };
+                // counted in the rows of the section, which can have other lines per beat than the block
+                let note = match note.position {
+                    Some((playhead, phase)) => {
+                        let (row, phase) = row_at_lpb(playhead.row, phase, synth.block_lpb(playhead.block), lpb);
+
+                        NotePlayed { position: Some((Playhead { row, ..playhead }, phase)), ..note }
+                    }
+                    None => note,
+                };
+                let Some(take) = recorder.write().record(&note, cursor.peek().row, len) else {
+                    continue;
+                };
+
+                history.write().edit_step(&mut sections.write(), section, take.row, |step| take.write(step));
+
+                if recorder.peek().mode == RecordMode::Step {
+                    cursor.write().row = recorder.peek().next_row(take.row, len);
+                }
+            }
+        }
+    });
+
+    rsx! {
+        div {
+            id: "record",
+            class: "row space-around normal-text",
+
+            div {
+                class: if recorder.read().is_armed() { "button selected" } else { "button" },
+                onclick: move |_| {
+                    let armed = recorder.read().is_armed();
+                    recorder.write().arm(!armed);
+                },
+
+                "REC"
+            }
+            div {
+                class: "button",
+                onclick: move |_| {
+                    let mode = recorder.read().mode;
+                    recorder.write().mode = RecordMode::iter().cycle().skip_while(|other| *other != mode).nth(1).unwrap_or_default();
+                },
+
+                "{recorder.read().mode}"
+            }
+            if recorder.read().mode == RecordMode::Step {
+                NumberField {
+                    label: "STEP",
+                    value: recorder.read().edit_step,
+                    min: 0,
+                    max: MAX_EDIT_STEP,
+                    onchange: move |value: usize| recorder.write().edit_step = value,
+                }
+            } else {
+                NumberField {
+                    label: "QUANT",
+                    value: recorder.read().quantize,
+                    min: 1,
+                    max: MAX_QUANTIZE,
+                    onchange: move |value: usize| recorder.write().quantize = value,
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn PlayTone() -> Element {
+    let mut playing = false;
+
+    rsx! {
         button { onclick: move |_| {
             let send = if !playing {
                 MIDI_SEND.send(MidiMessage::NoteOn(midi_control::Channel::Ch1, KeyEvent { key: 48, value: 90 }))
@@ -587,13 +2396,422 @@ fn PlayTone() -> Element {
     }
 }
 
+/// gain, pan, mute and solo for every synth, plus the master volume.
+#[component]
+fn MixerPanel(instruments: Signal<Vec<(SynthId, EngineKind)>>, mixer: Signal<Mixer>) -> Element {
+    let synth = use_context::<TabSynth>();
+
+    rsx! {
+        div {
+            id: "mixer",
+            class: "col",
+
+            for (id, _engine) in instruments() {
+                div {
+                    class: "col mixer-strip normal-text",
+
+                    div { "{id}" }
+                    input {
+                        r#type: "range",
+                        min: "0",
+                        max: "2",
+                        step: "0.01",
+                        value: "{mixer().strip(&id).gain}",
+                        oninput: {
+                            let synth = synth.clone();
+                            let id = id.clone();
+
+                            move |event: FormEvent| {
+                                let mut strip = mixer().strip(&id);
+                                strip.gain = event.value().parse().unwrap_or(strip.gain);
+                                synth.set_strip(&id, strip);
+                                mixer.set(synth.mixer());
+                            }
+                        },
+                    }
+                    input {
+                        r#type: "range",
+                        min: "-1",
+                        max: "1",
+                        step: "0.01",
+                        value: "{mixer().strip(&id).pan}",
+                        oninput: {
+                            let synth = synth.clone();
+                            let id = id.clone();
+
+                            move |event: FormEvent| {
+                                let mut strip = mixer().strip(&id);
+                                strip.pan = event.value().parse().unwrap_or(strip.pan);
+                                synth.set_strip(&id, strip);
+                                mixer.set(synth.mixer());
+                            }
+                        },
+                    }
+                    div {
+                        class: "row",
+
+                        div {
+                            class: "button col normal-text",
+                            onclick: {
+                                let synth = synth.clone();
+                                let id = id.clone();
+
+                                move |_| {
+                                    let mut strip = mixer().strip(&id);
+                                    strip.mute = !strip.mute;
+                                    synth.set_strip(&id, strip);
+                                    mixer.set(synth.mixer());
+                                }
+                            },
+
+                            div { "M" }
+                            div {
+                                class: {
+                                    let mut classes = vec!["led"];
+                                    if mixer().strip(&id).mute { classes.push("led-on") }
+                                    classes.join(" ")
+                                },
+                            }
+                        }
+                        div {
+                            class: "button col normal-text",
+                            onclick: {
+                                let synth = synth.clone();
+                                let id = id.clone();
+
+                                move |_| {
+                                    let mut strip = mixer().strip(&id);
+                                    strip.solo = !strip.solo;
+                                    synth.set_strip(&id, strip);
+                                    mixer.set(synth.mixer());
+                                }
+                            },
+
+                            div { "S" }
+                            div {
+                                class: {
+                                    let mut classes = vec!["led"];
+                                    if mixer().strip(&id).solo { classes.push("led-on") }
+                                    classes.join(" ")
+                                },
+                            }
+                        }
+                    }
+                }
+            }
+
+            div {
+                class: "col mixer-strip normal-text",
+
+                div { "Master" }
+                input {
+                    r#type: "range",
+                    min: "0",
+                    max: "2",
+                    step: "0.01",
+                    value: "{mixer().master}",
+                    oninput: {
+                        let synth = synth.clone();
+
+                        move |event: FormEvent| {
+                            synth.set_master(event.value().parse().unwrap_or(mixer().master));
+                            mixer.set(synth.mixer());
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn ProjectMenu(
+    middle_view: Signal<MiddleColView>,
+    sections: Signal<Vec<Track>>,
+    patterns: Signal<Vec<Pattern>>,
+    song: Signal<Song>,
+    history: Signal<History>,
+    instruments: Signal<Vec<(SynthId, EngineKind)>>,
+    mixer: Signal<Mixer>,
+    bpm: Signal<f64>,
+    lpb: Signal<usize>,
+    displayed_section: Signal<usize>,
+    displayed_pattern: Signal<usize>,
+    edit_cell: Signal<Option<(usize, Colums)>>,
+) -> Element {
+    let synth = use_context::<TabSynth>();
+    let mut path = use_signal(|| "project.ron".to_string());
+    // where the project was last saved to or loaded from, "SAVE" writes back to it.
+    let mut project_path = use_signal(|| None::<PathBuf>);
+    // written from the thread a bounce renders on, so it has to be sync
+    let mut status = use_signal_sync(String::new);
+    let mut bouncing = use_signal_sync(|| false);
+    let mut bounce_format = use_signal(WavFormat::default);
+    let mut stems = use_signal(|| false);
+
+    let project = move |synth: &TabSynth| Project {
+        bpm: bpm(),
+        lpb: lpb(),
+        sections: sections(),
+        patterns: patterns(),
+        song: song(),
+        instruments: synth.instruments(),
+        mixer: synth.mixer(),
+        ..Default::default()
+    };
+
+    let mut save_to = move |to: PathBuf, synth: &TabSynth| {
+        match project(synth).save(&to) {
+            Ok(()) => {
+                info!("saved project to {}", to.display());
+                status.set(format!("saved {}", to.display()));
+                project_path.set(Some(to));
+            }
+            Err(e) => {
+                error!("{e}");
+                status.set(e);
+            }
+        }
+    };
+
+    rsx! {
+        div {
+            id: "project-menu",
+            class: "col",
+
+            input {
+                class: "normal-text",
+                value: "{path}",
+                oninput: move |event| path.set(event.value()),
+            }
+
+            div {
+                class: "row",
+
+                div {
+                    class: "button normal-text",
+                    onclick: {
+                        let synth = synth.clone();
+
+                        move |_| save_to(project_path().unwrap_or(PathBuf::from(path())), &synth)
+                    },
+
+                    "SAVE"
+                }
+                div {
+                    class: "button normal-text",
+                    onclick: {
+                        let synth = synth.clone();
+
+                        move |_| save_to(PathBuf::from(path()), &synth)
+                    },
+
+                    "SAVE AS"
+                }
+                div {
+                    class: "button normal-text",
+                    onclick: {
+                        let synth = synth.clone();
+
+                        move |_| {
+                            let from = PathBuf::from(path());
+
+                            match Project::load(&from) {
+                                Ok(project) => {
+                                    synth.load_instruments(&project.instruments);
+                                    synth.set_mixer(project.mixer);
+                                    mixer.set(synth.mixer());
+                                    instruments.set(synth.list_synths());
+                                    bpm.set(project.bpm);
+                                    lpb.set(project.lpb);
+
+                                    let mut loaded_sections = project.sections;
+
+                                    if loaded_sections.is_empty() {
+                                        loaded_sections.push(Track::default());
+                                    }
+
+                                    displayed_section.set(loaded_sections[0].uuid);
+                                    sections.set(loaded_sections);
+                                    history.write().clear();
+                                    let mut loaded_patterns = project.patterns;
+
+                                    if loaded_patterns.is_empty() {
+                                        loaded_patterns.push(Pattern::new(None, next_pattern_uuid(&[], &project.song)));
+                                    }
+
+                                    displayed_pattern.set(loaded_patterns[0].uuid);
+                                    patterns.set(loaded_patterns);
+                                    song.set(project.song);
+                                    middle_view.set(MiddleColView::Section);
+                                    edit_cell.set(None);
+
+                                    info!("loaded project from {}", from.display());
+                                    status.set(format!("loaded {}", from.display()));
+                                    project_path.set(Some(from));
+                                }
+                                Err(e) => {
+                                    error!("{e}");
+                                    status.set(e);
+                                }
+                            }
+                        }
+                    },
+
+                    "LOAD"
+                }
+            }
+
+            div {
+                class: "row",
+
+                div {
+                    class: "button normal-text",
+                    onclick: move |_| {
+                        let formats: Vec<WavFormat> = WavFormat::iter().collect();
+                        let i = formats.iter().position(|format| *format == bounce_format()).unwrap_or(0);
+                        bounce_format.set(formats[(i + 1) % formats.len()]);
+                    },
+
+                    "{bounce_format}"
+                }
+                div {
+                    class: "led-select",
+                    onclick: move |_| stems.set(!stems()),
+
+                    div {
+                        class: if stems() { "led led-on" } else { "led" },
+                    }
+                    div {
+                        class: "normal-text",
+                        "STEMS"
+                    }
+                }
+                div {
+                    class: if bouncing() { "button normal-text disabled" } else { "button normal-text" },
+                    onclick: {
+                        let synth = synth.clone();
+
+                        move |_| {
+                            if bouncing() {
+                                return;
+                            }
+
+                            let to = PathBuf::from(path()).with_extension("wav");
+                            let conf = Bounce {
+                                format: bounce_format(),
+                                stems: stems(),
+                                ..Default::default()
+                            };
+                            let project = project(&synth);
+
+                            bouncing.set(true);
+                            status.set(format!("bouncing {}...", to.display()));
+
+                            // rendering takes a while, so it happens off the ui thread
+                            spawn(move || {
+                                match render::bounce(&project, &to, &conf) {
+                                    Ok(written) => {
+                                        info!("bounced {} file(s) to {}", written.len(), to.display());
+                                        status.set(format!("bounced {}", to.display()));
+                                    }
+                                    Err(e) => {
+                                        error!("{e}");
+                                        status.set(e);
+                                    }
+                                }
+
+                                bouncing.set(false);
+                            });
+                        }
+                    },
+
+                    if bouncing() { "BOUNCING" } else { "BOUNCE" }
+                }
+            }
+
+            div {
+                class: "row",
+
+                div {
+                    class: "button normal-text",
+                    onclick: move |_| {
+                        let to = PathBuf::from(path()).with_extension("mid");
+
+                        match smf::save_midi(&to, &sections(), bpm(), lpb()) {
+                            Ok(()) => {
+                                info!("exported midi to {}", to.display());
+                                status.set(format!("exported {}", to.display()));
+                            }
+                            Err(e) => {
+                                error!("{e}");
+                                status.set(e);
+                            }
+                        }
+                    },
+
+                    "MIDI OUT"
+                }
+                div {
+                    class: "button normal-text",
+                    onclick: move |_| {
+                        let from = PathBuf::from(path()).with_extension("mid");
+                        let dev = instruments
+                            .peek()
+                            .first()
+                            .map(|(id, _engine)| id.clone())
+                            .unwrap_or("Default".into());
+
+                        match smf::load_midi(&from, lpb(), next_section_uuid(&sections.peek(), &patterns.peek()), &dev) {
+                            Ok(imported) => {
+                                let n = imported.tracks.len();
+                                for track in imported.tracks {
+                                    let end = sections.peek().len();
+                                    history.write().insert(&mut sections.write(), end, track);
+                                }
+
+                                let mut msg = format!("imported {n} section(s) from {}", from.display());
+
+                                // the file was written at this tempo, so it plays back the same
+                                if let Some(file_bpm) = imported.bpm {
+                                    let file_bpm = file_bpm.clamp(MIN_BPM as f64, MAX_BPM as f64);
+                                    bpm.set(file_bpm);
+                                    msg.push_str(&format!(" at {file_bpm:.0} bpm"));
+                                }
+
+                                if imported.dropped > 0 {
+                                    msg.push_str(&format!(", {} note(s) didn't fit and were left out", imported.dropped));
+                                }
+
+                                info!("{msg}");
+                                status.set(msg);
+                            }
+                            Err(e) => {
+                                error!("{e}");
+                                status.set(e);
+                            }
+                        }
+                    },
+
+                    "MIDI IN"
+                }
+            }
+
+            div {
+                class: "normal-text",
+                "{status}"
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn note_display() {
-        assert_eq!(display_midi_note(60), "C-4");
+        assert_eq!(display_midi_note(60), "C-5");
     }
 }
 
@@ -0,0 +1,433 @@
+use crate::{
+    SynthId,
+    playback::{SeqMsg, clock::ClockMsg},
+    synth::TabSynth,
+};
+use crossbeam::channel::{Sender, bounded};
+use midi_control::MidiMessage;
+use packet::{Packet, decode, decode_clock, encode, encode_clock};
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::spawn,
+    time::Duration,
+};
+use tracing::*;
+use transport::Transport;
+
+pub mod packet;
+#[cfg(all(feature = "desktop", target_os = "linux"))]
+pub mod seq;
+pub mod transport;
+#[cfg(target_os = "android")]
+pub mod usb;
+
+/// what our ALSA sequencer client is called, on linux desktops.
+#[cfg(all(feature = "desktop", target_os = "linux"))]
+const ALSA_CLIENT: &str = "tracker-synth";
+/// how long the reader waits on a device before checking whether it should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(50);
+/// how many messages can queue up for a device before new ones get dropped. the audio thread
+/// sends them, so it never waits on a slow device.
+pub const OUTPUT_QUEUE: usize = 1024;
+/// the cable that output goes out on. multi-port devices get everything on their first port.
+const OUTPUT_CABLE: u8 = 0;
+
+/// a connected device. what's played on it is sent to the `input` it was connected with, its clock
+/// to `clock`, and what's sent to `output` is played on it. reading stops when it's dropped, writing once
+/// every clone of `output` is. if reading or writing fails it stops running, and `on_lost` is
+/// called.
+pub struct MidiDevice {
+    name: SynthId,
+    output: Sender<SeqMsg>,
+    running: Arc<AtomicBool>,
+}
+
+impl MidiDevice {
+    pub fn connect(
+        transport: Arc<dyn Transport>,
+        input: Sender<MidiMessage>,
+        clock: Sender<ClockMsg>,
+        on_lost: impl Fn() + Clone + Send + 'static,
+    ) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let (output, outgoing) = bounded::<SeqMsg>(OUTPUT_QUEUE);
+
+        spawn({
+            let transport = transport.clone();
+            let running = running.clone();
+            let on_lost = on_lost.clone();
+
+            move || {
+                let mut packets = Vec::new();
+
+                while running.load(Ordering::Relaxed) {
+                    if let Err(e) = transport.read(&mut packets, READ_TIMEOUT) {
+                        warn!("{e}");
+                        running.store(false, Ordering::Relaxed);
+                        on_lost();
+                        return;
+                    }
+
+                    for packet in packets.drain(..) {
+                        let sent = if let Some(msg) = decode(packet) {
+                            input.send(msg).is_ok()
+                        } else if let Some(msg) = decode_clock(packet) {
+                            clock.send(msg).is_ok()
+                        } else {
+                            true
+                        };
+
+                        if !sent {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        spawn({
+            let transport = transport.clone();
+            let running = running.clone();
+
+            move || {
+                // everything that's waiting goes out in one transfer
+                while let Ok(msg) = outgoing.recv() {
+                    let packets: Vec<_> = [msg]
+                        .into_iter()
+                        .chain(outgoing.try_iter())
+                        .filter_map(|msg| write_packet(&msg))
+                        .collect();
+
+                    if let Err(e) = transport.write(&packets) {
+                        warn!("{e}");
+                        running.store(false, Ordering::Relaxed);
+                        on_lost();
+                        return;
+                    }
+                }
+            }
+        });
+
+        Self {
+            name: transport.name().to_string(),
+            output,
+            running,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// false once the device is gone, or has been dropped.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// where to send midi to play it on the device.
+    pub fn output(&self) -> Sender<SeqMsg> {
+        self.output.clone()
+    }
+}
+
+/// `msg` as a packet for a device. synth parameters don't go to devices.
+fn write_packet(msg: &SeqMsg) -> Option<Packet> {
+    match msg {
+        SeqMsg::Midi(msg) => encode(OUTPUT_CABLE, msg),
+        SeqMsg::Clock(msg) => Some(encode_clock(OUTPUT_CABLE, *msg)),
+        SeqMsg::Param(_) => None,
+    }
+}
+
+impl Drop for MidiDevice {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// every connected device. their input goes to the synths, and tracks play them by name.
+#[derive(Clone, Default)]
+pub struct MidiDevices(Arc<Mutex<Vec<MidiDevice>>>);
+
+impl MidiDevices {
+    /// start reading from `transport` into `input` and `clock`, and let tracks whose `dev` is its name play
+    /// it. connecting a device by the same name again replaces the old connection. a device that
+    /// goes away is disconnected by itself.
+    pub fn connect(
+        &self,
+        transport: Arc<dyn Transport>,
+        input: Sender<MidiMessage>,
+        clock: Sender<ClockMsg>,
+        synth: &TabSynth,
+    ) {
+        let on_lost = {
+            let devices = self.clone();
+            let synth = synth.clone();
+
+            move || devices.remove_lost(&synth)
+        };
+        let device = MidiDevice::connect(transport, input, clock, on_lost);
+        info!("connected to {}", device.name());
+        synth.set_midi_output(device.name(), Some(device.output()));
+
+        if let Ok(mut devices) = self.0.lock() {
+            devices.retain(|other| other.name != device.name);
+            devices.push(device);
+        }
+
+        // it could have gone before it was in the list
+        self.remove_lost(synth);
+    }
+
+    pub fn disconnect(&self, name: &str, synth: &TabSynth) {
+        synth.set_midi_output(name, None);
+
+        if let Ok(mut devices) = self.0.lock() {
+            devices.retain(|device| device.name != name);
+        }
+    }
+
+    /// forget every device that's stopped running, so that nothing plays it any more.
+    fn remove_lost(&self, synth: &TabSynth) {
+        let Ok(mut devices) = self.0.lock() else {
+            return;
+        };
+        let (lost, kept): (Vec<_>, Vec<_>) =
+            devices.drain(..).partition(|device| !device.is_running());
+        *devices = kept;
+
+        for device in lost {
+            warn!("lost {}, disconnecting it", device.name());
+            synth.set_midi_output(device.name(), None);
+        }
+    }
+
+    /// the names of every connected device, in the order they were connected.
+    pub fn names(&self) -> Vec<SynthId> {
+        self.0
+            .lock()
+            .map(|devices| devices.iter().map(|device| device.name.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// every midi device there is to connect to: USB devices on android, and a sequencer client of
+/// our own on linux desktops.
+pub fn scan() -> Vec<Arc<dyn Transport>> {
+    usb_devices().into_iter().chain(alsa_ports()).collect()
+}
+
+/// only android has USB host support.
+#[cfg(target_os = "android")]
+fn usb_devices() -> Vec<Arc<dyn Transport>> {
+    usb::UsbMidi::scan()
+        .into_iter()
+        .map(|device| Arc::new(device) as Arc<dyn Transport>)
+        .collect()
+}
+
+#[cfg(not(target_os = "android"))]
+fn usb_devices() -> Vec<Arc<dyn Transport>> {
+    Vec::new()
+}
+
+#[cfg(all(feature = "desktop", target_os = "linux"))]
+fn alsa_ports() -> Vec<Arc<dyn Transport>> {
+    match seq::AlsaPort::open(ALSA_CLIENT) {
+        Ok(port) => vec![Arc::new(port)],
+        Err(e) => {
+            warn!("{e}");
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(not(all(feature = "desktop", target_os = "linux")))]
+fn alsa_ports() -> Vec<Arc<dyn Transport>> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        playback::{
+            DEFAULT_VELOCITY,
+            clock::ClockMode,
+            note_on,
+        },
+        tracks::{Sf2Cmd, Track, TrackerCmd},
+    };
+    use crossbeam::channel::unbounded;
+    use midi_control::{Channel, KeyEvent};
+    use std::time::Instant;
+    use transport::Loopback;
+
+    fn key(key: u8) -> KeyEvent {
+        KeyEvent { key, value: 100 }
+    }
+
+    #[test]
+    fn loopback() {
+        let transport = Loopback::new("Loop");
+        let mut packets = Vec::new();
+
+        transport
+            .write(&[[0x09, 0x90, 60, 100], [0x08, 0x80, 60, 0]])
+            .unwrap();
+        transport.read(&mut packets, Duration::ZERO).unwrap();
+        assert_eq!(packets, vec![[0x09, 0x90, 60, 100], [0x08, 0x80, 60, 0]]);
+
+        packets.clear();
+        transport
+            .read(&mut packets, Duration::from_millis(1))
+            .unwrap();
+        assert_eq!(packets, Vec::<Packet>::new());
+    }
+
+    #[test]
+    fn input_reaches_the_router() {
+        let (send, recv) = unbounded();
+        let (clock_send, clock_recv) = unbounded();
+        let device = MidiDevice::connect(Arc::new(Loopback::new("Loop")), send, clock_send, || {});
+
+        // played on the device, it comes straight back as input
+        let msgs = [
+            SeqMsg::Midi(MidiMessage::NoteOn(Channel::Ch3, key(60))),
+            SeqMsg::Midi(MidiMessage::Invalid),
+            SeqMsg::Param(Sf2Cmd::Offset(10)),
+            SeqMsg::Clock(ClockMsg::SongPosition(300)),
+            SeqMsg::Clock(ClockMsg::Continue),
+            SeqMsg::Midi(MidiMessage::NoteOff(Channel::Ch3, key(60))),
+        ];
+        msgs.into_iter()
+            .for_each(|msg| device.output().send(msg).unwrap());
+
+        let timeout = Duration::from_secs(1);
+        assert_eq!(
+            recv.recv_timeout(timeout),
+            Ok(MidiMessage::NoteOn(Channel::Ch3, key(60)))
+        );
+        assert_eq!(
+            recv.recv_timeout(timeout),
+            Ok(MidiMessage::NoteOff(Channel::Ch3, key(60)))
+        );
+        // and its clock on its own
+        assert_eq!(
+            clock_recv.recv_timeout(timeout),
+            Ok(ClockMsg::SongPosition(300))
+        );
+        assert_eq!(clock_recv.recv_timeout(timeout), Ok(ClockMsg::Continue));
+
+        drop(device);
+        assert!(recv.recv_timeout(timeout).is_err());
+    }
+
+    #[test]
+    fn tracks_play_devices_by_name() {
+        let synth = TabSynth::headless();
+        let devices = MidiDevices::default();
+        let (send, recv) = unbounded();
+        devices.connect(Arc::new(Loopback::new("Gear")), send, unbounded().0, &synth);
+        assert_eq!(devices.names(), vec!["Gear".to_string()]);
+
+        let mut track = Track::new(None, 0, "Gear".into());
+        track.chan = Channel::Ch10;
+        track.steps[0].note = Some(36);
+        track.steps[0].cmds.0 = TrackerCmd::Panic;
+        synth.play(vec![track]);
+
+        let mut events = Vec::new();
+        let start = Instant::now();
+        let mut played = Vec::new();
+
+        while played.len() < 2 && start.elapsed() < Duration::from_secs(1) {
+            synth.next_sample(&mut events);
+            played.extend(recv.try_iter());
+        }
+
+        assert!(matches!(
+            played[0],
+            MidiMessage::ControlChange(Channel::Ch10, _)
+        ));
+        assert_eq!(played[1], note_on(Channel::Ch10, 36, DEFAULT_VELOCITY));
+
+        devices.disconnect("Gear", &synth);
+        assert!(devices.names().is_empty());
+        assert!(synth.midi_outputs().is_empty());
+    }
+
+    /// a device that's been unplugged.
+    struct Unplugged;
+
+    impl Transport for Unplugged {
+        fn name(&self) -> &str {
+            "Gone"
+        }
+
+        fn read(&self, _packets: &mut Vec<Packet>, _timeout: Duration) -> Result<(), String> {
+            Err("Gone was unplugged".into())
+        }
+
+        fn write(&self, _packets: &[Packet]) -> Result<(), String> {
+            Err("Gone was unplugged".into())
+        }
+    }
+
+    #[test]
+    fn lost_devices_disconnect() {
+        let synth = TabSynth::headless();
+        let devices = MidiDevices::default();
+        let (send, _recv) = unbounded();
+        let (clock, _clock_recv) = unbounded();
+        devices.connect(Arc::new(Loopback::new("Gear")), send.clone(), clock.clone(), &synth);
+        devices.connect(Arc::new(Unplugged), send, clock, &synth);
+
+        let start = Instant::now();
+
+        while devices.names().len() > 1 && start.elapsed() < Duration::from_secs(1) {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(devices.names(), vec!["Gear".to_string()]);
+        assert_eq!(synth.midi_outputs(), vec!["Gear".to_string()]);
+    }
+
+    #[test]
+    fn clock_goes_to_every_device() {
+        let synth = TabSynth::headless();
+        let devices = MidiDevices::default();
+        let (send, _recv) = unbounded();
+        let (clock, recv) = unbounded();
+        devices.connect(Arc::new(Loopback::new("Gear")), send.clone(), clock.clone(), &synth);
+        devices.connect(Arc::new(Loopback::new("Drums")), send, clock, &synth);
+
+        // playing neither of them
+        synth.set_clock_mode(ClockMode::Send);
+        synth.play(vec![Track::new(None, 0, "Default".into())]);
+
+        let mut events = Vec::new();
+        let start = Instant::now();
+        let mut played = Vec::new();
+
+        while played.len() < 4 && start.elapsed() < Duration::from_secs(1) {
+            synth.next_sample(&mut events);
+            played.extend(recv.try_iter());
+        }
+
+        let count = |msg| played.iter().filter(|played| **played == msg).count();
+        assert_eq!(count(ClockMsg::Start), 2);
+        assert_eq!(count(ClockMsg::Tick), 2);
+
+        // ticks could still be on their way
+        synth.stop();
+        let stops: Vec<_> = std::iter::from_fn(|| recv.recv_timeout(Duration::from_secs(1)).ok())
+            .filter(|msg| *msg != ClockMsg::Tick)
+            .take(2)
+            .collect();
+        assert_eq!(stops, vec![ClockMsg::Stop; 2]);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{
+    playback::clock::{ClockMsg, MAX_SPP},
+    tracks::{CHANNELS, channel_index},
+};
+use midi_control::{ControlEvent, KeyEvent, MidiMessage};
+
+/// one USB-MIDI event: a header holding the cable number and code index number (CIN), then up to
+/// three bytes of midi.
+pub type Packet = [u8; 4];
+
+/// the midi in `packet`, for channel voice messages. sysex, system messages and the rest come back
+/// as `None`, as do packets whose CIN doesn't match their status byte.
+pub fn decode(packet: Packet) -> Option<MidiMessage> {
+    let [header, status, data_1, data_2] = packet;
+    let cin = header & 0x0F;
+
+    if !(0x8..=0xE).contains(&cin) || status >> 4 != cin {
+        return None;
+    }
+
+    let chan = CHANNELS[(status & 0x0F) as usize];
+    let (data_1, data_2) = (data_1 & 0x7F, data_2 & 0x7F);
+    let key = KeyEvent {
+        key: data_1,
+        value: data_2,
+    };
+
+    Some(match cin {
+        0x8 => MidiMessage::NoteOff(chan, key),
+        0x9 => MidiMessage::NoteOn(chan, key),
+        0xA => MidiMessage::PolyKeyPressure(chan, key),
+        0xB => MidiMessage::ControlChange(
+            chan,
+            ControlEvent {
+                control: data_1,
+                value: data_2,
+            },
+        ),
+        0xC => MidiMessage::ProgramChange(chan, data_1),
+        0xD => MidiMessage::ChannelPressure(chan, data_1),
+        _ => MidiMessage::PitchBend(chan, data_1, data_2),
+    })
+}
+
+/// `msg` as a packet on `cable`, or `None` if it isn't a channel voice message. unused data bytes
+/// are zero, as the spec asks.
+pub fn encode(cable: u8, msg: &MidiMessage) -> Option<Packet> {
+    let (kind, chan, data_1, data_2) = match msg {
+        MidiMessage::NoteOff(chan, KeyEvent { key, value }) => (0x8, chan, *key, *value),
+        MidiMessage::NoteOn(chan, KeyEvent { key, value }) => (0x9, chan, *key, *value),
+        MidiMessage::PolyKeyPressure(chan, KeyEvent { key, value }) => (0xA, chan, *key, *value),
+        MidiMessage::ControlChange(chan, ControlEvent { control, value }) => {
+            (0xB, chan, *control, *value)
+        }
+        MidiMessage::ProgramChange(chan, program) => (0xC, chan, *program, 0),
+        MidiMessage::ChannelPressure(chan, pressure) => (0xD, chan, *pressure, 0),
+        MidiMessage::PitchBend(chan, lsb, msb) => (0xE, chan, *lsb, *msb),
+        _ => return None,
+    };
+
+    Some([
+        ((cable & 0x0F) << 4) | kind,
+        (kind << 4) | channel_index(*chan),
+        data_1 & 0x7F,
+        data_2 & 0x7F,
+    ])
+}
+
+/// the clock message in `packet`, if it's one.
+pub fn decode_clock(packet: Packet) -> Option<ClockMsg> {
+    let [header, status, lsb, msb] = packet;
+
+    match (header & 0x0F, status) {
+        (0xF, 0xF8) => Some(ClockMsg::Tick),
+        (0xF, 0xFA) => Some(ClockMsg::Start),
+        (0xF, 0xFB) => Some(ClockMsg::Continue),
+        (0xF, 0xFC) => Some(ClockMsg::Stop),
+        (0x3, 0xF2) => Some(ClockMsg::SongPosition(
+            ((msb as u16 & 0x7F) << 7) | (lsb as u16 & 0x7F),
+        )),
+        _ => None,
+    }
+}
+
+/// `msg` as a packet on `cable`. the real time ones are a single byte, a song position pointer is
+/// a three byte system common message.
+pub fn encode_clock(cable: u8, msg: ClockMsg) -> Packet {
+    let cable = (cable & 0x0F) << 4;
+
+    match msg {
+        ClockMsg::Tick => [cable | 0xF, 0xF8, 0, 0],
+        ClockMsg::Start => [cable | 0xF, 0xFA, 0, 0],
+        ClockMsg::Continue => [cable | 0xF, 0xFB, 0, 0],
+        ClockMsg::Stop => [cable | 0xF, 0xFC, 0, 0],
+        ClockMsg::SongPosition(pos) => {
+            let pos = pos.min(MAX_SPP);
+
+            [cable | 0x3, 0xF2, (pos & 0x7F) as u8, (pos >> 7) as u8]
+        }
+    }
+}
+
+/// a channel voice or clock message, as raw midi `bytes`, in a packet on `cable`.
+pub fn from_bytes(cable: u8, bytes: &[u8]) -> Option<Packet> {
+    let (&status, data) = bytes.split_first()?;
+    let cin = match status {
+        0x80..=0xEF => status >> 4,
+        // song position pointer
+        0xF2 => 0x3,
+        // real time
+        0xF8..=0xFF => 0xF,
+        _ => return None,
+    };
+
+    Some([
+        ((cable & 0x0F) << 4) | cin,
+        status,
+        data.first().copied().unwrap_or_default(),
+        data.get(1).copied().unwrap_or_default(),
+    ])
+}
+
+/// the raw midi in `packet`, without its header or padding.
+pub fn midi_bytes(packet: &Packet) -> &[u8] {
+    let len = match packet[0] & 0x0F {
+        // reserved for future use
+        0x0 | 0x1 => 0,
+        0x5 | 0xF => 1,
+        0x2 | 0x6 | 0xC | 0xD => 2,
+        _ => 3,
+    };
+
+    &packet[1..1 + len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midi_control::Channel;
+
+    #[test]
+    fn round_trip() {
+        let key = || KeyEvent {
+            key: 60,
+            value: 100,
+        };
+        let msgs = [
+            MidiMessage::NoteOff(Channel::Ch1, key()),
+            MidiMessage::NoteOn(Channel::Ch16, key()),
+            MidiMessage::PolyKeyPressure(Channel::Ch2, key()),
+            MidiMessage::ControlChange(
+                Channel::Ch10,
+                ControlEvent {
+                    control: 74,
+                    value: 127,
+                },
+            ),
+            MidiMessage::ProgramChange(Channel::Ch3, 5),
+            MidiMessage::ChannelPressure(Channel::Ch4, 64),
+            MidiMessage::PitchBend(Channel::Ch5, 0, 64),
+        ];
+
+        for msg in msgs {
+            let packet = encode(3, &msg).unwrap();
+
+            assert_eq!(packet[0] >> 4, 3, "{msg:?}");
+            assert_eq!(decode(packet), Some(msg));
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let note_on = MidiMessage::NoteOn(
+            Channel::Ch2,
+            KeyEvent {
+                key: 60,
+                value: 100,
+            },
+        );
+
+        assert_eq!(encode(0, &note_on), Some([0x09, 0x91, 60, 100]));
+        assert_eq!(
+            encode(1, &MidiMessage::ProgramChange(Channel::Ch1, 7)),
+            Some([0x1C, 0xC0, 7, 0])
+        );
+        assert_eq!(encode(0, &MidiMessage::Invalid), None);
+
+        // the cable doesn't matter on the way in
+        assert_eq!(decode([0xF9, 0x91, 60, 100]), Some(note_on));
+        // sysex, a clock tick, and a CIN that doesn't match its status
+        assert_eq!(decode([0x04, 0xF0, 0x7E, 0x7F]), None);
+        assert_eq!(decode([0x0F, 0xF8, 0, 0]), None);
+        assert_eq!(decode([0x08, 0x91, 60, 100]), None);
+        // an empty packet, as some devices pad their transfers with
+        assert_eq!(decode([0; 4]), None);
+    }
+
+    #[test]
+    fn raw_midi() {
+        assert_eq!(from_bytes(0, &[0x91, 60, 100]), Some([0x09, 0x91, 60, 100]));
+        assert_eq!(from_bytes(2, &[0xC0, 7]), Some([0x2C, 0xC0, 7, 0]));
+        assert_eq!(from_bytes(0, &[0xF8]), Some([0x0F, 0xF8, 0, 0]));
+        assert_eq!(
+            from_bytes(0, &[0xF2, 0x10, 0x01]),
+            Some([0x03, 0xF2, 0x10, 0x01])
+        );
+        assert_eq!(from_bytes(0, &[0xF0, 0x7E]), None);
+        assert_eq!(from_bytes(0, &[]), None);
+
+        assert_eq!(midi_bytes(&[0x09, 0x91, 60, 100]), &[0x91, 60, 100]);
+        assert_eq!(midi_bytes(&[0x1C, 0xC0, 7, 0]), &[0xC0, 7]);
+        assert_eq!(midi_bytes(&[0x0F, 0xF8, 0, 0]), &[0xF8]);
+        assert_eq!(midi_bytes(&[0; 4]), &[] as &[u8]);
+    }
+
+    #[test]
+    fn clock() {
+        let msgs = [
+            ClockMsg::Tick,
+            ClockMsg::Start,
+            ClockMsg::Continue,
+            ClockMsg::Stop,
+            ClockMsg::SongPosition(0),
+            ClockMsg::SongPosition(MAX_SPP),
+        ];
+
+        for msg in msgs {
+            let packet = encode_clock(1, msg);
+
+            assert_eq!(packet[0] >> 4, 1, "{msg:?}");
+            assert_eq!(decode_clock(packet), Some(msg));
+            // and the other way round
+            assert_eq!(decode(packet), None);
+        }
+
+        assert_eq!(
+            encode_clock(0, ClockMsg::SongPosition(200)),
+            [0x03, 0xF2, 200 - 128, 1]
+        );
+        assert_eq!(decode_clock([0x09, 0x91, 60, 100]), None);
+        assert_eq!(decode_clock([0; 4]), None);
+    }
+}
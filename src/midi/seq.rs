@@ -0,0 +1,176 @@
+use super::{
+    packet::{Packet, from_bytes, midi_bytes},
+    transport::Transport,
+};
+use alsa::{
+    Direction,
+    poll::{Descriptors, poll, pollfd},
+    seq::{MidiEvent, PortCap, PortType, Seq},
+};
+use std::{ffi::CString, sync::Mutex, time::Duration};
+
+/// room for the largest channel voice message.
+const EVENT_SIZE: u32 = 3;
+/// the cable packets read from the sequencer say they came in on.
+const INPUT_CABLE: u8 = 0;
+
+/// an ALSA sequencer client of our own, with an "in" port that other clients play into and an
+/// "out" port that plays what tracks send it. wiring it up to hardware or other programs is left
+/// to `aconnect` or a patchbay.
+pub struct AlsaPort {
+    name: String,
+    seq: Mutex<Seq>,
+    in_port: i32,
+    out_port: i32,
+    /// what to wait on for input, so the reader doesn't hold `seq` while it waits.
+    fds: Vec<pollfd>,
+}
+
+impl AlsaPort {
+    pub fn open(name: &str) -> Result<Self, String> {
+        let seq = Seq::open(None, None, true)
+            .map_err(|e| format!("couldn't open the ALSA sequencer: {e}"))?;
+        let client_name = CString::new(name).map_err(|e| e.to_string())?;
+        seq.set_client_name(&client_name)
+            .map_err(|e| e.to_string())?;
+
+        let kind = PortType::MIDI_GENERIC | PortType::APPLICATION;
+        let in_port = seq
+            .create_simple_port(c"in", PortCap::WRITE | PortCap::SUBS_WRITE, kind)
+            .map_err(|e| format!("couldn't make {name}'s input port: {e}"))?;
+        let out_port = seq
+            .create_simple_port(c"out", PortCap::READ | PortCap::SUBS_READ, kind)
+            .map_err(|e| format!("couldn't make {name}'s output port: {e}"))?;
+        let fds = (&seq, Some(Direction::Capture))
+            .get()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            name: name.to_string(),
+            seq: Mutex::new(seq),
+            in_port,
+            out_port,
+            fds,
+        })
+    }
+
+    /// the sequencer client number, and the numbers of the input and output ports.
+    pub fn address(&self) -> Result<(i32, i32, i32), String> {
+        let seq = self.seq.lock().map_err(|e| e.to_string())?;
+        let client = seq.client_id().map_err(|e| e.to_string())?;
+
+        Ok((client, self.in_port, self.out_port))
+    }
+}
+
+impl Transport for AlsaPort {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&self, packets: &mut Vec<Packet>, timeout: Duration) -> Result<(), String> {
+        let mut fds = self.fds.clone();
+        poll(&mut fds, timeout.as_millis() as i32).map_err(|e| e.to_string())?;
+
+        let seq = self.seq.lock().map_err(|e| e.to_string())?;
+        let mut input = seq.input();
+        let coder = MidiEvent::new(EVENT_SIZE).map_err(|e| e.to_string())?;
+        coder.enable_running_status(false);
+
+        while input.event_input_pending(true).map_err(|e| e.to_string())? > 0 {
+            let mut event = input.event_input().map_err(|e| e.to_string())?;
+            let mut bytes = [0; EVENT_SIZE as usize];
+
+            // port announcements and the like don't decode, and get skipped
+            if let Ok(len) = coder.decode(&mut bytes, &mut event)
+                && let Some(packet) = from_bytes(INPUT_CABLE, &bytes[..len])
+            {
+                packets.push(packet);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, packets: &[Packet]) -> Result<(), String> {
+        let seq = self.seq.lock().map_err(|e| e.to_string())?;
+        let mut coder = MidiEvent::new(EVENT_SIZE).map_err(|e| e.to_string())?;
+
+        for packet in packets {
+            coder.reset_encode();
+
+            let Ok((_, Some(mut event))) = coder.encode(midi_bytes(packet)) else {
+                continue;
+            };
+
+            event.set_source(self.out_port);
+            event.set_subs();
+            event.set_direct();
+            seq.event_output_direct(&mut event)
+                .map_err(|e| format!("couldn't write to {}: {e}", self.name))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alsa::seq::{Addr, PortSubscribe};
+
+    /// snd-seq-dummy's "Midi Through" port, which plays back everything sent to it.
+    const MIDI_THROUGH: Addr = Addr {
+        client: 14,
+        port: 0,
+    };
+
+    fn subscribe(port: &AlsaPort, sender: Addr, dest: Addr) {
+        let sub = PortSubscribe::empty().unwrap();
+        sub.set_sender(sender);
+        sub.set_dest(dest);
+        port.seq.lock().unwrap().subscribe_port(&sub).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs the ALSA sequencer, with snd-seq-dummy loaded"]
+    fn through_midi_through() {
+        let port = AlsaPort::open("tracker-synth test").unwrap();
+        let (client, in_port, out_port) = port.address().unwrap();
+        subscribe(
+            &port,
+            Addr {
+                client,
+                port: out_port,
+            },
+            MIDI_THROUGH,
+        );
+        subscribe(
+            &port,
+            MIDI_THROUGH,
+            Addr {
+                client,
+                port: in_port,
+            },
+        );
+
+        let sent = [
+            [0x09, 0x91, 60, 100],
+            [0x0C, 0xC0, 7, 0],
+            [0x08, 0x81, 60, 0],
+        ];
+        port.write(&sent).unwrap();
+
+        let mut packets = Vec::new();
+
+        for _ in 0..20 {
+            port.read(&mut packets, Duration::from_millis(50)).unwrap();
+
+            if packets.len() >= sent.len() {
+                break;
+            }
+        }
+
+        assert_eq!(packets, sent);
+    }
+}
@@ -0,0 +1,53 @@
+use super::packet::Packet;
+use crossbeam::channel::{Receiver, Sender, unbounded};
+use std::time::Duration;
+
+/// moves USB-MIDI packets to and from a device. shared between the thread that reads from it and
+/// the one that writes to it, so both sides take `&self`.
+pub trait Transport: Send + Sync {
+    /// what the device calls itself. tracks play it by this name.
+    fn name(&self) -> &str;
+    /// waits up to `timeout` for packets from the device, and adds any to `packets`. an error
+    /// means the device is gone.
+    fn read(&self, packets: &mut Vec<Packet>, timeout: Duration) -> Result<(), String>;
+    fn write(&self, packets: &[Packet]) -> Result<(), String>;
+}
+
+/// a pretend device that plays back everything written to it, for testing without hardware.
+#[derive(Debug)]
+pub struct Loopback {
+    name: String,
+    queue: (Sender<Packet>, Receiver<Packet>),
+}
+
+impl Loopback {
+    pub fn new(name: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            queue: unbounded(),
+        }
+    }
+}
+
+impl Transport for Loopback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&self, packets: &mut Vec<Packet>, timeout: Duration) -> Result<(), String> {
+        if let Ok(packet) = self.queue.1.recv_timeout(timeout) {
+            packets.push(packet);
+            packets.extend(self.queue.1.try_iter());
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, packets: &[Packet]) -> Result<(), String> {
+        for packet in packets {
+            self.queue.0.send(*packet).map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use super::{packet::Packet, transport::Transport};
+use android_usbser::usb::{self, DeviceInfo};
+use crossbeam::channel::{Receiver, RecvTimeoutError, unbounded};
+use futures_lite::future::block_on;
+use nusb::{Interface, transfer::RequestBuffer};
+use std::{thread::spawn, time::Duration};
+use tracing::*;
+
+/// the USB audio device class, and the subclass of it that carries midi.
+const AUDIO_CLASS: u8 = 0x01;
+const MIDI_STREAMING: u8 = 0x03;
+/// endpoint addresses with this bit set go from the device to us.
+const DIRECTION_IN: u8 = 0x80;
+/// how much to ask for per bulk transfer. a full speed bulk endpoint's largest packet.
+const TRANSFER_SIZE: usize = 64;
+
+/// a USB MIDI class device, through android's USB host API.
+pub struct UsbMidi {
+    name: String,
+    interface: Interface,
+    out_endpoint: u8,
+    /// packets read by the thread that waits on the device. it hangs up when the device goes.
+    incoming: Receiver<Packet>,
+}
+
+impl UsbMidi {
+    /// every USB MIDI device that's plugged in and that we're allowed to use. permission is asked
+    /// for the rest, so they turn up in a later scan once it's granted.
+    pub fn scan() -> Vec<Self> {
+        let devices = match usb::list_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!("couldn't list USB devices: {e}");
+                return Vec::new();
+            }
+        };
+
+        devices
+            .iter()
+            .filter(|info| midi_interface(info).is_some())
+            .filter_map(|info| match Self::open(info) {
+                Ok(device) => Some(device),
+                Err(e) => {
+                    warn!("{e}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn open(info: &DeviceInfo) -> Result<Self, String> {
+        let name = info
+            .product_string()
+            .cloned()
+            .unwrap_or_else(|| format!("USB {:04x}:{:04x}", info.vendor_id(), info.product_id()));
+
+        if !info.check_permission().map_err(|e| e.to_string())? {
+            info.request_permission().map_err(|e| e.to_string())?;
+            return Err(format!(
+                "asked for permission to use {name}, scan again once it's given"
+            ));
+        }
+
+        let (number, in_endpoint, out_endpoint) =
+            midi_interface(info).ok_or_else(|| format!("{name} has no midi interface"))?;
+        let device = info
+            .open_device()
+            .map_err(|e| format!("couldn't open {name}: {e}"))?;
+        let interface = device
+            .claim_interface(number)
+            .map_err(|e| format!("couldn't claim the midi interface of {name}: {e}"))?;
+        let (packets, incoming) = unbounded();
+
+        spawn({
+            let interface = interface.clone();
+            let name = name.clone();
+
+            move || {
+                loop {
+                    let done =
+                        block_on(interface.bulk_in(in_endpoint, RequestBuffer::new(TRANSFER_SIZE)));
+
+                    if let Err(e) = done.status {
+                        info!("stopped reading from {name}: {e}");
+                        return;
+                    }
+
+                    for packet in done.data.chunks_exact(4) {
+                        if packets
+                            .send([packet[0], packet[1], packet[2], packet[3]])
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            name,
+            interface,
+            out_endpoint,
+            incoming,
+        })
+    }
+}
+
+/// the number of the interface of `info` that streams midi, and its bulk in and out endpoints.
+fn midi_interface(info: &DeviceInfo) -> Option<(u8, u8, u8)> {
+    info.interfaces()
+        .filter(|interface| {
+            interface.class() == AUDIO_CLASS && interface.subclass() == MIDI_STREAMING
+        })
+        .find_map(|interface| {
+            let mut endpoints = interface.endpoints().map(|endpoint| endpoint.address());
+            let in_endpoint = endpoints
+                .clone()
+                .find(|address| address & DIRECTION_IN != 0)?;
+            let out_endpoint = endpoints.find(|address| address & DIRECTION_IN == 0)?;
+
+            Some((interface.interface_number(), in_endpoint, out_endpoint))
+        })
+}
+
+impl Transport for UsbMidi {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn read(&self, packets: &mut Vec<Packet>, timeout: Duration) -> Result<(), String> {
+        match self.incoming.recv_timeout(timeout) {
+            Ok(packet) => {
+                packets.push(packet);
+                packets.extend(self.incoming.try_iter());
+
+                Ok(())
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => Err(format!("{} was unplugged", self.name)),
+        }
+    }
+
+    fn write(&self, packets: &[Packet]) -> Result<(), String> {
+        let data = packets.concat();
+
+        block_on(self.interface.bulk_out(self.out_endpoint, data))
+            .status
+            .map_err(|e| format!("couldn't write to {}: {e}", self.name))
+    }
+}
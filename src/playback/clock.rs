@@ -0,0 +1,171 @@
+use super::tempo::{MAX_BPM, MIN_BPM};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use strum::EnumIter;
+
+/// midi clock ticks per beat (quarter note).
+pub const PPQN: usize = 24;
+/// ticks per "midi beat", the 16th notes that song position pointers count in.
+pub const TICKS_PER_SPP: usize = 6;
+/// the furthest a song position pointer can point, it's 14 bits.
+pub const MAX_SPP: u16 = 0x3FFF;
+/// how many ticks the tempo is estimated from. two beats smooths out the jitter of USB and the
+/// OS without lagging too far behind a tempo change.
+const WINDOW: usize = 2 * PPQN;
+/// ticks further apart than this mean the clock stopped, so estimating starts again. a tick at
+/// `MIN_BPM` is 125ms.
+const TICK_TIMEOUT: Duration = Duration::from_millis(500);
+/// estimates closer than this to the tempo in use are jitter, not a tempo change.
+const TEMPO_TOLERANCE: f64 = 0.25;
+
+/// the midi system messages that keep gear in time with each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockMsg {
+    Tick,
+    /// play from the top of the song.
+    Start,
+    /// play from where it stopped, or where the last `SongPosition` pointed.
+    Continue,
+    Stop,
+    /// go to this many 16th notes into the song, without playing.
+    SongPosition(u16),
+}
+
+/// where the tempo comes from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, EnumIter, strum_macros::Display)]
+pub enum ClockMode {
+    /// our own, keeping it to ourselves.
+    #[default]
+    #[strum(to_string = "INT")]
+    Internal,
+    /// our own, sent out to every connected device along with start and stop.
+    #[strum(to_string = "SEND")]
+    Send,
+    /// whatever clock comes in. playing waits for a start or continue from it.
+    #[strum(to_string = "FOLLOW")]
+    Follow,
+}
+
+/// works out a tempo from incoming clock ticks.
+#[derive(Clone, Debug, Default)]
+pub struct ClockFollower {
+    ticks: VecDeque<Instant>,
+    /// the last tempo `tick` came up with.
+    bpm: Option<f64>,
+}
+
+impl ClockFollower {
+    /// a tick arrived at `at`. returns the tempo when it changes, rounded to a tenth of a beat per
+    /// minute. it takes a beat of ticks to come up with one.
+    pub fn tick(&mut self, at: Instant) -> Option<f64> {
+        if self
+            .ticks
+            .back()
+            .is_some_and(|last| at.saturating_duration_since(*last) > TICK_TIMEOUT)
+        {
+            self.ticks.clear();
+        }
+
+        self.ticks.push_back(at);
+
+        if self.ticks.len() > WINDOW + 1 {
+            self.ticks.pop_front();
+        }
+
+        if self.ticks.len() <= PPQN {
+            return None;
+        }
+
+        let tick = self.tick_length()?;
+        let bpm = (60.0 / (tick * PPQN as f64)).clamp(MIN_BPM as f64, MAX_BPM as f64);
+        let bpm = (bpm * 10.0).round() / 10.0;
+
+        if self
+            .bpm
+            .is_some_and(|old| (old - bpm).abs() < TEMPO_TOLERANCE)
+        {
+            return None;
+        }
+
+        self.bpm = Some(bpm);
+
+        Some(bpm)
+    }
+
+    pub fn bpm(&self) -> Option<f64> {
+        self.bpm
+    }
+
+    /// the seconds between ticks, as the slope of the line that best fits them. a late tick only
+    /// nudges it, where averaging the gaps would let the first and last ticks decide everything.
+    fn tick_length(&self) -> Option<f64> {
+        let first = *self.ticks.front()?;
+        let n = self.ticks.len() as f64;
+        let mean_x = (n - 1.0) / 2.0;
+        let secs = |at: &Instant| at.saturating_duration_since(first).as_secs_f64();
+        let mean_y = self.ticks.iter().map(secs).sum::<f64>() / n;
+        let (mut cov, mut var) = (0.0, 0.0);
+
+        for (x, y) in self.ticks.iter().map(secs).enumerate() {
+            let dx = x as f64 - mean_x;
+            cov += dx * (y - mean_y);
+            var += dx * dx;
+        }
+
+        (var > 0.0 && cov > 0.0).then(|| cov / var)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ticks at `bpm`, each up to 2ms early or late, from `from` seconds in.
+    fn ticks(bpm: f64, n: usize, from: f64, seed: &mut u64) -> Vec<f64> {
+        (0..n)
+            .map(|i| {
+                *seed = (*seed * 1103515245 + 12345) % (1 << 31);
+                let jitter = (*seed % 4001) as f64 / 1e6 - 0.002;
+
+                from + i as f64 * 60.0 / (bpm * PPQN as f64) + jitter
+            })
+            .collect()
+    }
+
+    #[test]
+    fn following() {
+        // far enough in that jitter can't land before it
+        let start = Instant::now() + Duration::from_secs(1);
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+        let mut follower = ClockFollower::default();
+        let mut seed = 7;
+
+        // nothing until there's a beat to go on
+        let steady = ticks(120.0, 200, 0.0, &mut seed);
+        let tempos: Vec<_> = steady.iter().map(|secs| follower.tick(at(*secs))).collect();
+        assert!(tempos[..PPQN].iter().all(Option::is_none));
+
+        // and then jitter doesn't keep moving it
+        let changes: Vec<_> = tempos.iter().flatten().collect();
+        assert!(changes.len() <= 3, "{changes:?}");
+        assert!((follower.bpm().unwrap() - 120.0).abs() < 0.3);
+
+        // a new tempo takes over within a couple of beats
+        let slower = ticks(90.0, 3 * PPQN, 200.0 * 0.5 / PPQN as f64, &mut seed);
+        slower.iter().for_each(|secs| {
+            follower.tick(at(*secs));
+        });
+        assert!((follower.bpm().unwrap() - 90.0).abs() < 0.3);
+
+        // the clock stopping starts it again
+        let restarted = ticks(140.0, PPQN + 1, 60.0, &mut seed);
+        let tempos: Vec<_> = restarted
+            .iter()
+            .map(|secs| follower.tick(at(*secs)))
+            .collect();
+        assert!(tempos[..PPQN].iter().all(Option::is_none));
+        assert!((tempos[PPQN].unwrap() - 140.0).abs() < 1.0);
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use strum::EnumIter;
+
+/// the most a track can swing, see `swing_delay`.
+pub const MAX_SWING: u8 = 100;
+
+/// how late row `row` plays with `swing`, in steps. swing pushes every other row late, by a
+/// percentage of half a step. 0 is straight, and 66 is close to a triplet shuffle.
+pub fn swing_delay(swing: u8, row: usize) -> f64 {
+    if row % 2 == 1 {
+        swing.min(MAX_SWING) as f64 / 100.0 * 0.5
+    } else {
+        0.0
+    }
+}
+
+/// a timing and accent template that a track's rows are played through, repeating every few
+/// rows.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    strum_macros::Display,
+)]
+pub enum Groove {
+    #[default]
+    #[strum(to_string = "NONE")]
+    None,
+    /// a triplet shuffle, with the off beats played softer.
+    #[strum(to_string = "SHFL")]
+    Shuffle,
+    /// everything but the beat drags a little behind.
+    #[strum(to_string = "LAID")]
+    LaidBack,
+    /// straight, but leaning on the beat.
+    #[strum(to_string = "ACNT")]
+    Accent,
+    /// small, uneven pushes and dips, so repeats don't sound like a machine.
+    #[strum(to_string = "HUMN")]
+    Human,
+}
+
+impl Groove {
+    /// how late each row is, in steps. only late, since a step can't play before it starts.
+    pub fn timing(&self) -> &'static [f64] {
+        match self {
+            Self::None | Self::Accent => &[0.0],
+            Self::Shuffle => &[0.0, 1.0 / 3.0],
+            Self::LaidBack => &[0.0, 0.1, 0.05, 0.15],
+            Self::Human => &[0.0, 0.04, 0.02, 0.06, 0.01, 0.05, 0.03, 0.02],
+        }
+    }
+
+    /// what each row's velocity is multiplied by.
+    pub fn accents(&self) -> &'static [f32] {
+        match self {
+            Self::None | Self::LaidBack => &[1.0],
+            Self::Shuffle => &[1.0, 0.8],
+            Self::Accent => &[1.2, 0.8, 1.0, 0.8],
+            Self::Human => &[1.0, 0.92, 0.97, 0.9, 1.0, 0.94, 0.96, 0.9],
+        }
+    }
+
+    pub fn delay(&self, row: usize) -> f64 {
+        let timing = self.timing();
+
+        timing[row % timing.len()]
+    }
+
+    pub fn accent(&self, row: usize) -> f32 {
+        let accents = self.accents();
+
+        accents[row % accents.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    #[test]
+    fn templates() {
+        for groove in Groove::iter() {
+            assert!(
+                !groove.timing().is_empty() && !groove.accents().is_empty(),
+                "{groove}"
+            );
+            assert!(
+                groove
+                    .timing()
+                    .iter()
+                    .all(|delay| (0.0..1.0).contains(delay)),
+                "{groove}"
+            );
+        }
+
+        assert_eq!(Groove::Shuffle.delay(3), 1.0 / 3.0);
+        assert_eq!(Groove::Accent.accent(4), 1.2);
+        assert_eq!(swing_delay(50, 0), 0.0);
+        assert_eq!(swing_delay(50, 1), 0.25);
+        assert_eq!(swing_delay(255, 1), 0.5);
+    }
+}
@@ -0,0 +1,1832 @@
+use crate::{
+    N_STEPS, SynthId,
+    tracks::{MidiNote, RepeatConf, Sf2Cmd, Step, Track, TrackerCmd, rename_dev},
+};
+use clock::{ClockMode, ClockMsg, MAX_SPP, PPQN, TICKS_PER_SPP};
+use midi_control::{Channel, ControlEvent, KeyEvent, MidiMessage};
+use std::ops::Range;
+use stepper_synth_backend::SAMPLE_RATE;
+use tempo::MIN_BPM;
+
+pub mod clock;
+pub mod groove;
+pub mod tempo;
+
+/// a point in time, counted in samples since playback started.
+pub type SampleTime = u64;
+
+pub const DEFAULT_BPM: f64 = 120.0;
+/// lines (steps) per beat. 4 makes every step a 16th note.
+pub const DEFAULT_LPB: usize = 4;
+/// the velocity used for steps that have a note but no velocity set.
+pub const DEFAULT_VELOCITY: u8 = 85;
+/// the midi "All Notes Off" channel mode message.
+pub const ALL_NOTES_OFF_CC: u8 = 123;
+
+/// how many samples a single step lasts at the given tempo.
+pub fn samples_per_step(bpm: f64, lpb: usize) -> f64 {
+    SAMPLE_RATE as f64 * 60.0 / (bpm * lpb as f64)
+}
+
+/// the row, and how far through it, that `phase` of the way through `row` of a grid with
+/// `from_lpb` lines per beat lands on in one with `to_lpb`.
+pub fn row_at_lpb(row: usize, phase: f64, from_lpb: usize, to_lpb: usize) -> (usize, f64) {
+    if from_lpb == to_lpb {
+        return (row, phase);
+    }
+
+    let at = (row as f64 + phase) * to_lpb as f64 / from_lpb.max(1) as f64;
+
+    (at as usize, at.fract())
+}
+
+/// what a sequencer event asks the device to do, or what a device has to say.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SeqMsg {
+    Midi(MidiMessage),
+    /// a synth parameter change that has no midi equivalent.
+    Param(Sf2Cmd),
+    /// keeps external gear in time. these go to every connected device, whatever their `dev`.
+    Clock(ClockMsg),
+}
+
+/// a message addressed to a synth, scheduled at a sample.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeqEvent {
+    pub at: SampleTime,
+    pub dev: SynthId,
+    pub msg: SeqMsg,
+}
+
+impl SeqEvent {
+    fn is_note_off(&self) -> bool {
+        matches!(self.msg, SeqMsg::Midi(MidiMessage::NoteOff(..)))
+    }
+
+    fn is_note_on(&self) -> bool {
+        matches!(self.msg, SeqMsg::Midi(MidiMessage::NoteOn(..)))
+    }
+}
+
+/// the step that is sounding: which block of the song, and which row of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Playhead {
+    pub block: usize,
+    pub row: usize,
+}
+
+/// how the notes of a single step get played, after both of its commands are taken into account.
+///
+/// the two command slots compose like so:
+/// - `Chord` offsets from both slots are combined.
+/// - `Roll` and `Repeat` both set how the step is retriggered, so if both slots retrigger, the
+///   second slot wins. the same goes for two `HoldFor`s.
+/// - `HoldFor` lengthens the last hit of the step, so a held `Repeat` rings on after its last
+///   repeat.
+#[derive(Clone, Debug, PartialEq)]
+struct NotePlan {
+    /// semi-tone offsets, relative to the root, of the extra notes to play.
+    chord: Vec<i8>,
+    /// how many times the notes are (re)triggered.
+    hits: usize,
+    /// how far apart the hits are.
+    spacing: RepeatConf,
+    /// how many extra steps the last hit is held for.
+    hold: usize,
+}
+
+impl NotePlan {
+    fn new<'a>(cmds: impl IntoIterator<Item = &'a TrackerCmd>) -> Self {
+        let mut plan = Self {
+            chord: Vec::new(),
+            hits: 1,
+            spacing: RepeatConf::Step,
+            hold: 0,
+        };
+
+        for cmd in cmds {
+            match cmd {
+                TrackerCmd::Chord { chord } => plan.chord.extend(chord.iter().copied()),
+                TrackerCmd::Roll { times } => {
+                    plan.hits = times.0 + 1;
+                    plan.spacing = RepeatConf::HalfStep;
+                }
+                TrackerCmd::Repeat { times } => {
+                    plan.hits = times.0 + 1;
+                    plan.spacing = RepeatConf::Step;
+                }
+                TrackerCmd::HoldFor { notes } => plan.hold = notes.0,
+                _ => {}
+            }
+        }
+
+        plan
+    }
+
+    /// the notes to play for `root`, skipping chord notes that fall outside the midi range.
+    fn notes(&self, root: MidiNote) -> Vec<MidiNote> {
+        let mut notes = vec![root];
+
+        for offset in self.chord.iter() {
+            let note = root as i16 + *offset as i16;
+
+            if (0..=127).contains(&note) && !notes.contains(&(note as MidiNote)) {
+                notes.push(note as MidiNote);
+            }
+        }
+
+        notes
+    }
+}
+
+/// walks the steps of one or more `Track`s in lock step and turns them into midi events. a song
+/// is a list of blocks (one per pattern) that are played one after the other, looping back to the
+/// first block at the end.
+///
+/// time only moves forward when `process` is called, so the audio callback (or an offline
+/// renderer) is what drives the sequencer, not the wall-clock.
+#[derive(Clone, Debug)]
+pub struct Sequencer {
+    /// the tracks to play in parallel, for each pattern in the song.
+    song: Vec<Vec<Track>>,
+    /// index into `song` of the block that is playing.
+    block: usize,
+    /// the value of `steps_fired` when the playing block started.
+    block_start: u64,
+    bpm: f64,
+    lpb: usize,
+    playing: bool,
+    /// samples processed since playback started.
+    now: SampleTime,
+    /// how many steps have been fired since playback started.
+    steps_fired: u64,
+    /// events that have been scheduled but not yet emitted, sorted by time (NoteOffs first).
+    pending: Vec<SeqEvent>,
+    /// the step, and the sample it started on, that the current tempo is counted from.
+    anchor: (u64, SampleTime),
+    /// the step that the playing block's lines per beat took over on, and how many clock ticks
+    /// into the song it is. clock ticks are counted on from there.
+    grid: (u64, f64),
+    /// go back to the first block at the end of the song, instead of stopping.
+    looping: bool,
+    /// the song has ended (only when not looping), no more steps will fire.
+    finished: bool,
+    /// stopped part way, ready to pick up from the same step.
+    paused: bool,
+    /// rows of the playing block to go round and round, instead of playing all of it.
+    loop_range: Option<Range<usize>>,
+    /// the last step that fired.
+    playhead: Option<Playhead>,
+    clock: ClockMode,
+    /// while following a clock, how many ticks into the song it started us from and how many
+    /// ticks have come in since.
+    follow: Option<(f64, u64)>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self::new(DEFAULT_BPM, DEFAULT_LPB)
+    }
+}
+
+impl Sequencer {
+    pub fn new(bpm: f64, lpb: usize) -> Self {
+        Self {
+            song: Vec::new(),
+            block: 0,
+            block_start: 0,
+            bpm,
+            lpb: lpb.max(1),
+            playing: false,
+            now: 0,
+            steps_fired: 0,
+            pending: Vec::new(),
+            anchor: (0, 0),
+            grid: (0, 0.0),
+            looping: true,
+            finished: false,
+            paused: false,
+            loop_range: None,
+            playhead: None,
+            clock: ClockMode::default(),
+            follow: None,
+        }
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    pub fn lpb(&self) -> usize {
+        self.lpb
+    }
+
+    /// the sample `process` has got up to. it only moves while playing.
+    pub fn now(&self) -> SampleTime {
+        self.now
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// the step that fired last, while playing or paused.
+    pub fn playhead(&self) -> Option<Playhead> {
+        self.playhead.filter(|_| self.playing || self.paused)
+    }
+
+    /// the step that fired last, and how far through it playback is, from 0.0 to 1.0. `None`
+    /// unless playing.
+    pub fn playhead_phase(&self) -> Option<(Playhead, f64)> {
+        let playhead = self.playhead.filter(|_| self.playing)?;
+        let start = self.step_start(self.steps_fired.checked_sub(1)?);
+        let phase = self.now.saturating_sub(start) as f64 / self.samples_per_step();
+
+        Some((playhead, phase.clamp(0.0, 1.0)))
+    }
+
+    /// true when there is nothing left to play, either because playback was stopped or because a
+    /// song that doesn't loop has reached its end and let go of every note.
+    pub fn is_finished(&self) -> bool {
+        !self.playing || (self.finished && self.pending.is_empty())
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn clock_mode(&self) -> ClockMode {
+        self.clock
+    }
+
+    /// send clock out, follow it in, or neither. ticks go out from the next step, start and stop
+    /// the next time playback does.
+    pub fn set_clock_mode(&mut self, mode: ClockMode) {
+        self.clock = mode;
+        self.follow = None;
+    }
+
+    /// only play `rows` of each block, over and over. `None` plays whole blocks again. playback
+    /// started before the range starts at its first row.
+    pub fn set_loop_range(&mut self, rows: Option<Range<usize>>) {
+        self.loop_range = rows.filter(|rows| !rows.is_empty());
+    }
+
+    /// change the tempo. steps that have already fired keep their timing, the new tempo takes
+    /// over from the next step.
+    pub fn set_tempo(&mut self, bpm: f64, lpb: usize) {
+        let next = self.steps_fired;
+        self.anchor = (next, self.step_start(next));
+        self.bpm = bpm;
+        self.lpb = lpb.max(1);
+    }
+
+    /// the step (row), within the playing pattern, that will fire next.
+    pub fn position(&self) -> usize {
+        let step = (self.steps_fired - self.block_start) as usize;
+
+        if step >= self.block_len(self.block) { 0 } else { step }
+    }
+
+    /// the uuids of the sections in `block` of the song.
+    pub fn block_sections(&self, block: usize) -> Vec<usize> {
+        self.song
+            .get(block)
+            .map(|tracks| tracks.iter().map(|track| track.uuid).collect())
+            .unwrap_or_default()
+    }
+
+    /// the index, into the song, of the pattern that is playing.
+    pub fn pattern(&self) -> usize {
+        if self.position() == 0 && self.steps_fired > self.block_start {
+            self.next_block()
+        } else {
+            self.block
+        }
+    }
+
+    /// how long a step of the playing block lasts.
+    pub fn samples_per_step(&self) -> f64 {
+        samples_per_step(self.bpm, self.block_lpb(self.block))
+    }
+
+    /// the lines per beat that block `i` of the song steps at, which are its first track's. the
+    /// other tracks in it still play their rows at their own rate. an empty block steps at the
+    /// sequencer's own.
+    pub fn block_lpb(&self, i: usize) -> usize {
+        self.song
+            .get(i)
+            .and_then(|block| block.first())
+            .map_or(self.lpb, |track| track.lpb)
+            .max(1)
+    }
+
+    /// start playing a song from the first step of its first block. any notes still held from
+    /// previous playback are dropped, so call `stop` first if they need to be released.
+    pub fn play_song(&mut self, song: Vec<Vec<Track>>) {
+        self.play_song_from(song, 0, 0);
+    }
+
+    /// like `play_song`, but starting from `row` of `block`. when following an external clock,
+    /// this only gets the song ready, and it's the clock's start or continue that plays it.
+    pub fn play_song_from(&mut self, song: Vec<Vec<Track>>, block: usize, row: usize) {
+        self.song = song;
+        self.playing = self.song.iter().any(|block| !block.is_empty());
+        self.block = block.min(self.song.len().saturating_sub(1));
+        self.block_start = 0;
+        self.now = 0;
+        // a row before the loop range starts at the range instead
+        let row = self.loop_range.as_ref().map_or(row, |rows| row.max(rows.start));
+        // counting from `row` lines the block up so that `row` fires first, right away
+        self.steps_fired = row.min(self.block_len(self.block) - 1) as u64;
+        self.pending.clear();
+        self.anchor = (self.steps_fired, 0);
+        self.grid = (self.steps_fired, self.song_ticks(self.block, self.steps_fired as usize));
+        self.finished = false;
+        self.paused = false;
+        self.playhead = None;
+
+        match self.clock {
+            ClockMode::Send if self.playing => {
+                let song_pos = self.song_position();
+                let msgs = if song_pos == 0 {
+                    vec![ClockMsg::Start]
+                } else {
+                    vec![ClockMsg::SongPosition(song_pos), ClockMsg::Continue]
+                };
+
+                self.pending.extend(msgs.into_iter().map(|msg| clock_event(0, msg)));
+            }
+            ClockMode::Follow => {
+                self.paused = self.playing;
+                self.playing = false;
+            }
+            _ => {}
+        }
+    }
+
+    /// loop `tracks`, all playing in parallel.
+    pub fn play(&mut self, tracks: Vec<Track>) {
+        self.play_song(vec![tracks]);
+    }
+
+    pub fn play_track(&mut self, track: Track) {
+        self.play(vec![track]);
+    }
+
+    /// follow a synth being renamed, so playing tracks and their pending NoteOffs still reach it.
+    pub fn rename_dev(&mut self, from: &str, to: &str) {
+        self.song
+            .iter_mut()
+            .for_each(|block| rename_dev(block, from, to));
+        self.pending
+            .iter_mut()
+            .filter(|event| event.dev == from)
+            .for_each(|event| event.dev = to.to_string());
+    }
+
+    /// stop playback, returning NoteOffs for every note that was still sounding.
+    pub fn stop(&mut self) -> Vec<SeqEvent> {
+        let was_playing = self.playing;
+        self.playing = false;
+        self.paused = false;
+
+        let mut released = released_now(self.pending.drain(..), self.now);
+        released.extend(self.clock_stop(was_playing));
+
+        released
+    }
+
+    /// stop where we are, so that `resume` carries on from the next step. returns NoteOffs for
+    /// every note that was sounding.
+    pub fn pause(&mut self) -> Vec<SeqEvent> {
+        if !self.playing {
+            return Vec::new();
+        }
+
+        self.playing = false;
+        self.paused = true;
+
+        let mut released = released_now(self.pending.drain(..), self.now);
+        released.extend(self.clock_stop(true));
+
+        released
+    }
+
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.playing = true;
+
+            if self.clock == ClockMode::Send {
+                self.pending.push(clock_event(self.now, ClockMsg::Continue));
+            }
+
+            self.follow = (self.clock == ClockMode::Follow).then(|| (self.ticks_at(self.steps_fired), 0));
+        }
+    }
+
+    /// do as an external clock says, when following one. returns NoteOffs for every note that was
+    /// sounding, if it stopped us or moved us somewhere else. ticks go to `clock_tick` instead, along
+    /// with when they came in.
+    pub fn clock_input(&mut self, msg: ClockMsg) -> Vec<SeqEvent> {
+        if self.clock != ClockMode::Follow {
+            return Vec::new();
+        }
+
+        match msg {
+            ClockMsg::Start => {
+                let released = self.cue(0);
+                self.resume();
+
+                released
+            }
+            ClockMsg::Continue => {
+                self.resume();
+                Vec::new()
+            }
+            ClockMsg::Stop => self.pause(),
+            ClockMsg::SongPosition(pos) => self.cue(pos as u64 * TICKS_PER_SPP as u64),
+            ClockMsg::Tick => Vec::new(),
+        }
+    }
+
+    /// a clock tick came in on sample `at`, while following. the tempo worked out from the ticks is
+    /// never quite the clock's, so each one puts the next step back where the ticks so far say it
+    /// falls, and playback can't drift away from the clock however long it runs.
+    pub fn clock_tick(&mut self, at: SampleTime) {
+        let Some((from, ticks)) = self.follow.filter(|_| self.playing && !self.finished) else {
+            return;
+        };
+        self.follow = Some((from, ticks + 1));
+
+        // where the tick is, in steps since playback started
+        let (grid_step, grid_ticks) = self.grid;
+        let lpb = self.block_lpb(self.block) as f64;
+        let step = grid_step as f64 + (from + ticks as f64 - grid_ticks) * lpb / PPQN as f64;
+        let next_at = at as f64 + (self.steps_fired as f64 - step) * self.samples_per_step();
+
+        self.anchor = (self.steps_fired, (next_at.round() as SampleTime).max(self.now));
+    }
+
+    /// advance the sequencer by `n_samples`, pushing every event that falls inside that window
+    /// onto `out`. events are pushed in time order, NoteOffs before anything else on the same
+    /// sample.
+    pub fn process(&mut self, n_samples: usize, out: &mut Vec<SeqEvent>) {
+        if !self.playing {
+            return;
+        }
+
+        let end = self.now + n_samples as SampleTime;
+
+        while self.now < end {
+            let next_step_at = if self.finished {
+                SampleTime::MAX
+            } else {
+                self.step_start(self.steps_fired)
+            };
+            let next = self
+                .pending
+                .first()
+                .map_or(next_step_at, |event| event.at.min(next_step_at));
+
+            if next >= end {
+                break;
+            }
+
+            self.now = next;
+
+            if next_step_at == self.now {
+                self.fire_step();
+            }
+
+            let due = self
+                .pending
+                .iter()
+                .position(|event| event.at > self.now)
+                .unwrap_or(self.pending.len());
+            out.extend(self.pending.drain(..due));
+        }
+
+        self.now = end;
+    }
+
+    /// go to `ticks` clock ticks into the song, wrapping round at the end, ready for the clock to
+    /// start us from there. this arms the last song played even once it's been stopped, so the
+    /// clock can start it again. a step before the loop range cues its first row. returns
+    /// NoteOffs for every note that was sounding.
+    fn cue(&mut self, ticks: u64) -> Vec<SeqEvent> {
+        let released = released_now(self.pending.drain(..), self.now);
+        let song_len = self.song_ticks(self.song.len(), 0);
+        let mut ticks = if song_len > 0.0 { ticks as f64 % song_len } else { 0.0 };
+        let mut block = 0;
+
+        while block + 1 < self.song.len() {
+            let block_ticks = (self.block_len(block) * PPQN) as f64 / self.block_lpb(block) as f64;
+
+            if ticks < block_ticks {
+                break;
+            }
+
+            ticks -= block_ticks;
+            block += 1;
+        }
+
+        // a pointer part way through a step goes to the start of it
+        let row = (ticks * self.block_lpb(block) as f64 / PPQN as f64 + 1e-9) as usize;
+
+        // following a clock, this waits for it
+        let song = std::mem::take(&mut self.song);
+        self.play_song_from(song, block, row);
+
+        released
+    }
+
+    /// how many clock ticks into the song `row` of `block` is.
+    fn song_ticks(&self, block: usize, row: usize) -> f64 {
+        let ticks = |rows: usize, lpb: usize| (rows * PPQN) as f64 / lpb as f64;
+
+        (0..block)
+            .map(|i| ticks(self.block_len(i), self.block_lpb(i)))
+            .sum::<f64>()
+            + ticks(row, self.block_lpb(block))
+    }
+
+    /// how many clock ticks into the song step number `n` (counted since playback started) is.
+    fn ticks_at(&self, n: u64) -> f64 {
+        let (step, ticks) = self.grid;
+
+        ticks + (n as f64 - step as f64) * PPQN as f64 / self.block_lpb(self.block) as f64
+    }
+
+    /// how far into the song the next step is, in the 16th notes of a song position pointer.
+    fn song_position(&self) -> u16 {
+        let row = (self.steps_fired - self.block_start) as usize;
+        let spp = (self.song_ticks(self.block, row) + 1e-9) as usize / TICKS_PER_SPP;
+
+        spp.min(MAX_SPP as usize) as u16
+    }
+
+    /// a clock stop, at the current sample, if we send clock and were playing.
+    fn clock_stop(&self, was_playing: bool) -> Option<SeqEvent> {
+        (self.clock == ClockMode::Send && was_playing)
+            .then(|| clock_event(self.now, ClockMsg::Stop))
+    }
+
+    /// the sample that step number `n` (counted since playback started) begins on.
+    fn step_start(&self, n: u64) -> SampleTime {
+        self.step_time(n, 0.0)
+    }
+
+    /// the sample that lands `offset` steps after the start of step `n`.
+    fn step_time(&self, n: u64, offset: f64) -> SampleTime {
+        let (anchor_step, anchor_at) = self.anchor;
+        let steps = n.saturating_sub(anchor_step) as f64 + offset;
+
+        anchor_at + (steps * self.samples_per_step()).round() as SampleTime
+    }
+
+    /// how many steps block `i` lasts, which is as long as its longest track takes to play at
+    /// its own lines per beat. empty blocks still last a full section so that a song can have
+    /// rests in it.
+    fn block_len(&self, i: usize) -> usize {
+        let lpb = self.block_lpb(i);
+
+        self.song
+            .get(i)
+            .and_then(|block| {
+                block
+                    .iter()
+                    .map(|track| (track.steps.len() * lpb).div_ceil(track.lpb.max(1)))
+                    .max()
+            })
+            .unwrap_or(N_STEPS)
+            .max(1)
+    }
+
+    fn next_block(&self) -> usize {
+        (self.block + 1) % self.song.len().max(1)
+    }
+
+    fn schedule(&mut self, events: impl IntoIterator<Item = SeqEvent>) {
+        self.pending.extend(events);
+        self.pending
+            .sort_by_key(|event| (event.at, !event.is_note_off()));
+    }
+
+    /// cut every note sounding on `dev` right now and forget its pending retriggers.
+    fn panic(&mut self, dev: &SynthId) {
+        let (cut, keep): (Vec<_>, Vec<_>) = self
+            .pending
+            .drain(..)
+            .partition(|event| event.dev == *dev && (event.is_note_on() || event.is_note_off()));
+        self.pending = keep;
+        let released = released_now(cut, self.now);
+        self.schedule(released);
+    }
+
+    fn fire_step(&mut self) {
+        if let Some(rows) = self.loop_range.clone() {
+            let row = (self.steps_fired - self.block_start) as usize;
+            let end = rows.end.min(self.block_len(self.block));
+
+            // rows before the range (it was set while they played) carry on into it
+            if row >= end {
+                self.block_start += (row - rows.start.min(end - 1)) as u64;
+            }
+        }
+
+        if (self.steps_fired - self.block_start) as usize >= self.block_len(self.block) {
+            if self.next_block() == 0 && !self.looping {
+                self.finished = true;
+                return;
+            }
+
+            // the next block can step at another rate, so time and ticks count on from here
+            let n = self.steps_fired;
+            self.anchor = (n, self.step_start(n));
+            self.grid = (n, self.ticks_at(n));
+            self.block = self.next_block();
+            self.block_start = n;
+        }
+
+        let n = self.steps_fired;
+        let row = (n - self.block_start) as usize;
+        self.playhead = Some(Playhead {
+            block: self.block,
+            row,
+        });
+        let at = |offset: f64| self.step_time(n, offset);
+        let lpb = self.block_lpb(self.block);
+        let mut scheduled = Vec::new();
+        let mut panics = Vec::new();
+        let mut tempo = None;
+
+        if self.clock == ClockMode::Send {
+            scheduled.extend(
+                ticks_in_step(self.ticks_at(n), lpb)
+                    .map(|offset| clock_event(at(offset), ClockMsg::Tick)),
+            );
+        }
+
+        for track in self.song.get(self.block).into_iter().flatten() {
+            let track_lpb = track.lpb.max(1);
+            // the rows of the track that start during this step. just the one, unless the track
+            // has other lines per beat than the block
+            let rows = (row * track_lpb).div_ceil(lpb)..((row + 1) * track_lpb).div_ceil(lpb);
+            // how long one of the track's rows lasts, in steps
+            let scale = lpb as f64 / track_lpb as f64;
+
+            for track_row in rows {
+                let start = (track_row * lpb) as f64 / track_lpb as f64 - row as f64;
+                let track_row = track_row % track.steps.len().max(1);
+                let Some(step) = track.steps.get(track_row) else {
+                    continue;
+                };
+
+                for cmd in [&step.cmds.0, &step.cmds.1] {
+                    match cmd {
+                        TrackerCmd::Panic => panics.push(track.dev.clone()),
+                        TrackerCmd::Tempo { bpm } => tempo = Some(*bpm),
+                        _ => {}
+                    }
+                }
+
+                let delay = track.delay(track_row);
+                let accent = track.accent(track_row);
+                let row_at = |offset: f64| at(start + offset * scale);
+                schedule_step(track, step, row_at, delay, accent, &mut scheduled);
+            }
+        }
+
+        for dev in panics {
+            self.panic(&dev);
+        }
+
+        self.schedule(scheduled);
+        self.steps_fired += 1;
+
+        // this step was already timed at the old tempo, so the new one starts with the next.
+        if let Some(bpm) = tempo {
+            self.set_tempo(bpm.max(MIN_BPM as u16) as f64, self.lpb);
+        }
+    }
+}
+
+fn clock_event(at: SampleTime, msg: ClockMsg) -> SeqEvent {
+    SeqEvent {
+        at,
+        dev: SynthId::new(),
+        msg: SeqMsg::Clock(msg),
+    }
+}
+
+/// where, in steps from its start, each clock tick lands in a step that starts `from` ticks into
+/// the song. there are `PPQN` ticks to every `lpb` steps, so a step can have a fractional number
+/// of them.
+fn ticks_in_step(from: f64, lpb: usize) -> impl Iterator<Item = f64> {
+    let ticks_per_step = PPQN as f64 / lpb as f64;
+    // the first tick at or after the start of the step, and the first one after it. a little
+    // leeway keeps a tick on the start of the step in it, whatever rounding did to `from`
+    let first = (from - 1e-6).ceil() as u64;
+    let last = (from + ticks_per_step - 1e-6).ceil() as u64;
+
+    (first..last).map(move |tick| ((tick as f64 - from) / ticks_per_step).max(0.0))
+}
+
+/// one NoteOff, at `now`, for every distinct note that `events` would have released later.
+fn released_now(events: impl IntoIterator<Item = SeqEvent>, now: SampleTime) -> Vec<SeqEvent> {
+    let mut released: Vec<SeqEvent> = Vec::new();
+
+    for event in events.into_iter().filter(SeqEvent::is_note_off) {
+        if !released
+            .iter()
+            .any(|other| other.dev == event.dev && other.msg == event.msg)
+        {
+            released.push(SeqEvent { at: now, ..event });
+        }
+    }
+
+    released
+}
+
+/// turns one step of `track` into events, including any retriggers and NoteOffs that land after
+/// the step has ended. `at` maps an offset, in steps from the start of the step, to a sample, and
+/// the velocity of every note is multiplied by `accent`.
+///
+/// the step starts `delay` steps late, from swing or a groove, but its notes still end on the
+/// grid. otherwise a late note would end after the next row's note started, and cut it off when
+/// it's the same note.
+fn schedule_step(
+    track: &Track,
+    step: &Step,
+    at: impl Fn(f64) -> SampleTime,
+    delay: f64,
+    accent: f32,
+    out: &mut Vec<SeqEvent>,
+) {
+    let event = |at, msg| SeqEvent {
+        at,
+        dev: track.dev.clone(),
+        msg,
+    };
+    let cmds = [&step.cmds.0, &step.cmds.1];
+
+    for cmd in cmds {
+        match cmd {
+            TrackerCmd::Panic => out.push(event(
+                at(delay),
+                SeqMsg::Midi(control_change(track.chan, ALL_NOTES_OFF_CC, 0)),
+            )),
+            TrackerCmd::MidiCmd { cc_param, arg } => out.push(event(
+                at(delay),
+                SeqMsg::Midi(control_change(track.chan, *cc_param, *arg)),
+            )),
+            TrackerCmd::Custom(cmd) => out.push(event(at(delay), SeqMsg::Param(*cmd))),
+            _ => {}
+        }
+    }
+
+    let Some(root) = step.note else {
+        return;
+    };
+    let velocity = step.velocity.unwrap_or(DEFAULT_VELOCITY);
+    // an accent can't make a note silent, or louder than midi goes.
+    let velocity = ((velocity as f32 * accent).round() as u8).clamp(velocity.min(1), 127);
+    let plan = NotePlan::new(cmds);
+    let spacing = plan.spacing.steps();
+
+    for hit in 0..plan.hits {
+        let start = hit as f64 * spacing;
+        let len = if hit + 1 == plan.hits {
+            spacing + plan.hold as f64
+        } else {
+            spacing
+        };
+        // only a delay as long as the note itself pushes its end back, so it still sounds
+        let end = if delay < len {
+            start + len
+        } else {
+            start + delay + len
+        };
+
+        for note in plan.notes(root) {
+            out.push(event(
+                at(start + delay),
+                SeqMsg::Midi(note_on(track.chan, note, velocity)),
+            ));
+            out.push(event(at(end), SeqMsg::Midi(note_off(track.chan, note))));
+        }
+    }
+}
+
+pub fn note_on(chan: Channel, note: MidiNote, velocity: u8) -> MidiMessage {
+    MidiMessage::NoteOn(
+        chan,
+        KeyEvent {
+            key: note,
+            value: velocity,
+        },
+    )
+}
+
+pub fn note_off(chan: Channel, note: MidiNote) -> MidiMessage {
+    MidiMessage::NoteOff(chan, KeyEvent { key: note, value: 0 })
+}
+
+/// builds a CC message, clamping both bytes to the 7-bit midi data range.
+pub fn control_change(chan: Channel, control: u8, value: u8) -> MidiMessage {
+    MidiMessage::ControlChange(
+        chan,
+        ControlEvent {
+            control: control.min(127),
+            value: value.min(127),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::less_then::UsizeLessThan;
+    use groove::Groove;
+
+    fn track_with(notes: &[(usize, MidiNote, Option<u8>)]) -> Track {
+        let mut track = Track::default();
+
+        for (i, note, velocity) in notes {
+            track.steps[*i].note = Some(*note);
+            track.steps[*i].velocity = *velocity;
+        }
+
+        track
+    }
+
+    /// renders `n_samples` in blocks of `block` samples, the way the audio callback would.
+    fn render(seq: &mut Sequencer, n_samples: usize, block: usize) -> Vec<SeqEvent> {
+        let mut events = Vec::new();
+        let mut done = 0;
+
+        while done < n_samples {
+            let n = block.min(n_samples - done);
+            seq.process(n, &mut events);
+            done += n;
+        }
+
+        events
+    }
+
+    fn midi(at: SampleTime, msg: MidiMessage) -> SeqEvent {
+        SeqEvent {
+            at,
+            dev: "Default".into(),
+            msg: SeqMsg::Midi(msg),
+        }
+    }
+
+    #[test]
+    fn step_length() {
+        let sps = samples_per_step(120.0, 4);
+        assert_eq!(sps, SAMPLE_RATE as f64 / 8.0);
+    }
+
+    #[test]
+    fn note_timing() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let sps = seq.samples_per_step();
+        seq.play_track(track_with(&[(0, 48, Some(100)), (4, 50, None), (5, 52, Some(10))]));
+
+        let events = render(&mut seq, (sps * 8.0) as usize, 1);
+        let at = |step: f64| (step * sps).round() as SampleTime;
+        let chan = Channel::Ch1;
+
+        assert_eq!(
+            events,
+            vec![
+                midi(at(0.0), note_on(chan, 48, 100)),
+                midi(at(1.0), note_off(chan, 48)),
+                midi(at(4.0), note_on(chan, 50, DEFAULT_VELOCITY)),
+                midi(at(5.0), note_off(chan, 50)),
+                midi(at(5.0), note_on(chan, 52, 10)),
+                midi(at(6.0), note_off(chan, 52)),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_size_does_not_change_timing() {
+        let mut track = track_with(&[(0, 60, None), (3, 62, None), (7, 64, None), (100, 65, None)]);
+        track.steps[3].cmds.0 = TrackerCmd::Roll {
+            times: UsizeLessThan(3),
+        };
+        let n_samples = (samples_per_step(133.0, 3) * 130.0) as usize;
+
+        let mut per_sample = Sequencer::new(133.0, 3);
+        per_sample.play_track(track.clone());
+        let mut per_block = Sequencer::new(133.0, 3);
+        per_block.play_track(track);
+
+        assert_eq!(
+            render(&mut per_sample, n_samples, 1),
+            render(&mut per_block, n_samples, 512)
+        );
+    }
+
+    #[test]
+    fn loops_at_end_of_track() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let sps = seq.samples_per_step();
+        let len = Track::default().steps.len();
+        seq.play_track(track_with(&[(0, 48, None)]));
+
+        let events = render(&mut seq, (sps * (len as f64 + 0.5)) as usize, 256);
+        let note_ons: Vec<SampleTime> = events
+            .iter()
+            .filter(|event| event.is_note_on())
+            .map(|event| event.at)
+            .collect();
+
+        assert_eq!(note_ons, vec![0, (len as f64 * sps).round() as SampleTime]);
+        assert_eq!(seq.position(), 1);
+    }
+
+    #[test]
+    fn parallel_tracks_use_their_own_device_and_channel() {
+        let mut bass = track_with(&[(0, 36, None)]);
+        bass.dev = "Bass".into();
+        bass.chan = Channel::Ch2;
+        let lead = track_with(&[(0, 72, None)]);
+
+        let mut seq = Sequencer::default();
+        seq.play(vec![bass, lead]);
+        let events = render(&mut seq, 1, 1);
+
+        assert_eq!(
+            events,
+            vec![
+                SeqEvent {
+                    at: 0,
+                    dev: "Bass".into(),
+                    msg: SeqMsg::Midi(note_on(Channel::Ch2, 36, DEFAULT_VELOCITY)),
+                },
+                midi(0, note_on(Channel::Ch1, 72, DEFAULT_VELOCITY)),
+            ]
+        );
+    }
+
+    #[test]
+    fn song_advances_pattern_by_pattern() {
+        let intro = track_with(&[(0, 48, None)]);
+        let mut bass = track_with(&[(0, 36, None), (127, 38, None)]);
+        bass.dev = "Bass".into();
+        let lead = track_with(&[(64, 72, None)]);
+
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        let len = N_STEPS as f64;
+        seq.play_song(vec![vec![intro], vec![bass, lead]]);
+
+        let events = render(&mut seq, (sps * (len * 2.0 + 1.5)) as usize, 128);
+        let note_ons: Vec<(SampleTime, SynthId)> = events
+            .iter()
+            .filter(|event| event.is_note_on())
+            .map(|event| (event.at, event.dev.clone()))
+            .collect();
+        let at = |step: f64| (step * sps).round() as SampleTime;
+
+        assert_eq!(
+            note_ons,
+            vec![
+                (at(0.0), "Default".into()),
+                (at(len), "Bass".into()),
+                (at(len + 64.0), "Default".into()),
+                (at(len + 127.0), "Bass".into()),
+                (at(len * 2.0), "Default".into()),
+            ]
+        );
+        assert_eq!(seq.pattern(), 0);
+        assert_eq!(seq.position(), 2);
+    }
+
+    #[test]
+    fn song_ends_when_not_looping() {
+        let mut last = track_with(&[(0, 48, None), (127, 50, None)]);
+        last.steps[127].cmds.0 = TrackerCmd::HoldFor {
+            notes: UsizeLessThan(2),
+        };
+
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        let len = N_STEPS as f64;
+        seq.set_looping(false);
+        seq.play_song(vec![vec![track_with(&[])], vec![last]]);
+
+        let events = render(&mut seq, (sps * (len * 3.0)) as usize, 256);
+        let at = |step: f64| (step * sps).round() as SampleTime;
+
+        assert_eq!(events.iter().filter(|event| event.is_note_on()).count(), 2);
+        // the held note still gets its NoteOff after the song is over.
+        assert_eq!(
+            events.last(),
+            Some(&midi(at(len * 2.0 + 2.0), note_off(Channel::Ch1, 50)))
+        );
+        assert!(seq.is_finished());
+    }
+
+    #[test]
+    fn tempo_changes_at_the_next_step() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let slow = seq.samples_per_step();
+        seq.play_track(track_with(&[(0, 48, None), (1, 50, None), (2, 52, None)]));
+
+        let mut events = render(&mut seq, (slow * 0.5) as usize, 1);
+        seq.set_tempo(240.0, 4);
+        let fast = seq.samples_per_step();
+        events.extend(render(&mut seq, (slow * 3.0) as usize, 1));
+
+        let note_ons: Vec<SampleTime> = events
+            .iter()
+            .filter(|event| event.is_note_on())
+            .map(|event| event.at)
+            .collect();
+        let step_1 = slow.round() as SampleTime;
+
+        assert_eq!(
+            note_ons,
+            vec![0, step_1, step_1 + fast.round() as SampleTime]
+        );
+        assert_eq!(seq.bpm(), 240.0);
+    }
+
+    #[test]
+    fn playhead_phase() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let sps = seq.samples_per_step();
+        assert_eq!(seq.playhead_phase(), None);
+
+        seq.play_track(track_with(&[]));
+        render(&mut seq, (sps * 2.25) as usize, 64);
+        let (playhead, phase) = seq.playhead_phase().unwrap();
+        assert_eq!(playhead, Playhead { block: 0, row: 2 });
+        assert!((phase - 0.25).abs() < 0.01, "{phase}");
+
+        seq.pause();
+        assert_eq!(seq.playhead_phase(), None);
+    }
+
+    #[test]
+    fn stop_releases_held_notes() {
+        let mut seq = Sequencer::default();
+        seq.play_track(track_with(&[(0, 48, None)]));
+        render(&mut seq, 10, 1);
+
+        let released = seq.stop();
+
+        assert_eq!(released, vec![midi(10, note_off(Channel::Ch1, 48))]);
+        assert!(!seq.is_playing());
+        assert!(render(&mut seq, 100_000, 1).is_empty());
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        seq.play_track(track_with(&[(0, 48, None), (1, 50, None)]));
+        render(&mut seq, 10, 1);
+
+        assert_eq!(seq.pause(), vec![midi(10, note_off(Channel::Ch1, 48))]);
+        assert!(seq.is_paused());
+        assert!(render(&mut seq, 100_000, 1).is_empty());
+        assert_eq!(seq.playhead(), Some(Playhead { block: 0, row: 0 }));
+
+        // time stood still, so the next step is where it would have been
+        seq.resume();
+        let events = render(&mut seq, sps as usize + 1, 1);
+        assert_eq!(
+            events.first(),
+            Some(&midi(sps.round() as SampleTime, note_on(Channel::Ch1, 50, DEFAULT_VELOCITY)))
+        );
+        assert_eq!(seq.playhead(), Some(Playhead { block: 0, row: 1 }));
+
+        seq.stop();
+        assert_eq!(seq.playhead(), None);
+    }
+
+    #[test]
+    fn play_from_a_row() {
+        let intro = track_with(&[(0, 48, None)]);
+        let verse = track_with(&[(5, 60, None), (6, 62, None)]);
+
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        seq.play_song_from(vec![vec![intro], vec![verse]], 1, 5);
+
+        let note_ons: Vec<SeqEvent> = render(&mut seq, (sps * 1.5) as usize, 64)
+            .into_iter()
+            .filter(SeqEvent::is_note_on)
+            .collect();
+
+        assert_eq!(
+            note_ons,
+            vec![
+                midi(0, note_on(Channel::Ch1, 60, DEFAULT_VELOCITY)),
+                midi(sps.round() as SampleTime, note_on(Channel::Ch1, 62, DEFAULT_VELOCITY)),
+            ]
+        );
+        assert_eq!(seq.pattern(), 1);
+        assert_eq!(seq.playhead(), Some(Playhead { block: 1, row: 6 }));
+    }
+
+    #[test]
+    fn loop_range() {
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        seq.set_loop_range(Some(4..6));
+        seq.play_song_from(vec![vec![track_with(&[(4, 48, None), (5, 50, None)])]], 0, 4);
+
+        let rows: Vec<MidiNote> = render(&mut seq, (sps * 5.5) as usize, 1)
+            .into_iter()
+            .filter_map(|event| match event.msg {
+                SeqMsg::Midi(MidiMessage::NoteOn(_, KeyEvent { key, .. })) => Some(key),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(rows, vec![48, 50, 48, 50, 48, 50]);
+        assert_eq!(seq.playhead(), Some(Playhead { block: 0, row: 5 }));
+    }
+
+    #[test]
+    fn loop_range_from_before_it() {
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        seq.set_loop_range(Some(4..6));
+        seq.play_song(vec![vec![track_with(&[(0, 36, None), (4, 48, None), (5, 50, None)])]]);
+
+        assert_eq!(
+            note_ons(render(&mut seq, (sps * 3.5) as usize, 64))
+                .into_iter()
+                .map(|(_, note, _)| note)
+                .collect::<Vec<_>>(),
+            vec![48, 50, 48, 50]
+        );
+        assert_eq!(seq.playhead(), Some(Playhead { block: 0, row: 5 }));
+
+        // a range set while earlier rows play is played into, then looped
+        let mut seq = Sequencer::default();
+        seq.play_track(track_with(&[(0, 36, None), (2, 38, None), (4, 48, None)]));
+        render(&mut seq, 1, 1);
+        seq.set_loop_range(Some(4..5));
+
+        assert_eq!(
+            note_ons(render(&mut seq, (sps * 5.0) as usize, 64))
+                .into_iter()
+                .map(|(_, note, _)| note)
+                .collect::<Vec<_>>(),
+            vec![38, 48, 48]
+        );
+    }
+
+    #[test]
+    fn sections_play_at_their_own_lines_per_beat() {
+        let beat = samples_per_step(120.0, 1);
+        let at = |beats: f64| (beats * beat).round() as SampleTime;
+        let notes = |events| {
+            note_ons(events)
+                .into_iter()
+                .map(|(at, note, _)| (at, note))
+                .collect::<Vec<_>>()
+        };
+        let mut triplets = track_with(&[(0, 48, None), (1, 50, None), (3, 52, None)]);
+        triplets.lpb = 3;
+
+        // on its own, a triplet section steps three rows to the beat
+        let mut seq = Sequencer::new(120.0, 4);
+        seq.play_track(triplets.clone());
+        assert_eq!(seq.samples_per_step(), samples_per_step(120.0, 3));
+        assert_eq!(
+            notes(render(&mut seq, (beat * 1.5) as usize, 64)),
+            vec![(at(0.0), 48), (at(1.0 / 3.0), 50), (at(1.0), 52)]
+        );
+
+        // next to a section of 16ths, the block steps at the first one's rate and each plays
+        // its own rows
+        let sixteenths = track_with(&[(0, 60, None), (2, 62, None), (4, 64, None)]);
+        let mut seq = Sequencer::new(120.0, 4);
+        seq.play(vec![sixteenths, triplets]);
+        assert_eq!(
+            notes(render(&mut seq, (beat * 1.5) as usize, 64)),
+            vec![
+                (at(0.0), 60),
+                (at(0.0), 48),
+                (at(1.0 / 3.0), 50),
+                (at(0.5), 62),
+                (at(1.0), 64),
+                (at(1.0), 52),
+            ]
+        );
+        // as long as the triplets take to play, in 16ths
+        assert_eq!(seq.block_len(0), N_STEPS * 4 / 3 + 1);
+    }
+
+    /// (sample, note, velocity) of every NoteOn in `events`.
+    fn note_ons(events: Vec<SeqEvent>) -> Vec<(SampleTime, MidiNote, u8)> {
+        events
+            .into_iter()
+            .filter_map(|event| match event.msg {
+                SeqMsg::Midi(MidiMessage::NoteOn(_, KeyEvent { key, value })) => {
+                    Some((event.at, key, value))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn swing_delays_every_other_row() {
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        let mut track = track_with(&[(0, 48, None), (1, 50, None), (2, 52, None), (3, 53, None)]);
+        track.swing = 50;
+        seq.play_track(track);
+
+        let at = |step: f64| (step * sps).round() as SampleTime;
+
+        assert_eq!(
+            note_ons(render(&mut seq, (sps * 4.0) as usize, 64)),
+            vec![
+                (at(0.0), 48, DEFAULT_VELOCITY),
+                (at(1.25), 50, DEFAULT_VELOCITY),
+                (at(2.0), 52, DEFAULT_VELOCITY),
+                (at(3.25), 53, DEFAULT_VELOCITY),
+            ]
+        );
+    }
+
+    #[test]
+    fn swung_notes_end_on_the_grid() {
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        // the same note twice, the way a bass line or hats repeat
+        let mut track = track_with(&[(1, 48, None), (2, 48, None)]);
+        track.swing = 50;
+        seq.play_track(track);
+
+        let at = |step: f64| (step * sps).round() as SampleTime;
+        let chan = Channel::Ch1;
+
+        assert_eq!(
+            render(&mut seq, (sps * 4.0) as usize, 64),
+            vec![
+                midi(at(1.25), note_on(chan, 48, DEFAULT_VELOCITY)),
+                midi(at(2.0), note_off(chan, 48)),
+                midi(at(2.0), note_on(chan, 48, DEFAULT_VELOCITY)),
+                midi(at(3.0), note_off(chan, 48)),
+            ]
+        );
+    }
+
+    #[test]
+    fn groove_accents_and_timing() {
+        let mut seq = Sequencer::default();
+        let sps = seq.samples_per_step();
+        let mut track = track_with(&[(0, 48, Some(100)), (1, 50, Some(100)), (2, 52, Some(120))]);
+        track.groove = Groove::Shuffle;
+        seq.play_track(track);
+
+        let at = |step: f64| (step * sps).round() as SampleTime;
+
+        assert_eq!(
+            note_ons(render(&mut seq, (sps * 3.0) as usize, 1)),
+            vec![(at(0.0), 48, 100), (at(4.0 / 3.0), 50, 80), (at(2.0), 52, 120)]
+        );
+
+        let mut seq = Sequencer::default();
+        let mut track = track_with(&[(0, 48, Some(120)), (1, 50, Some(1))]);
+        track.groove = Groove::Accent;
+        seq.play_track(track);
+
+        let velocities: Vec<u8> = note_ons(render(&mut seq, (sps * 2.0) as usize, 1))
+            .into_iter()
+            .map(|(_, _, velocity)| velocity)
+            .collect();
+
+        // accents stay in midi range, and never silence a note.
+        assert_eq!(velocities, vec![127, 1]);
+    }
+
+    #[test]
+    fn tempo_cmd_changes_timing_from_the_next_step() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let slow = samples_per_step(120.0, 4);
+        let fast = samples_per_step(240.0, 4);
+        let mut track = track_with(&[(0, 48, None), (1, 50, None), (2, 52, None), (3, 53, None)]);
+        track.steps[1].cmds.1 = TrackerCmd::Tempo { bpm: 240 };
+        seq.play_track(track);
+
+        let events = render(&mut seq, (slow * 2.0 + fast * 2.0) as usize, 100);
+        let starts: Vec<SampleTime> = note_ons(events).into_iter().map(|(at, ..)| at).collect();
+
+        assert_eq!(
+            starts,
+            vec![
+                0,
+                slow.round() as SampleTime,
+                (slow * 2.0).round() as SampleTime,
+                (slow * 2.0 + fast).round() as SampleTime,
+            ]
+        );
+        assert_eq!(seq.bpm(), 240.0);
+    }
+
+    /// one row of the command table: what steps 0 and 1 hold, and the events that should come out
+    /// of rendering them, with times given in steps.
+    struct CmdCase {
+        name: &'static str,
+        steps: [(Option<MidiNote>, TrackerCmd, TrackerCmd); 2],
+        expected: Vec<(f64, SeqMsg)>,
+    }
+
+    #[test]
+    fn tracker_cmds() {
+        use TrackerCmd as Cmd;
+
+        let ch = Channel::Ch1;
+        let on = |note| SeqMsg::Midi(note_on(ch, note, DEFAULT_VELOCITY));
+        let off = |note| SeqMsg::Midi(note_off(ch, note));
+        let cc = |control, value| SeqMsg::Midi(control_change(ch, control, value));
+        let none = (None, Cmd::None, Cmd::None);
+
+        let cases = [
+            CmdCase {
+                name: "no command",
+                steps: [(Some(60), Cmd::None, Cmd::None), none.clone()],
+                expected: vec![(0.0, on(60)), (1.0, off(60))],
+            },
+            CmdCase {
+                name: "chord",
+                steps: [
+                    (Some(60), Cmd::Chord { chord: vec![4, 7] }, Cmd::None),
+                    none.clone(),
+                ],
+                expected: vec![
+                    (0.0, on(60)),
+                    (0.0, on(64)),
+                    (0.0, on(67)),
+                    (1.0, off(60)),
+                    (1.0, off(64)),
+                    (1.0, off(67)),
+                ],
+            },
+            CmdCase {
+                name: "chord notes outside the midi range are dropped",
+                steps: [
+                    (Some(125), Cmd::Chord { chord: vec![-12, 7] }, Cmd::None),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, on(125)), (0.0, on(113)), (1.0, off(125)), (1.0, off(113))],
+            },
+            CmdCase {
+                name: "roll",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::Roll {
+                            times: UsizeLessThan(2),
+                        },
+                        Cmd::None,
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![
+                    (0.0, on(60)),
+                    (0.5, off(60)),
+                    (0.5, on(60)),
+                    (1.0, off(60)),
+                    (1.0, on(60)),
+                    (1.5, off(60)),
+                ],
+            },
+            CmdCase {
+                name: "repeat",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::Repeat {
+                            times: UsizeLessThan(1),
+                        },
+                        Cmd::None,
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, on(60)), (1.0, off(60)), (1.0, on(60)), (2.0, off(60))],
+            },
+            CmdCase {
+                name: "hold",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::HoldFor {
+                            notes: UsizeLessThan(3),
+                        },
+                        Cmd::None,
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, on(60)), (4.0, off(60))],
+            },
+            CmdCase {
+                name: "panic cuts held notes",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::HoldFor {
+                            notes: UsizeLessThan(8),
+                        },
+                        Cmd::None,
+                    ),
+                    (None, Cmd::Panic, Cmd::None),
+                ],
+                expected: vec![(0.0, on(60)), (1.0, off(60)), (1.0, cc(ALL_NOTES_OFF_CC, 0))],
+            },
+            CmdCase {
+                name: "panic drops pending retriggers",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::Repeat {
+                            times: UsizeLessThan(4),
+                        },
+                        Cmd::None,
+                    ),
+                    (Some(62), Cmd::None, Cmd::Panic),
+                ],
+                expected: vec![
+                    (0.0, on(60)),
+                    (1.0, off(60)),
+                    (1.0, cc(ALL_NOTES_OFF_CC, 0)),
+                    (1.0, on(62)),
+                    (2.0, off(62)),
+                ],
+            },
+            CmdCase {
+                name: "midi cc",
+                steps: [
+                    (
+                        None,
+                        Cmd::MidiCmd {
+                            cc_param: 74,
+                            arg: 100,
+                        },
+                        Cmd::None,
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, cc(74, 100))],
+            },
+            CmdCase {
+                name: "midi cc is clamped to 7 bits",
+                steps: [
+                    (
+                        None,
+                        Cmd::MidiCmd {
+                            cc_param: 255,
+                            arg: 200,
+                        },
+                        Cmd::None,
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, cc(127, 127))],
+            },
+            CmdCase {
+                name: "custom",
+                steps: [
+                    (None, Cmd::Custom(Sf2Cmd::Atk(10)), Cmd::None),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, SeqMsg::Param(Sf2Cmd::Atk(10)))],
+            },
+            CmdCase {
+                name: "chords from both slots are combined",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::Chord { chord: vec![4] },
+                        Cmd::Chord { chord: vec![4, 7] },
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![
+                    (0.0, on(60)),
+                    (0.0, on(64)),
+                    (0.0, on(67)),
+                    (1.0, off(60)),
+                    (1.0, off(64)),
+                    (1.0, off(67)),
+                ],
+            },
+            CmdCase {
+                name: "second slot retrigger wins",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::Repeat {
+                            times: UsizeLessThan(5),
+                        },
+                        Cmd::Roll {
+                            times: UsizeLessThan(1),
+                        },
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, on(60)), (0.5, off(60)), (0.5, on(60)), (1.0, off(60))],
+            },
+            CmdCase {
+                name: "chord and roll",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::Chord { chord: vec![7] },
+                        Cmd::Roll {
+                            times: UsizeLessThan(1),
+                        },
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![
+                    (0.0, on(60)),
+                    (0.0, on(67)),
+                    (0.5, off(60)),
+                    (0.5, off(67)),
+                    (0.5, on(60)),
+                    (0.5, on(67)),
+                    (1.0, off(60)),
+                    (1.0, off(67)),
+                ],
+            },
+            CmdCase {
+                name: "hold lengthens the last repeat",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::HoldFor {
+                            notes: UsizeLessThan(2),
+                        },
+                        Cmd::Repeat {
+                            times: UsizeLessThan(1),
+                        },
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, on(60)), (1.0, off(60)), (1.0, on(60)), (4.0, off(60))],
+            },
+            CmdCase {
+                name: "cc is sent before the note",
+                steps: [
+                    (
+                        Some(60),
+                        Cmd::None,
+                        Cmd::MidiCmd {
+                            cc_param: 1,
+                            arg: 64,
+                        },
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![(0.0, cc(1, 64)), (0.0, on(60)), (1.0, off(60))],
+            },
+            CmdCase {
+                name: "commands without a note only fire their messages",
+                steps: [
+                    (
+                        None,
+                        Cmd::Chord { chord: vec![4] },
+                        Cmd::Roll {
+                            times: UsizeLessThan(3),
+                        },
+                    ),
+                    none.clone(),
+                ],
+                expected: vec![],
+            },
+        ];
+
+        for CmdCase {
+            name,
+            steps,
+            expected,
+        } in cases
+        {
+            let mut track = Track::default();
+
+            for (i, (note, cmd_1, cmd_2)) in steps.into_iter().enumerate() {
+                track.steps[i].note = note;
+                track.steps[i].cmds = (cmd_1, cmd_2);
+            }
+
+            let mut seq = Sequencer::default();
+            let sps = seq.samples_per_step();
+            seq.play_track(track);
+
+            let events = render(&mut seq, (sps * 10.0) as usize, 64);
+            let expected: Vec<SeqEvent> = expected
+                .into_iter()
+                .map(|(steps, msg)| SeqEvent {
+                    at: (steps * sps).round() as SampleTime,
+                    dev: "Default".into(),
+                    msg,
+                })
+                .collect();
+
+            assert_eq!(events, expected, "{name}");
+        }
+    }
+
+    fn clocks(events: &[SeqEvent]) -> Vec<(SampleTime, ClockMsg)> {
+        events
+            .iter()
+            .filter_map(|event| match event.msg {
+                SeqMsg::Clock(msg) => Some((event.at, msg)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sending_clock() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let sps = seq.samples_per_step();
+        seq.set_clock_mode(ClockMode::Send);
+        seq.play_track(track_with(&[(0, 48, None)]));
+
+        // start goes out before the first tick, and the tick before the first note
+        let events = render(&mut seq, (sps * 4.0) as usize, 64);
+        assert_eq!(events[0].msg, SeqMsg::Clock(ClockMsg::Start));
+        assert_eq!(events[1].msg, SeqMsg::Clock(ClockMsg::Tick));
+        assert!(events[2].is_note_on());
+
+        // a beat of four steps is 24 ticks
+        let tick = sps * 4.0 / PPQN as f64;
+        let ticks: Vec<_> = clocks(&events)
+            .into_iter()
+            .filter(|(_, msg)| *msg == ClockMsg::Tick)
+            .map(|(at, _)| at)
+            .collect();
+        assert_eq!(ticks.len(), PPQN);
+        for (i, at) in ticks.into_iter().enumerate() {
+            assert!((at as f64 - i as f64 * tick).abs() <= 1.0, "tick {i} at {at}");
+        }
+
+        let now = (sps * 4.0) as SampleTime;
+        assert_eq!(clocks(&seq.pause()), vec![(now, ClockMsg::Stop)]);
+        seq.resume();
+        assert_eq!(
+            clocks(&render(&mut seq, 1, 1))[0],
+            (now, ClockMsg::Continue)
+        );
+        assert_eq!(clocks(&seq.stop()), vec![(now + 1, ClockMsg::Stop)]);
+
+        // starting part way through says where
+        seq.play_song_from(vec![vec![track_with(&[])]], 0, 8);
+        assert_eq!(
+            clocks(&render(&mut seq, 1, 1)),
+            vec![
+                (0, ClockMsg::SongPosition(8)),
+                (0, ClockMsg::Continue),
+                (0, ClockMsg::Tick)
+            ]
+        );
+    }
+
+    #[test]
+    fn following_clock() {
+        let mut seq = Sequencer::new(120.0, 4);
+        seq.set_clock_mode(ClockMode::Follow);
+        seq.play_track(track_with(&[(0, 48, None), (8, 50, None)]));
+
+        // playing waits for the clock to start
+        assert!(render(&mut seq, 10_000, 64).is_empty());
+        assert!(seq.is_paused());
+
+        assert!(seq.clock_input(ClockMsg::Start).is_empty());
+        assert_eq!(
+            note_ons(render(&mut seq, 1, 1)),
+            vec![(0, 48, DEFAULT_VELOCITY)]
+        );
+
+        assert_eq!(
+            seq.clock_input(ClockMsg::Stop),
+            vec![midi(1, note_off(Channel::Ch1, 48))]
+        );
+        assert!(render(&mut seq, 10_000, 64).is_empty());
+
+        // two 16ths a step, at four lines a beat
+        seq.clock_input(ClockMsg::SongPosition(8));
+        assert!(render(&mut seq, 10_000, 64).is_empty());
+        seq.clock_input(ClockMsg::Continue);
+        assert_eq!(
+            note_ons(render(&mut seq, 1, 1)),
+            vec![(0, 50, DEFAULT_VELOCITY)]
+        );
+
+        // and without following, clock is ignored
+        let mut seq = Sequencer::default();
+        seq.play_track(track_with(&[(0, 48, None)]));
+        assert!(seq.clock_input(ClockMsg::Stop).is_empty());
+        assert!(seq.is_playing());
+    }
+
+    #[test]
+    fn following_clock_stays_locked_to_its_ticks() {
+        // clocks a little either side of the tempo we think they are, for 16 bars
+        for bpm in [120.4, 119.6] {
+            let mut seq = Sequencer::new(120.0, 4);
+            seq.set_clock_mode(ClockMode::Follow);
+            let mut track = Track::default();
+            track.steps.iter_mut().for_each(|step| step.note = Some(48));
+            seq.play_track(track);
+            seq.clock_input(ClockMsg::Start);
+
+            let tick = samples_per_step(bpm, 4) * 4.0 / PPQN as f64;
+            let mut fired = Vec::new();
+            let mut now = 0;
+
+            for i in 0..16 * 16 * TICKS_PER_SPP {
+                let at = (i as f64 * tick).round() as SampleTime;
+                fired.extend(note_ons(render(&mut seq, (at - now) as usize, 256)));
+                now = at;
+                seq.clock_tick(at);
+            }
+
+            // each step on its sixth tick, give or take a few samples, however far in
+            assert_eq!(fired.len(), 16 * 16);
+
+            for (step, (at, _, _)) in fired.into_iter().enumerate() {
+                let expected = (step * TICKS_PER_SPP) as f64 * tick;
+                assert!(
+                    (at as f64 - expected).abs() <= 8.0,
+                    "{bpm} bpm: step {step} fired on {at}, its tick came on {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn following_clock_while_stopped() {
+        let mut seq = Sequencer::new(120.0, 4);
+        seq.set_clock_mode(ClockMode::Follow);
+        seq.play_track(track_with(&[(0, 48, None), (8, 50, None)]));
+        seq.stop();
+
+        // a song position then continue, as drum machines do when started part way through
+        seq.clock_input(ClockMsg::SongPosition(8));
+        assert!(seq.is_paused());
+        seq.clock_input(ClockMsg::Continue);
+        assert_eq!(
+            note_ons(render(&mut seq, 1, 1)),
+            vec![(0, 50, DEFAULT_VELOCITY)]
+        );
+
+        // and start, from the top
+        seq.stop();
+        seq.clock_input(ClockMsg::Start);
+        assert!(seq.is_playing());
+        assert_eq!(
+            note_ons(render(&mut seq, 1, 1)),
+            vec![(0, 48, DEFAULT_VELOCITY)]
+        );
+    }
+
+    #[test]
+    fn following_clock_into_a_loop_range() {
+        let mut seq = Sequencer::new(120.0, 4);
+        let sps = seq.samples_per_step();
+        seq.set_clock_mode(ClockMode::Follow);
+        seq.set_loop_range(Some(4..6));
+        seq.play_track(track_with(&[(0, 36, None), (4, 48, None), (5, 50, None)]));
+
+        // start cues the top of the song, which is before the range
+        seq.clock_input(ClockMsg::Start);
+        assert_eq!(
+            note_ons(render(&mut seq, (sps * 2.5) as usize, 64))
+                .into_iter()
+                .map(|(_, note, _)| note)
+                .collect::<Vec<_>>(),
+            vec![48, 50, 48]
+        );
+
+        // and so does a song position before it
+        seq.clock_input(ClockMsg::SongPosition(1));
+        seq.clock_input(ClockMsg::Continue);
+        assert_eq!(
+            note_ons(render(&mut seq, 1, 1)),
+            vec![(0, 48, DEFAULT_VELOCITY)]
+        );
+        assert_eq!(seq.playhead(), Some(Playhead { block: 0, row: 4 }));
+    }
+}
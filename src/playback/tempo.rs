@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+
+pub const MIN_BPM: usize = 20;
+pub const MAX_BPM: usize = 999;
+/// taps further apart than this start counting again.
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// how many taps the tempo is averaged over.
+const TAPS: usize = 5;
+
+/// works out a tempo from a button being tapped along to the beat.
+#[derive(Clone, Debug, Default)]
+pub struct TapTempo {
+    taps: Vec<Instant>,
+}
+
+impl TapTempo {
+    /// tap at `now`. returns the tempo once there's been more than one tap, rounded to a tenth of
+    /// a beat per minute.
+    pub fn tap(&mut self, now: Instant) -> Option<f64> {
+        if self
+            .taps
+            .last()
+            .is_some_and(|last| now.saturating_duration_since(*last) > TAP_TIMEOUT)
+        {
+            self.taps.clear();
+        }
+
+        self.taps.push(now);
+
+        if self.taps.len() > TAPS {
+            self.taps.remove(0);
+        }
+
+        let (first, last) = (self.taps.first()?, self.taps.last()?);
+        let beat =
+            last.saturating_duration_since(*first).as_secs_f64() / (self.taps.len() - 1) as f64;
+
+        (beat > 0.0).then(|| {
+            let bpm = (60.0 / beat).clamp(MIN_BPM as f64, MAX_BPM as f64);
+
+            (bpm * 10.0).round() / 10.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tapping() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut tap = TapTempo::default();
+
+        assert_eq!(tap.tap(ms(0)), None);
+        assert_eq!(tap.tap(ms(500)), Some(120.0));
+        // averaged over the taps so far
+        assert_eq!(tap.tap(ms(1100)), Some(109.1));
+
+        // a long gap starts again
+        assert_eq!(tap.tap(ms(5000)), None);
+        assert_eq!(tap.tap(ms(5400)), Some(150.0));
+
+        // only the last few taps count
+        let mut tap = TapTempo::default();
+        for i in 0..3 {
+            tap.tap(ms(i * 1000));
+        }
+        let bpm = (0..6).map(|i| tap.tap(ms(3000 + i * 250))).last().flatten();
+        assert_eq!(bpm, Some(240.0));
+    }
+}
@@ -0,0 +1,683 @@
+use crate::{
+    playback::{DEFAULT_BPM, DEFAULT_LPB},
+    synth::{InstrumentConf, mixer::Mixer},
+    tracks::{Pattern, Song, Track},
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// bumped every time the layout of `Project` changes.
+///
+/// - 2: added `mixer`.
+/// - 3: sections have their own length, `lpb` and `beats`.
+/// - 4: sections have `swing` and a `groove`, and there's a tempo command.
+/// - 5: instruments can play a SoundFont, saved as the file and preset.
+/// - 6: instruments can be samplers, saved as their pads, and there's a sample offset command.
+pub const PROJECT_VERSION: u32 = 6;
+/// the first bytes of a binary project file.
+const BINARY_MAGIC: &[u8; 4] = b"TSYN";
+
+/// everything needed to bring a song back after the app closes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub bpm: f64,
+    pub lpb: usize,
+    pub sections: Vec<Track>,
+    pub patterns: Vec<Pattern>,
+    pub song: Song,
+    pub instruments: Vec<InstrumentConf>,
+    #[serde(default)]
+    pub mixer: Mixer,
+}
+
+impl Default for Project {
+    fn default() -> Self {
+        Self {
+            version: PROJECT_VERSION,
+            bpm: DEFAULT_BPM,
+            lpb: DEFAULT_LPB,
+            sections: Vec::new(),
+            patterns: Vec::new(),
+            song: Song::default(),
+            instruments: Vec::new(),
+            mixer: Mixer::default(),
+        }
+    }
+}
+
+/// how a project is stored on disk, picked from the file extension.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ProjectFormat {
+    /// human readable, good for diffing and hand editing. (`.ron`)
+    Ron,
+    /// compact, for when the project is big. (anything else, `.tsp` by convention)
+    Binary,
+}
+
+impl ProjectFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("ron") => Self::Ron,
+            _ => Self::Binary,
+        }
+    }
+}
+
+impl Project {
+    pub fn to_ron(&self) -> Result<String, String> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| format!("failed to write project as RON: {e}"))
+    }
+
+    pub fn from_ron(text: &str) -> Result<Self, String> {
+        let project: Self =
+            ron::from_str(text).map_err(|e| format!("failed to read RON project: {e}"))?;
+
+        project.check_version()
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = BINARY_MAGIC.to_vec();
+        bytes.extend(self.version.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self)
+            .map_err(|e| format!("failed to write binary project: {e}"))?;
+
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let Some(body) = bytes.strip_prefix(BINARY_MAGIC) else {
+            return Err("not a tracker-synth project file".into());
+        };
+        let Some((version, body)) = body.split_first_chunk::<4>() else {
+            return Err("project file is truncated".into());
+        };
+        let version = u32::from_le_bytes(*version);
+
+        // bincode has no field names, so each older layout needs a decoder of its own.
+        let project: Self = match version {
+            PROJECT_VERSION => bincode::deserialize(body)
+                .map_err(|e| format!("failed to read binary project: {e}"))?,
+            version if version < PROJECT_VERSION => legacy::decode(version, body)?,
+            version => {
+                return Err(format!(
+                    "project was saved by a newer version of tracker-synth (version {version}, this is {PROJECT_VERSION})"
+                ));
+            }
+        };
+
+        project.check_version()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let bytes = match ProjectFormat::from_path(path) {
+            ProjectFormat::Ron => self.to_ron()?.into_bytes(),
+            ProjectFormat::Binary => self.to_bytes()?,
+        };
+
+        fs::write(path, bytes).map_err(|e| format!("failed to write {}: {e}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+
+        match ProjectFormat::from_path(path) {
+            ProjectFormat::Ron => Self::from_ron(
+                std::str::from_utf8(&bytes).map_err(|e| format!("{} is not text: {e}", path.display()))?,
+            ),
+            ProjectFormat::Binary => Self::from_bytes(&bytes),
+        }
+    }
+
+    fn check_version(self) -> Result<Self, String> {
+        if self.version > PROJECT_VERSION {
+            Err(format!(
+                "project was saved by a newer version of tracker-synth (version {}, this is {PROJECT_VERSION})",
+                self.version
+            ))
+        } else {
+            for section in self.sections.iter() {
+                section.check()?;
+            }
+
+            Ok(Self {
+                version: PROJECT_VERSION,
+                ..self
+            })
+        }
+    }
+}
+
+/// the layouts binary projects had before `PROJECT_VERSION`, and how to bring them up to date.
+/// fields were only ever added at the end, so most of a layout is the current type.
+mod legacy {
+    use super::Project;
+    use crate::{
+        MAX_STEPS,
+        less_then::UsizeLessThan,
+        playback::DEFAULT_LPB,
+        synth::{EngineKind, InstrumentConf, Sf2Conf, mixer::Mixer},
+        tracks::{DEFAULT_BEATS, MidiNote, Pattern, Sf2Cmd, Song, Step, Track, TrackerCmd},
+    };
+    use midi_control::Channel;
+    use serde::{Deserialize, de::DeserializeOwned};
+
+    /// `TrackerCmd` before version 4 added `Tempo` in front of `Custom`.
+    #[derive(Deserialize)]
+    enum CmdV1 {
+        None,
+        Chord {
+            chord: Vec<i8>,
+        },
+        Roll {
+            times: UsizeLessThan<{ MAX_STEPS * 2 - 1 }>,
+        },
+        Repeat {
+            times: UsizeLessThan<{ MAX_STEPS - 1 }>,
+        },
+        HoldFor {
+            notes: UsizeLessThan<{ MAX_STEPS }>,
+        },
+        Panic,
+        MidiCmd {
+            cc_param: u8,
+            arg: u8,
+        },
+        Custom(Sf2Cmd),
+    }
+
+    impl From<CmdV1> for TrackerCmd {
+        fn from(cmd: CmdV1) -> Self {
+            match cmd {
+                CmdV1::None => Self::None,
+                CmdV1::Chord { chord } => Self::Chord { chord },
+                CmdV1::Roll { times } => Self::Roll { times },
+                CmdV1::Repeat { times } => Self::Repeat { times },
+                CmdV1::HoldFor { notes } => Self::HoldFor { notes },
+                CmdV1::Panic => Self::Panic,
+                CmdV1::MidiCmd { cc_param, arg } => Self::MidiCmd { cc_param, arg },
+                CmdV1::Custom(cmd) => Self::Custom(cmd),
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct StepV1 {
+        note: Option<MidiNote>,
+        velocity: Option<u8>,
+        cmds: (CmdV1, CmdV1),
+    }
+
+    impl From<StepV1> for Step {
+        fn from(step: StepV1) -> Self {
+            Self {
+                note: step.note,
+                velocity: step.velocity,
+                cmds: (step.cmds.0.into(), step.cmds.1.into()),
+            }
+        }
+    }
+
+    /// a `Track` from versions 1 and 2, before it had a grid of its own.
+    #[derive(Deserialize)]
+    struct TrackV1 {
+        steps: Vec<StepV1>,
+        dev: String,
+        #[serde(with = "crate::tracks::channel_serde")]
+        chan: Channel,
+        name: String,
+        uuid: usize,
+    }
+
+    impl From<TrackV1> for Track {
+        fn from(track: TrackV1) -> Self {
+            TrackV3 {
+                steps: track.steps,
+                dev: track.dev,
+                chan: track.chan,
+                name: track.name,
+                uuid: track.uuid,
+                lpb: DEFAULT_LPB,
+                beats: DEFAULT_BEATS,
+            }
+            .into()
+        }
+    }
+
+    /// a `Track` from version 3, before swing and grooves.
+    #[derive(Deserialize)]
+    struct TrackV3 {
+        steps: Vec<StepV1>,
+        dev: String,
+        #[serde(with = "crate::tracks::channel_serde")]
+        chan: Channel,
+        name: String,
+        uuid: usize,
+        lpb: usize,
+        beats: usize,
+    }
+
+    impl From<TrackV3> for Track {
+        fn from(track: TrackV3) -> Self {
+            Self {
+                steps: track.steps.into_iter().map(Step::from).collect(),
+                dev: track.dev,
+                chan: track.chan,
+                name: track.name,
+                uuid: track.uuid,
+                lpb: track.lpb,
+                beats: track.beats,
+                ..Track::default()
+            }
+        }
+    }
+
+    /// an `InstrumentConf` from before version 5, when every instrument was a wave table.
+    #[derive(Deserialize)]
+    struct InstrumentV1 {
+        id: String,
+        engine: EngineKind,
+    }
+
+    impl From<InstrumentV1> for InstrumentConf {
+        fn from(conf: InstrumentV1) -> Self {
+            Self {
+                id: conf.id,
+                engine: conf.engine,
+                sf2: None,
+                sampler: None,
+            }
+        }
+    }
+
+    /// an `InstrumentConf` from version 5, before samplers.
+    #[derive(Deserialize)]
+    struct InstrumentV5 {
+        id: String,
+        engine: EngineKind,
+        sf2: Option<Sf2Conf>,
+    }
+
+    impl From<InstrumentV5> for InstrumentConf {
+        fn from(conf: InstrumentV5) -> Self {
+            Self {
+                id: conf.id,
+                engine: conf.engine,
+                sf2: conf.sf2,
+                sampler: None,
+            }
+        }
+    }
+
+    /// a `Project` from version 1, before the mixer.
+    #[derive(Deserialize)]
+    struct ProjectV1 {
+        version: u32,
+        bpm: f64,
+        lpb: usize,
+        sections: Vec<TrackV1>,
+        patterns: Vec<Pattern>,
+        song: Song,
+        instruments: Vec<InstrumentV1>,
+    }
+
+    impl From<ProjectV1> for Project {
+        fn from(project: ProjectV1) -> Self {
+            ProjectV2 {
+                version: project.version,
+                bpm: project.bpm,
+                lpb: project.lpb,
+                sections: project.sections,
+                patterns: project.patterns,
+                song: project.song,
+                instruments: project.instruments,
+                mixer: Mixer::default(),
+            }
+            .into()
+        }
+    }
+
+    /// a `Project` from versions 2 to 5, with whatever its tracks and instruments looked like then.
+    #[derive(Deserialize)]
+    struct ProjectV2<T, I> {
+        version: u32,
+        bpm: f64,
+        lpb: usize,
+        sections: Vec<T>,
+        patterns: Vec<Pattern>,
+        song: Song,
+        instruments: Vec<I>,
+        mixer: Mixer,
+    }
+
+    impl<T: Into<Track>, I: Into<InstrumentConf>> From<ProjectV2<T, I>> for Project {
+        fn from(project: ProjectV2<T, I>) -> Self {
+            Self {
+                version: project.version,
+                bpm: project.bpm,
+                lpb: project.lpb,
+                sections: project.sections.into_iter().map(Into::into).collect(),
+                patterns: project.patterns,
+                song: project.song,
+                instruments: project.instruments.into_iter().map(Into::into).collect(),
+                mixer: project.mixer,
+            }
+        }
+    }
+
+    fn read<T: DeserializeOwned + Into<Project>>(
+        body: &[u8],
+        version: u32,
+    ) -> Result<Project, String> {
+        bincode::deserialize::<T>(body)
+            .map(Into::into)
+            .map_err(|e| format!("failed to read version {version} binary project: {e}"))
+    }
+
+    /// decode the body of a binary project saved as `version`.
+    pub fn decode(version: u32, body: &[u8]) -> Result<Project, String> {
+        match version {
+            1 => read::<ProjectV1>(body, version),
+            2 => read::<ProjectV2<TrackV1, InstrumentV1>>(body, version),
+            3 => read::<ProjectV2<TrackV3, InstrumentV1>>(body, version),
+            4 => read::<ProjectV2<Track, InstrumentV1>>(body, version),
+            5 => read::<ProjectV2<Track, InstrumentV5>>(body, version),
+            _ => Err(format!(
+                "project file is version {version}, which never existed"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        less_then::UsizeLessThan,
+        playback::groove::Groove,
+        synth::{
+            EngineKind, Sf2Conf,
+            sampler::{Pad, SamplerConf, SamplerMode},
+        },
+        tracks::{Sf2Cmd, Step, TrackerCmd},
+    };
+    use midi_control::Channel;
+
+    /// a project with every `TrackerCmd` (and `Sf2Cmd`) variant in it somewhere.
+    fn every_cmd_project() -> Project {
+        let cmds = [
+            TrackerCmd::None,
+            TrackerCmd::Chord {
+                chord: vec![4, 7, -12],
+            },
+            TrackerCmd::Roll {
+                times: UsizeLessThan(3),
+            },
+            TrackerCmd::Repeat {
+                times: UsizeLessThan(126),
+            },
+            TrackerCmd::HoldFor {
+                notes: UsizeLessThan(127),
+            },
+            TrackerCmd::Panic,
+            TrackerCmd::MidiCmd {
+                cc_param: 74,
+                arg: 127,
+            },
+            TrackerCmd::Tempo { bpm: 140 },
+            TrackerCmd::Custom(Sf2Cmd::Atk(10)),
+            TrackerCmd::Custom(Sf2Cmd::Dcy(20)),
+            TrackerCmd::Custom(Sf2Cmd::Dcy2(30)),
+            TrackerCmd::Custom(Sf2Cmd::Sus(40)),
+            TrackerCmd::Custom(Sf2Cmd::Rel(50)),
+            TrackerCmd::Custom(Sf2Cmd::Volume(0.5)),
+            TrackerCmd::Custom(Sf2Cmd::Offset(60)),
+        ];
+        let mut lead = Track::new(Some("Lead".into()), 0, "Default".into());
+        let mut bass = Track::new(None, 1, "Bass".into());
+        bass.chan = Channel::Ch16;
+        bass.set_len(48);
+        bass.lpb = 3;
+        bass.beats = 3;
+        bass.swing = 66;
+        bass.groove = Groove::Human;
+
+        for (i, cmd) in cmds.iter().enumerate() {
+            lead.steps[i].note = Some(i as u8 + 40);
+            lead.steps[i].velocity = Some(i as u8 * 9);
+            lead.steps[i].cmds = (cmd.clone(), cmds[cmds.len() - 1 - i].clone());
+        }
+
+        let mut pattern = Pattern::new(None, 0);
+        pattern.columns = vec![Some(0), None, Some(1)];
+
+        Project {
+            bpm: 97.5,
+            lpb: 3,
+            sections: vec![lead, bass],
+            patterns: vec![pattern],
+            song: Song {
+                chain: vec![0, 0],
+            },
+            instruments: vec![
+                InstrumentConf {
+                    id: "Default".into(),
+                    engine: EngineKind::WaveTable,
+                    sf2: None,
+                    sampler: None,
+                },
+                InstrumentConf {
+                    id: "Bass".into(),
+                    engine: EngineKind::Sf2,
+                    sf2: Some(Sf2Conf {
+                        path: "test-data/tiny.sf2".into(),
+                        bank: 128,
+                        preset: 0,
+                    }),
+                    sampler: None,
+                },
+                InstrumentConf {
+                    id: "Drums".into(),
+                    engine: EngineKind::Sampler,
+                    sf2: None,
+                    sampler: Some(SamplerConf {
+                        mode: SamplerMode::Kit,
+                        pads: vec![
+                            Pad::new("kick.wav".into(), 36),
+                            Pad {
+                                start: 10,
+                                end: Some(2000),
+                                looping: Some(100..1000),
+                                choke: Some(1),
+                                gain: 0.5,
+                                ..Pad::new("hats/open.wav".into(), 46)
+                            },
+                        ],
+                    }),
+                },
+            ],
+            mixer: {
+                let mut mixer = Mixer::default();
+                mixer.strip_mut("Bass").pan = -0.5;
+                mixer.strip_mut("Default").mute = true;
+                mixer.master = 0.75;
+
+                mixer
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        let project = every_cmd_project();
+        let text = project.to_ron().unwrap();
+
+        assert_eq!(Project::from_ron(&text), Ok(project));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let project = every_cmd_project();
+        let bytes = project.to_bytes().unwrap();
+
+        assert!(bytes.starts_with(BINARY_MAGIC));
+        assert_eq!(Project::from_bytes(&bytes), Ok(project));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let project = every_cmd_project();
+        let dir = std::env::temp_dir();
+
+        for name in ["tracker-synth-test.ron", "tracker-synth-test.tsp"] {
+            let path = dir.join(name);
+            project.save(&path).unwrap();
+            let loaded = Project::load(&path);
+            let _ = fs::remove_file(&path);
+
+            assert_eq!(loaded, Ok(project.clone()), "{name}");
+        }
+    }
+
+    #[test]
+    fn version_1_ron_loads() {
+        let text = "(version: 1, bpm: 120.0, lpb: 4, sections: [], patterns: [], song: (chain: []), instruments: [])";
+        let project = Project::from_ron(text).unwrap();
+
+        assert_eq!(project.version, PROJECT_VERSION);
+        assert_eq!(project.mixer, Mixer::default());
+    }
+
+    #[test]
+    fn version_1_binary_loads() {
+        // written out by hand the way bincode laid out a version 1 project
+        let mut bytes = BINARY_MAGIC.to_vec();
+        let put_u32 = |bytes: &mut Vec<u8>, n: u32| bytes.extend(n.to_le_bytes());
+        let put_u64 = |bytes: &mut Vec<u8>, n: u64| bytes.extend(n.to_le_bytes());
+        let put_str = |bytes: &mut Vec<u8>, text: &str| {
+            bytes.extend((text.len() as u64).to_le_bytes());
+            bytes.extend(text.as_bytes());
+        };
+        put_u32(&mut bytes, 1);
+        put_u32(&mut bytes, 1);
+        bytes.extend(97.5f64.to_le_bytes());
+        put_u64(&mut bytes, 3);
+        // one section, two steps long
+        put_u64(&mut bytes, 1);
+        put_u64(&mut bytes, 2);
+        // C-4 at 100, with the volume command, which was the 8th command before `Tempo`
+        bytes.extend([1, 60, 1, 100]);
+        put_u32(&mut bytes, 7);
+        put_u32(&mut bytes, 5);
+        bytes.extend(0.5f32.to_le_bytes());
+        put_u32(&mut bytes, 0);
+        // an empty step with a chord
+        bytes.extend([0, 0]);
+        put_u32(&mut bytes, 1);
+        put_u64(&mut bytes, 2);
+        bytes.extend([4, 7]);
+        put_u32(&mut bytes, 0);
+        put_str(&mut bytes, "Default");
+        bytes.push(10);
+        put_str(&mut bytes, "Lead");
+        put_u64(&mut bytes, 3);
+        // one pattern, playing it, played once
+        put_u64(&mut bytes, 1);
+        put_str(&mut bytes, "PATTERN-0");
+        put_u64(&mut bytes, 0);
+        put_u64(&mut bytes, 1);
+        bytes.push(1);
+        put_u64(&mut bytes, 3);
+        put_u64(&mut bytes, 1);
+        put_u64(&mut bytes, 0);
+        // one wave table
+        put_u64(&mut bytes, 1);
+        put_str(&mut bytes, "Default");
+        put_u32(&mut bytes, 0);
+
+        let project = Project::from_bytes(&bytes).unwrap();
+        let section = &project.sections[0];
+
+        assert_eq!(project.version, PROJECT_VERSION);
+        assert_eq!((project.bpm, project.lpb), (97.5, 3));
+        assert_eq!(
+            (section.name.as_str(), section.uuid, section.chan),
+            ("Lead", 3, Channel::Ch10)
+        );
+        assert_eq!((section.lpb, section.beats, section.swing), (4, 4, 0));
+        assert_eq!(
+            section.steps[0],
+            Step {
+                note: Some(60),
+                velocity: Some(100),
+                cmds: (TrackerCmd::Custom(Sf2Cmd::Volume(0.5)), TrackerCmd::None),
+            }
+        );
+        assert_eq!(
+            section.steps[1].cmds.0,
+            TrackerCmd::Chord { chord: vec![4, 7] }
+        );
+        assert_eq!(project.patterns[0].columns, vec![Some(3)]);
+        assert_eq!(project.song.chain, vec![0]);
+        assert_eq!(project.instruments[0].engine, EngineKind::WaveTable);
+        assert_eq!(project.mixer, Mixer::default());
+
+        // and every version that came after has a decoder
+        for version in 1..PROJECT_VERSION {
+            assert!(
+                !legacy::decode(version, &[])
+                    .unwrap_err()
+                    .contains("never existed")
+            );
+        }
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let project = Project {
+            version: PROJECT_VERSION + 1,
+            ..Default::default()
+        };
+
+        assert!(Project::from_ron(&project.to_ron().unwrap()).is_err());
+        assert!(Project::from_bytes(&project.to_bytes().unwrap()).is_err());
+    }
+
+    #[test]
+    fn out_of_range_args_are_rejected() {
+        let mut project = every_cmd_project();
+        project.sections[0].steps[0].cmds.0 = TrackerCmd::HoldFor {
+            notes: UsizeLessThan(1000),
+        };
+
+        assert!(Project::from_ron(&project.to_ron().unwrap()).is_err());
+    }
+
+    #[test]
+    fn args_past_the_end_of_a_section_are_rejected() {
+        let mut project = every_cmd_project();
+        project.sections[1].set_len(16);
+        project.sections[1].steps[0].cmds.0 = TrackerCmd::HoldFor {
+            notes: UsizeLessThan(16),
+        };
+
+        assert!(Project::from_ron(&project.to_ron().unwrap()).is_err());
+        assert!(Project::from_bytes(&project.to_bytes().unwrap()).is_err());
+    }
+
+    #[test]
+    fn tempos_out_of_range_are_rejected() {
+        let mut project = every_cmd_project();
+        project.sections[0].steps[0].cmds.0 = TrackerCmd::Tempo { bpm: 5 };
+
+        assert!(Project::from_ron(&project.to_ron().unwrap()).is_err());
+    }
+
+    #[test]
+    fn not_a_project() {
+        assert!(Project::from_bytes(b"RIFF....WAVE").is_err());
+        assert!(Project::from_bytes(b"TSYN").is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{
+    playback::Playhead,
+    tracks::{MidiNote, Step, TrackerCmd},
+};
+use std::time::{Duration, Instant};
+use strum::EnumIter;
+
+/// notes that start this close to the first note of a chord are part of it.
+pub const CHORD_WINDOW: Duration = Duration::from_millis(50);
+/// the furthest step record can move on after a note.
+pub const MAX_EDIT_STEP: usize = 16;
+/// the coarsest grid live takes can snap to, in rows.
+pub const MAX_QUANTIZE: usize = 16;
+
+/// where recorded notes go.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumIter, strum_macros::Display)]
+pub enum RecordMode {
+    /// where the cursor is, which then moves on by the edit step.
+    #[default]
+    #[strum(to_string = "STEP")]
+    Step,
+    /// where the sequencer is as it plays, snapped to the grid.
+    #[strum(to_string = "LIVE")]
+    Live,
+}
+
+/// a note that started on a keyboard, and where the sequencer was when it did.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NotePlayed {
+    pub note: MidiNote,
+    pub velocity: u8,
+    pub at: Instant,
+    /// the step that fired last and how far through it playback was, from 0.0 to 1.0. `None`
+    /// when nothing is playing.
+    pub position: Option<(Playhead, f64)>,
+}
+
+/// a recorded note or chord, and the row it goes on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Take {
+    pub row: usize,
+    /// the lowest note played.
+    pub root: MidiNote,
+    /// the velocity of the first note played.
+    pub velocity: u8,
+    /// the rest of the notes, in semi-tones above the root.
+    pub chord: Vec<i8>,
+}
+
+impl Take {
+    /// write the take over `step`. a chord goes in the command slot that already has one, or the
+    /// first empty one, and a single note takes away a chord that was there.
+    pub fn write(&self, step: &mut Step) {
+        let is_chord = |cmd: &TrackerCmd| matches!(cmd, TrackerCmd::Chord { .. });
+        step.note = Some(self.root);
+        step.velocity = Some(self.velocity);
+
+        for cmd in [&mut step.cmds.0, &mut step.cmds.1] {
+            if is_chord(cmd) {
+                *cmd = TrackerCmd::None;
+            }
+        }
+
+        if self.chord.is_empty() {
+            return;
+        }
+
+        let chord = TrackerCmd::Chord {
+            chord: self.chord.clone(),
+        };
+
+        if step.cmds.0 == TrackerCmd::None || step.cmds.1 != TrackerCmd::None {
+            step.cmds.0 = chord;
+        } else {
+            step.cmds.1 = chord;
+        }
+    }
+}
+
+/// the notes of the chord being played, from the first one on.
+#[derive(Clone, Debug, PartialEq)]
+struct Chord {
+    started: Instant,
+    row: usize,
+    velocity: u8,
+    notes: Vec<MidiNote>,
+}
+
+impl Chord {
+    fn take(&self) -> Take {
+        let root = self.notes.iter().copied().min().unwrap_or_default();
+        let mut chord: Vec<i8> = self
+            .notes
+            .iter()
+            .filter(|note| **note != root)
+            .map(|note| (note - root) as i8)
+            .collect();
+        chord.sort();
+        chord.dedup();
+
+        Take {
+            row: self.row,
+            root,
+            velocity: self.velocity,
+            chord,
+        }
+    }
+}
+
+/// turns notes played on a keyboard into steps of a track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recorder {
+    pub mode: RecordMode,
+    /// how many rows the cursor moves on after each note, in step mode.
+    pub edit_step: usize,
+    /// the grid live takes snap to, in rows.
+    pub quantize: usize,
+    armed: bool,
+    chord: Option<Chord>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self {
+            mode: RecordMode::default(),
+            edit_step: 1,
+            quantize: 1,
+            armed: false,
+            chord: None,
+        }
+    }
+}
+
+impl Recorder {
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    pub fn arm(&mut self, armed: bool) {
+        self.armed = armed;
+        self.chord = None;
+    }
+
+    /// what `played` records into a track `len` rows long, with the cursor on row `cursor`. notes
+    /// of a chord each come back with the whole chord so far, on the row of its first note.
+    /// `None` when not armed, or for a live take while nothing is playing.
+    pub fn record(&mut self, played: &NotePlayed, cursor: usize, len: usize) -> Option<Take> {
+        if !self.armed {
+            return None;
+        }
+
+        if let Some(chord) = self.chord.as_mut()
+            && played.at.saturating_duration_since(chord.started) <= CHORD_WINDOW
+        {
+            chord.notes.push(played.note);
+
+            return Some(chord.take());
+        }
+
+        let len = len.max(1);
+        let row = match self.mode {
+            RecordMode::Step => cursor % len,
+            RecordMode::Live => {
+                let (playhead, phase) = played.position?;
+                let grid = self.quantize.clamp(1, MAX_QUANTIZE) as f64;
+
+                ((playhead.row as f64 + phase) / grid).round() as usize * grid as usize % len
+            }
+        };
+        let chord = Chord {
+            started: played.at,
+            row,
+            velocity: played.velocity,
+            notes: vec![played.note],
+        };
+        let take = chord.take();
+        self.chord = Some(chord);
+
+        Some(take)
+    }
+
+    /// where step record leaves the cursor after a take on `row`, wrapping round like the cursor
+    /// does.
+    pub fn next_row(&self, row: usize, len: usize) -> usize {
+        (row + self.edit_step.min(MAX_EDIT_STEP)) % len.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn played(note: MidiNote, at: Instant) -> NotePlayed {
+        NotePlayed {
+            note,
+            velocity: 100,
+            at,
+            position: None,
+        }
+    }
+
+    fn armed(mode: RecordMode) -> Recorder {
+        let mut recorder = Recorder {
+            mode,
+            ..Recorder::default()
+        };
+        recorder.arm(true);
+
+        recorder
+    }
+
+    #[test]
+    fn step_record() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut recorder = Recorder::default();
+        assert_eq!(recorder.record(&played(60, ms(0)), 0, 16), None);
+
+        recorder.arm(true);
+        recorder.edit_step = 2;
+        let take = recorder.record(&played(60, ms(0)), 3, 16).unwrap();
+        assert_eq!(
+            take,
+            Take {
+                row: 3,
+                root: 60,
+                velocity: 100,
+                chord: vec![]
+            }
+        );
+        assert_eq!(recorder.next_row(take.row, 16), 5);
+
+        // the next note is a new take, and the cursor wraps round
+        let take = recorder.record(&played(62, ms(500)), 15, 16).unwrap();
+        assert_eq!((take.row, take.root), (15, 62));
+        assert_eq!(recorder.next_row(take.row, 16), 1);
+    }
+
+    #[test]
+    fn chords() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut recorder = armed(RecordMode::Step);
+
+        recorder.record(&played(64, ms(0)), 0, 16);
+        recorder.record(&played(67, ms(20)), 1, 16);
+        // a lower note makes it the root, and doubled notes only count once
+        recorder.record(&played(60, ms(30)), 1, 16);
+        let take = recorder.record(&played(67, ms(50)), 1, 16).unwrap();
+        assert_eq!(
+            take,
+            Take {
+                row: 0,
+                root: 60,
+                velocity: 100,
+                chord: vec![4, 7]
+            }
+        );
+
+        // too late to be part of it
+        let take = recorder.record(&played(72, ms(51)), 1, 16).unwrap();
+        assert_eq!((take.row, take.root, take.chord), (1, 72, vec![]));
+    }
+
+    #[test]
+    fn live_record() {
+        let start = Instant::now();
+        let mut recorder = armed(RecordMode::Live);
+        let at = |row, phase| NotePlayed {
+            position: Some((Playhead { block: 0, row }, phase)),
+            ..played(60, start)
+        };
+
+        // nothing to go on
+        assert_eq!(recorder.record(&played(60, start), 0, 16), None);
+
+        // snapped to the nearest row, whatever the cursor says
+        let mut row = |row, phase| {
+            recorder.arm(true);
+            recorder.record(&at(row, phase), 9, 16).unwrap().row
+        };
+        assert_eq!(row(4, 0.3), 4);
+        assert_eq!(row(4, 0.6), 5);
+        // the block can be longer than the track
+        assert_eq!(row(15, 0.9), 0);
+        assert_eq!(row(20, 0.0), 4);
+
+        // or to a coarser grid
+        recorder.quantize = 4;
+        let mut row = |row, phase| {
+            recorder.arm(true);
+            recorder.record(&at(row, phase), 9, 16).unwrap().row
+        };
+        assert_eq!(row(5, 0.0), 4);
+        assert_eq!(row(6, 0.0), 8);
+    }
+
+    #[test]
+    fn writing() {
+        let take = |chord: Vec<i8>| Take {
+            row: 0,
+            root: 48,
+            velocity: 90,
+            chord,
+        };
+        let roll = TrackerCmd::Roll {
+            times: crate::less_then::UsizeLessThan(1),
+        };
+
+        let mut step = Step::default();
+        take(vec![3, 7]).write(&mut step);
+        assert_eq!((step.note, step.velocity), (Some(48), Some(90)));
+        assert_eq!(step.cmds.0, TrackerCmd::Chord { chord: vec![3, 7] });
+
+        // other commands stay put
+        let mut step = Step {
+            cmds: (roll.clone(), TrackerCmd::None),
+            ..Step::default()
+        };
+        take(vec![4]).write(&mut step);
+        assert_eq!(
+            step.cmds,
+            (roll.clone(), TrackerCmd::Chord { chord: vec![4] })
+        );
+
+        // and a single note takes the chord away
+        take(vec![]).write(&mut step);
+        assert_eq!(step.cmds, (roll, TrackerCmd::None));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{SynthId, project::Project, synth::TabSynth};
+use hound::{WavSpec, WavWriter};
+use std::path::{Path, PathBuf};
+use stepper_synth_backend::SAMPLE_RATE;
+use strum::EnumIter;
+
+/// seconds rendered after the song ends, so release tails aren't cut off.
+pub const DEFAULT_TAIL: f64 = 2.0;
+
+/// the sample format of a bounced WAV.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, EnumIter, strum_macros::Display)]
+pub enum WavFormat {
+    #[default]
+    #[strum(to_string = "16 BIT")]
+    Int16,
+    #[strum(to_string = "24 BIT")]
+    Int24,
+    #[strum(to_string = "32 FLOAT")]
+    Float32,
+}
+
+impl WavFormat {
+    fn spec(&self) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            Self::Int16 => (16, hound::SampleFormat::Int),
+            Self::Int24 => (24, hound::SampleFormat::Int),
+            Self::Float32 => (32, hound::SampleFormat::Float),
+        };
+
+        WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE as u32,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+/// how to bounce a song.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounce {
+    pub format: WavFormat,
+    /// also write one file per instrument next to the full mix.
+    pub stems: bool,
+    /// seconds to keep rendering after the last step.
+    pub tail: f64,
+}
+
+impl Default for Bounce {
+    fn default() -> Self {
+        Self {
+            format: WavFormat::default(),
+            stems: false,
+            tail: DEFAULT_TAIL,
+        }
+    }
+}
+
+/// plays the song in `project` once through, faster than real time, and returns the (left,
+/// right) frames. with `solo` set only that instrument is rendered, ignoring the mixer's mute and
+/// solo settings.
+pub fn render(project: &Project, solo: Option<&str>, tail: f64) -> Vec<(f32, f32)> {
+    let synth = offline_synth(project, solo);
+    let mut frames = Vec::new();
+    let mut events = Vec::new();
+
+    synth.play_song(project.song.blocks(&project.patterns, &project.sections));
+
+    loop {
+        let frame = synth.next_sample(&mut events);
+
+        // the song ends on the sample where the step after the last one would have started.
+        if synth.is_finished() {
+            break;
+        }
+
+        frames.push(frame);
+    }
+
+    let tail = (tail.max(0.0) * SAMPLE_RATE as f64) as usize;
+    frames.extend((0..tail).map(|_| synth.next_sample(&mut events)));
+
+    frames
+}
+
+/// a synth set up like the one the project was saved from, with no audio device.
+fn offline_synth(project: &Project, solo: Option<&str>) -> TabSynth {
+    let synth = TabSynth::headless();
+    let mut mixer = project.mixer.clone();
+
+    if let Some(solo) = solo {
+        mixer.strips.values_mut().for_each(|strip| {
+            strip.mute = false;
+            strip.solo = false;
+        });
+        mixer.strip_mut(solo).solo = true;
+    }
+
+    synth.load_instruments(&project.instruments);
+    synth.set_mixer(mixer);
+    synth.set_tempo(project.bpm, project.lpb);
+    synth.set_looping(false);
+
+    synth
+}
+
+pub fn write_wav(path: &Path, frames: &[(f32, f32)], format: WavFormat) -> Result<(), String> {
+    let err = |e: hound::Error| format!("failed to write {}: {e}", path.display());
+    let mut writer = WavWriter::create(path, format.spec()).map_err(err)?;
+
+    for sample in frames.iter().flat_map(|(left, right)| [*left, *right]) {
+        let sample = sample.clamp(-1.0, 1.0);
+
+        match format {
+            WavFormat::Int16 => writer.write_sample((sample * i16::MAX as f32) as i16),
+            WavFormat::Int24 => writer.write_sample((sample * 8_388_607.0) as i32),
+            WavFormat::Float32 => writer.write_sample(sample),
+        }
+        .map_err(err)?;
+    }
+
+    writer.finalize().map_err(err)
+}
+
+/// render `project` to `path`, and each instrument to a stem next to it if asked. returns every
+/// file that was written.
+pub fn bounce(project: &Project, path: &Path, conf: &Bounce) -> Result<Vec<PathBuf>, String> {
+    let mut written = vec![path.to_path_buf()];
+    write_wav(path, &render(project, None, conf.tail), conf.format)?;
+
+    if conf.stems {
+        for instrument in project.instruments.iter() {
+            let stem = stem_path(path, &instrument.id);
+            write_wav(&stem, &render(project, Some(&instrument.id), conf.tail), conf.format)?;
+            written.push(stem);
+        }
+    }
+
+    Ok(written)
+}
+
+/// "song.wav" -> "song-<id>.wav"
+fn stem_path(path: &Path, id: &SynthId) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    path.with_file_name(format!("{stem}-{id}.wav"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        synth::{EngineKind, InstrumentConf},
+        tracks::{Pattern, Song, Track},
+    };
+    use hound::WavReader;
+    use std::fs;
+
+    /// a short, fast song with two instruments, so renders stay quick.
+    fn short_project() -> Project {
+        let mut lead = Track::new(None, 0, "Default".into());
+        let mut bass = Track::new(None, 1, "Bass".into());
+
+        for i in (0..lead.steps.len()).step_by(8) {
+            lead.steps[i].note = Some(60 + (i / 8) as u8 % 12);
+            bass.steps[i].note = Some(36);
+        }
+
+        let mut pattern = Pattern::new(None, 0);
+        pattern.columns = vec![Some(0), Some(1)];
+
+        Project {
+            bpm: 960.0,
+            sections: vec![lead, bass],
+            patterns: vec![pattern],
+            song: Song { chain: vec![0] },
+            instruments: ["Default", "Bass"]
+                .into_iter()
+                .map(|id| InstrumentConf {
+                    id: id.into(),
+                    engine: EngineKind::WaveTable,
+                    sf2: None,
+                    sampler: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// compares `frames` with the golden file `golden/<name>.wav`, which is checked in.
+    /// `UPDATE_GOLDEN=1` (`just golden`) writes it instead, for when a render is meant to change.
+    fn golden(name: &str, frames: &[(f32, f32)]) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(format!("{name}.wav"));
+
+        if std::env::var("UPDATE_GOLDEN").is_ok_and(|update| update == "1") {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write_wav(&path, frames, WavFormat::Float32).unwrap();
+            return;
+        }
+
+        assert!(
+            path.exists(),
+            "{} is missing, record it with UPDATE_GOLDEN=1 (`just golden`)",
+            path.display()
+        );
+
+        let expected: Vec<f32> = WavReader::open(&path)
+            .unwrap()
+            .into_samples::<f32>()
+            .map(Result::unwrap)
+            .collect();
+        let got: Vec<f32> = frames.iter().flat_map(|(l, r)| [*l, *r]).collect();
+
+        assert_eq!(got.len(), expected.len(), "{name} changed length");
+        assert!(
+            got.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-6),
+            "{name} doesn't match {}",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn renders_are_deterministic() {
+        let project = short_project();
+        let first = render(&project, None, 0.1);
+
+        assert_eq!(first, render(&project, None, 0.1));
+        assert!(first.iter().any(|(l, r)| *l != 0.0 || *r != 0.0));
+        golden("short-project", &first);
+    }
+
+    #[test]
+    fn song_plays_once_then_the_tail() {
+        let project = short_project();
+        let steps = Track::default().steps.len() as f64;
+        let song_len = crate::playback::samples_per_step(project.bpm, project.lpb) * steps;
+        let frames = render(&project, None, 0.5);
+
+        assert_eq!(
+            frames.len(),
+            song_len.round() as usize + SAMPLE_RATE as usize / 2
+        );
+    }
+
+    #[test]
+    fn stems_are_soloed() {
+        let mut project = short_project();
+        project.mixer.strip_mut("Bass").mute = true;
+        project.sections[0].steps.iter_mut().for_each(|step| step.note = None);
+
+        assert!(render(&project, Some("Default"), 0.1)
+            .iter()
+            .all(|frame| *frame == (0.0, 0.0)));
+        // muted in the mix, but its stem still plays.
+        assert!(render(&project, Some("Bass"), 0.1)
+            .iter()
+            .any(|frame| *frame != (0.0, 0.0)));
+        assert_eq!(
+            stem_path(Path::new("/tmp/song.wav"), &"Bass".into()),
+            PathBuf::from("/tmp/song-Bass.wav")
+        );
+    }
+
+    #[test]
+    fn wav_formats() {
+        let frames = vec![(0.5, -0.5), (1.5, -1.0), (0.0, 0.25)];
+        let dir = std::env::temp_dir();
+
+        for format in [WavFormat::Int16, WavFormat::Int24, WavFormat::Float32] {
+            let path = dir.join(format!("tracker-synth-test-{format}.wav"));
+            write_wav(&path, &frames, format).unwrap();
+            let mut reader = WavReader::open(&path).unwrap();
+            let spec = reader.spec();
+            let samples: Vec<f32> = match format {
+                WavFormat::Float32 => reader.samples::<f32>().map(Result::unwrap).collect(),
+                _ => {
+                    let full_scale = (1 << (spec.bits_per_sample - 1)) as f32 - 1.0;
+
+                    reader
+                        .samples::<i32>()
+                        .map(|sample| sample.unwrap() as f32 / full_scale)
+                        .collect()
+                }
+            };
+            let _ = fs::remove_file(&path);
+
+            assert_eq!(spec.channels, 2);
+            assert_eq!(spec.sample_rate, SAMPLE_RATE as u32);
+            assert!(
+                samples
+                    .iter()
+                    .zip([0.5, -0.5, 1.0, -1.0, 0.0, 0.25])
+                    .all(|(got, want)| (got - want).abs() < 1e-4),
+                "{format}: {samples:?}"
+            );
+        }
+    }
+}
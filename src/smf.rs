@@ -0,0 +1,576 @@
+use crate::{
+    MAX_STEPS, SynthId,
+    less_then::UsizeLessThan,
+    playback::{ALL_NOTES_OFF_CC, DEFAULT_VELOCITY, SeqEvent, SeqMsg, Sequencer},
+    tracks::{Track, TrackerCmd, channel_from_index, channel_index},
+};
+use midi_control::{Channel, ControlEvent, KeyEvent, MidiMessage};
+use midly::{
+    Format, Header, MetaMessage, Smf, Timing, TrackEvent, TrackEventKind,
+    num::{u4, u7, u15, u24, u28},
+};
+use std::{fs, path::Path};
+use stepper_synth_backend::SAMPLE_RATE;
+use tracing::*;
+
+/// midi ticks per tracker step. leaves room for rolls (half steps) and triplets.
+const TICKS_PER_STEP: u16 = 24;
+
+/// what was read out of a midi file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiImport {
+    /// the first tempo found in the file.
+    pub bpm: Option<f64>,
+    /// one per midi track that had a name or any events in it.
+    pub tracks: Vec<Track>,
+    /// how many notes and commands didn't fit in their section and were left out.
+    pub dropped: usize,
+}
+
+/// a note read from a midi file, in ticks.
+struct ImportedNote {
+    start: u64,
+    end: Option<u64>,
+    key: u8,
+    vel: u8,
+}
+
+/// write `tracks` as a type-1 standard midi file, one midi track per `Track` on its own channel.
+/// rolls, repeats, chords and holds are written out as the notes they play. steps without a
+/// velocity are written with `DEFAULT_VELOCITY`, the velocity they play at.
+pub fn export(tracks: &[Track], bpm: f64, lpb: usize) -> Result<Vec<u8>, String> {
+    let lpb = lpb.max(1);
+    let ppq = u16::try_from(TICKS_PER_STEP as usize * lpb)
+        .ok()
+        .and_then(u15::try_from)
+        .ok_or_else(|| format!("{lpb} lines per beat is too fine for a midi file"))?;
+    let tempo = (60_000_000.0 / bpm).round().clamp(1.0, 0xFF_FFFF as f64) as u32;
+    let meta = |msg| TrackEvent {
+        delta: u28::from(0),
+        kind: TrackEventKind::Meta(msg),
+    };
+
+    let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(ppq)));
+    smf.tracks.push(vec![
+        meta(MetaMessage::Tempo(u24::from(tempo))),
+        meta(MetaMessage::EndOfTrack),
+    ]);
+
+    for track in tracks {
+        let mut events = vec![meta(MetaMessage::TrackName(track.name.as_bytes()))];
+        let mut last = 0;
+
+        for (tick, msg) in track_events(track, bpm, lpb) {
+            let Some(kind) = to_midly(&msg) else {
+                continue;
+            };
+
+            events.push(TrackEvent {
+                delta: u28::from((tick - last) as u32),
+                kind,
+            });
+            last = tick;
+        }
+
+        // the track ends where the section does, so that trailing empty rows come back on import
+        let end = (track.steps.len() * TICKS_PER_STEP as usize * lpb).div_ceil(track.lpb.max(1)) as u64;
+        events.push(TrackEvent {
+            delta: u28::from(end.saturating_sub(last) as u32),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(events);
+    }
+
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)
+        .map_err(|e| format!("failed to write midi file: {e}"))?;
+
+    Ok(bytes)
+}
+
+/// read a midi file, quantizing it onto a grid of `lpb` lines per beat, which the new tracks step
+/// at. notes that start on the same step become a `Chord` (the first note is the root), and notes
+/// longer than a step get a `HoldFor`. each new track is as long as its midi track (at most
+/// `MAX_STEPS`), plays on `dev` and gets a uuid counting up from `first_uuid`. a velocity of
+/// `DEFAULT_VELOCITY` comes back as no velocity at all, since a file can't tell the two apart.
+pub fn import(
+    bytes: &[u8],
+    lpb: usize,
+    first_uuid: usize,
+    dev: &SynthId,
+) -> Result<MidiImport, String> {
+    let smf = Smf::parse(bytes).map_err(|e| format!("failed to read midi file: {e}"))?;
+    let Timing::Metrical(ppq) = smf.header.timing else {
+        return Err("midi files timed in SMPTE frames aren't supported".into());
+    };
+    let ticks_per_step = ppq.as_int() as f64 / lpb.max(1) as f64;
+    let to_step = |tick: u64| (tick as f64 / ticks_per_step).round() as usize;
+    let mut bpm = None;
+    let mut tracks = Vec::new();
+    let mut dropped = 0;
+
+    for events in smf.tracks.iter() {
+        let mut name = None;
+        let mut chan = None;
+        let mut notes: Vec<ImportedNote> = Vec::new();
+        // (channel, key, index into notes) of every note that hasn't been released yet.
+        let mut held: Vec<(u8, u8, usize)> = Vec::new();
+        let mut ccs: Vec<(usize, u8, u8)> = Vec::new();
+        let mut tick = 0;
+
+        for event in events {
+            tick += event.delta.as_int() as u64;
+
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) if bpm.is_none() => {
+                    bpm = Some(60_000_000.0 / tempo.as_int().max(1) as f64)
+                }
+                TrackEventKind::Meta(MetaMessage::TrackName(bytes)) => {
+                    name = Some(String::from_utf8_lossy(bytes).to_string())
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    let channel = channel.as_int();
+                    chan.get_or_insert(channel);
+
+                    match message {
+                        midly::MidiMessage::NoteOn { key, vel } if vel.as_int() > 0 => {
+                            notes.push(ImportedNote {
+                                start: tick,
+                                end: None,
+                                key: key.as_int(),
+                                vel: vel.as_int(),
+                            });
+                            held.push((channel, key.as_int(), notes.len() - 1));
+                        }
+                        midly::MidiMessage::NoteOn { key, .. }
+                        | midly::MidiMessage::NoteOff { key, .. } => {
+                            if let Some(i) = held
+                                .iter()
+                                .position(|(c, k, _)| *c == channel && *k == key.as_int())
+                            {
+                                let (_, _, note) = held.remove(i);
+                                notes[note].end = Some(tick);
+                            }
+                        }
+                        midly::MidiMessage::Controller { controller, value } => {
+                            ccs.push((to_step(tick), controller.as_int(), value.as_int()))
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if name.is_none() && notes.is_empty() && ccs.is_empty() {
+            continue;
+        }
+
+        let mut track = Track::new(name, first_uuid + tracks.len(), dev.clone());
+        track.chan = chan.and_then(channel_from_index).unwrap_or(Channel::Ch1);
+        track.lpb = lpb.max(1);
+        // long enough for the last thing in the track, which might be a note starting late in
+        // its step
+        let len = notes
+            .iter()
+            .map(|note| to_step(note.start) + 1)
+            .chain(ccs.iter().map(|(at, ..)| at + 1))
+            .fold((tick as f64 / ticks_per_step).ceil() as usize, usize::max);
+        track.set_len(len.clamp(1, MAX_STEPS));
+        let track_dropped = quantize(&mut track, &notes, &ccs, ticks_per_step);
+
+        if track_dropped > 0 {
+            warn!(
+                "{track_dropped} note(s) or command(s) in \"{}\" didn't fit on the grid and were dropped",
+                track.name
+            );
+        }
+
+        dropped += track_dropped;
+        tracks.push(track);
+    }
+
+    Ok(MidiImport {
+        bpm,
+        tracks,
+        dropped,
+    })
+}
+
+pub fn save_midi(path: &Path, tracks: &[Track], bpm: f64, lpb: usize) -> Result<(), String> {
+    fs::write(path, export(tracks, bpm, lpb)?)
+        .map_err(|e| format!("failed to write {}: {e}", path.display()))
+}
+
+pub fn load_midi(
+    path: &Path,
+    lpb: usize,
+    first_uuid: usize,
+    dev: &SynthId,
+) -> Result<MidiImport, String> {
+    let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+
+    import(&bytes, lpb, first_uuid, dev)
+}
+
+/// every midi message that playing `track` once through would send, at its time in ticks.
+fn track_events(track: &Track, bpm: f64, lpb: usize) -> Vec<(u64, MidiMessage)> {
+    let mut seq = Sequencer::new(bpm, lpb);
+    let sps = seq.samples_per_step();
+    let mut events = Vec::new();
+    let mut track = track.clone();
+    seq.set_looping(false);
+
+    // the file has one tempo for the whole song, so tempo changes would throw the ticks off.
+    for step in track.steps.iter_mut() {
+        for cmd in [&mut step.cmds.0, &mut step.cmds.1] {
+            if matches!(cmd, TrackerCmd::Tempo { .. }) {
+                *cmd = TrackerCmd::None;
+            }
+        }
+    }
+
+    seq.play_track(track);
+
+    // the sequencer skips straight from one event to the next, so this doesn't take long.
+    while !seq.is_finished() {
+        seq.process(SAMPLE_RATE as usize, &mut events);
+    }
+
+    events
+        .into_iter()
+        .filter_map(|SeqEvent { at, msg, .. }| match msg {
+            SeqMsg::Midi(msg) => {
+                Some(((at as f64 / sps * TICKS_PER_STEP as f64).round() as u64, msg))
+            }
+            // synth parameters have no midi equivalent, and the file keeps its own time.
+            SeqMsg::Param(_) | SeqMsg::Clock(_) => None,
+        })
+        .collect()
+}
+
+fn to_midly(msg: &MidiMessage) -> Option<TrackEventKind<'static>> {
+    let (chan, message) = match msg {
+        MidiMessage::NoteOn(chan, KeyEvent { key, value }) => (
+            chan,
+            midly::MidiMessage::NoteOn {
+                key: u7::from(*key),
+                vel: u7::from(*value),
+            },
+        ),
+        MidiMessage::NoteOff(chan, KeyEvent { key, value }) => (
+            chan,
+            midly::MidiMessage::NoteOff {
+                key: u7::from(*key),
+                vel: u7::from(*value),
+            },
+        ),
+        MidiMessage::ControlChange(chan, ControlEvent { control, value }) => (
+            chan,
+            midly::MidiMessage::Controller {
+                controller: u7::from(*control),
+                value: u7::from(*value),
+            },
+        ),
+        _ => return None,
+    };
+
+    Some(TrackEventKind::Midi {
+        channel: u4::from(channel_index(*chan)),
+        message,
+    })
+}
+
+/// put `notes` and `ccs` onto the steps of `track`. returns how many had to be dropped, either
+/// for landing past the last step or for needing a third command slot.
+fn quantize(
+    track: &mut Track,
+    notes: &[ImportedNote],
+    ccs: &[(usize, u8, u8)],
+    ticks_per_step: f64,
+) -> usize {
+    let len = track.steps.len();
+    let mut chords: Vec<Vec<i8>> = vec![Vec::new(); len];
+    let mut holds = vec![0; len];
+    let mut dropped = 0;
+
+    for note in notes {
+        let i = (note.start as f64 / ticks_per_step).round() as usize;
+        let Some(step) = track.steps.get_mut(i) else {
+            dropped += 1;
+            continue;
+        };
+        let end = note.end.unwrap_or(note.start);
+        let steps = ((end.saturating_sub(note.start)) as f64 / ticks_per_step).round() as usize;
+        holds[i] = holds[i].max(steps.max(1) - 1);
+
+        match step.note {
+            None => {
+                step.note = Some(note.key);
+                // `Some(DEFAULT_VELOCITY)` and `None` play (and export) the same, so this is the
+                // one velocity that doesn't round trip as written
+                step.velocity = (note.vel != DEFAULT_VELOCITY).then_some(note.vel);
+            }
+            Some(root) => {
+                let offset = note.key as i16 - root as i16;
+
+                if let Ok(offset) = i8::try_from(offset)
+                    && offset != 0
+                    && !chords[i].contains(&offset)
+                {
+                    chords[i].push(offset);
+                }
+            }
+        }
+    }
+
+    let len = track.steps.len();
+
+    for (i, step) in track.steps.iter_mut().enumerate() {
+        let mut cmds = Vec::new();
+
+        if !chords[i].is_empty() {
+            cmds.push(TrackerCmd::Chord {
+                chord: chords[i].clone(),
+            });
+        }
+
+        if holds[i] > 0 {
+            cmds.push(TrackerCmd::HoldFor {
+                notes: UsizeLessThan(holds[i].min(len - 1)),
+            });
+        }
+
+        cmds.extend(
+            ccs.iter()
+                .filter(|(at, ..)| *at == i)
+                .map(|(_, cc_param, arg)| match *cc_param {
+                    ALL_NOTES_OFF_CC => TrackerCmd::Panic,
+                    cc_param => TrackerCmd::MidiCmd { cc_param, arg: *arg },
+                }),
+        );
+
+        let mut cmds = cmds.into_iter();
+        step.cmds = (
+            cmds.next().unwrap_or_default(),
+            cmds.next().unwrap_or_default(),
+        );
+        dropped += cmds.count();
+    }
+
+    dropped += ccs.iter().filter(|(at, ..)| *at >= len).count();
+
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// grid aligned material, using only what a midi file can carry.
+    fn grid_track(name: &str, uuid: usize, chan: Channel) -> Track {
+        let mut track = Track::new(Some(name.into()), uuid, "Default".into());
+        track.chan = chan;
+        let steps = [
+            (0, Some(60), None, TrackerCmd::None, TrackerCmd::None),
+            (
+                4,
+                Some(64),
+                Some(100),
+                TrackerCmd::Chord { chord: vec![3, 7] },
+                TrackerCmd::HoldFor {
+                    notes: UsizeLessThan(2),
+                },
+            ),
+            (
+                8,
+                Some(50),
+                None,
+                TrackerCmd::MidiCmd {
+                    cc_param: 74,
+                    arg: 10,
+                },
+                TrackerCmd::None,
+            ),
+            (
+                12,
+                Some(70),
+                Some(1),
+                TrackerCmd::Chord { chord: vec![-12] },
+                TrackerCmd::MidiCmd {
+                    cc_param: 7,
+                    arg: 127,
+                },
+            ),
+            (16, None, None, TrackerCmd::Panic, TrackerCmd::None),
+            (
+                20,
+                Some(40),
+                Some(127),
+                TrackerCmd::HoldFor {
+                    notes: UsizeLessThan(107),
+                },
+                TrackerCmd::None,
+            ),
+        ];
+
+        for (i, note, velocity, cmd_1, cmd_2) in steps {
+            track.steps[i].note = note;
+            track.steps[i].velocity = velocity;
+            track.steps[i].cmds = (cmd_1, cmd_2);
+        }
+
+        track
+    }
+
+    #[test]
+    fn round_trip() {
+        let tracks = vec![
+            grid_track("Lead", 0, Channel::Ch1),
+            grid_track("Drums", 1, Channel::Ch10),
+            Track::new(Some("Empty".into()), 2, "Default".into()),
+        ];
+        let bytes = export(&tracks, 132.0, 4).unwrap();
+        let imported = import(&bytes, 4, 0, &"Default".into()).unwrap();
+
+        assert_eq!(imported.tracks, tracks);
+        assert!((imported.bpm.unwrap() - 132.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn round_trip_short_section() {
+        let mut track = Track::new(Some("Short".into()), 0, "Default".into());
+        track.set_len(16);
+        track.steps[2].note = Some(60);
+        // everything after row 2 is empty, and still has to come back
+        let bytes = export(&[track.clone()], 120.0, 4).unwrap();
+        let imported = import(&bytes, 4, 0, &"Default".into()).unwrap().tracks;
+
+        assert_eq!(imported, vec![track]);
+    }
+
+    #[test]
+    fn round_trip_with_other_lpb() {
+        let mut track = grid_track("Lead", 3, Channel::Ch5);
+        track.lpb = 3;
+        let tracks = vec![track];
+        let bytes = export(&tracks, 90.0, 3).unwrap();
+
+        assert_eq!(
+            import(&bytes, 3, 3, &"Default".into()).unwrap().tracks,
+            tracks
+        );
+    }
+
+    #[test]
+    fn default_velocity_normalizes() {
+        let mut track = Track::new(Some("Lead".into()), 0, "Default".into());
+        track.steps[0].note = Some(60);
+        track.steps[0].velocity = Some(DEFAULT_VELOCITY);
+        track.steps[4].note = Some(62);
+        let bytes = export(&[track.clone()], 120.0, 4).unwrap();
+        let imported = import(&bytes, 4, 0, &"Default".into()).unwrap().tracks;
+
+        assert_eq!(imported[0].steps[0].velocity, None);
+        assert_eq!(imported[0].steps[4].velocity, None);
+        // which is still the same file
+        assert_eq!(export(&imported, 120.0, 4).unwrap(), bytes);
+    }
+
+    #[test]
+    fn import_quantizes() {
+        let ppq = 96;
+        let on = |delta: u32, key: u8| TrackEvent {
+            delta: u28::from(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::from(2),
+                message: midly::MidiMessage::NoteOn {
+                    key: u7::from(key),
+                    vel: u7::from(90),
+                },
+            },
+        };
+        let off = |delta: u32, key: u8| TrackEvent {
+            delta: u28::from(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::from(2),
+                message: midly::MidiMessage::NoteOff {
+                    key: u7::from(key),
+                    vel: u7::from(0),
+                },
+            },
+        };
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(u15::from(ppq))));
+        // a slightly late chord held for about three steps, then a short note that's early.
+        smf.tracks.push(vec![
+            on(5, 48),
+            on(2, 55),
+            on(0, 52),
+            off(64, 48),
+            off(0, 55),
+            off(3, 52),
+            on(20, 60),
+            off(10, 60),
+        ]);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let imported = import(&bytes, 4, 7, &"Keys".into()).unwrap();
+        let track = &imported.tracks[0];
+
+        assert_eq!(imported.bpm, None);
+        assert_eq!(track.uuid, 7);
+        assert_eq!(track.dev, "Keys");
+        assert_eq!(track.chan, Channel::Ch3);
+        assert_eq!(track.steps[0].note, Some(48));
+        assert_eq!(track.steps[0].velocity, Some(90));
+        assert_eq!(
+            track.steps[0].cmds,
+            (
+                TrackerCmd::Chord { chord: vec![7, 4] },
+                TrackerCmd::HoldFor {
+                    notes: UsizeLessThan(2)
+                }
+            )
+        );
+        assert_eq!(track.steps[4].note, Some(60));
+        assert_eq!(track.steps[4].cmds, (TrackerCmd::None, TrackerCmd::None));
+        // up to the end of the last note
+        assert_eq!(track.steps.len(), 5);
+        assert_eq!(imported.dropped, 0);
+    }
+
+    #[test]
+    fn import_sizes_sections_to_the_file() {
+        let note = |delta: u32, key: u8, vel: u8| TrackEvent {
+            delta: u28::from(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::from(0),
+                message: midly::MidiMessage::NoteOn {
+                    key: u7::from(key),
+                    vel: u7::from(vel),
+                },
+            },
+        };
+        // four lines a beat at 4 ticks a beat is a tick a step
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(u15::from(4))));
+        smf.tracks.push(vec![note(0, 60, 90), note(1, 60, 0), note(199, 62, 90), note(1, 62, 0)]);
+        smf.tracks.push(vec![note(299, 64, 90), note(1, 64, 0)]);
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let imported = import(&bytes, 4, 0, &"Default".into()).unwrap();
+
+        assert_eq!(imported.tracks[0].steps.len(), 201);
+        assert_eq!(imported.tracks[0].steps[200].note, Some(62));
+        // longer than a section can be, so the note past the end is dropped
+        assert_eq!(imported.tracks[1].steps.len(), MAX_STEPS);
+        assert!(imported.tracks[1].steps.iter().all(|step| step.note.is_none()));
+        assert_eq!(imported.dropped, 1);
+    }
+
+    #[test]
+    fn not_a_midi_file() {
+        assert!(import(b"TSYN....", 4, 0, &"Default".into()).is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::SynthId;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, f32::consts::FRAC_PI_4};
+
+/// midi channel volume, sets a strip's gain. 127 is unity.
+pub const VOLUME_CC: u8 = 7;
+/// midi pan, 64 is center.
+pub const PAN_CC: u8 = 10;
+/// the soft limiter leaves everything quieter than this untouched.
+const LIMITER_THRESHOLD: f32 = 0.8;
+
+/// the mixer settings of a single synth.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChannelStrip {
+    /// linear gain, 1.0 is unity.
+    pub gain: f32,
+    /// -1.0 is hard left, 1.0 is hard right.
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Default for ChannelStrip {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+impl ChannelStrip {
+    /// constant power pan law, so a sound keeps the same loudness as it moves across.
+    pub fn pan_gains(&self) -> (f32, f32) {
+        let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+
+        (angle.cos(), angle.sin())
+    }
+}
+
+/// sums every synth into a stereo pair.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mixer {
+    /// synths without a strip play at unity gain, centered.
+    pub strips: HashMap<SynthId, ChannelStrip>,
+    pub master: f32,
+    /// soft clip the master bus so that it never goes past full scale.
+    pub limiter: bool,
+    /// gain set by volume commands as the song plays, on top of the strip's own. it's part of the
+    /// song rather than the mix, so it isn't saved.
+    #[serde(skip)]
+    pub volumes: HashMap<SynthId, f32>,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self {
+            strips: HashMap::default(),
+            master: 1.0,
+            limiter: true,
+            volumes: HashMap::default(),
+        }
+    }
+}
+
+impl Mixer {
+    pub fn strip(&self, id: &str) -> ChannelStrip {
+        self.strips.get(id).copied().unwrap_or_default()
+    }
+
+    pub fn strip_mut(&mut self, id: &str) -> &mut ChannelStrip {
+        self.strips.entry(id.to_string()).or_default()
+    }
+
+    /// the gain volume commands left `id` at, 1.0 if none have played.
+    pub fn volume(&self, id: &str) -> f32 {
+        self.volumes.get(id).copied().unwrap_or(1.0)
+    }
+
+    pub fn set_volume(&mut self, id: &str, volume: f32) {
+        self.volumes.insert(id.to_string(), volume);
+    }
+
+    /// whether `id` can be heard, given the mute and solo state of every strip.
+    pub fn audible(&self, id: &str) -> bool {
+        let strip = self.strip(id);
+        let soloing = self.strips.values().any(|strip| strip.solo);
+
+        !strip.mute && (!soloing || strip.solo)
+    }
+
+    /// mix one sample from each synth into a (left, right) pair.
+    pub fn mix<'a>(&self, inputs: impl IntoIterator<Item = (&'a SynthId, f32)>) -> (f32, f32) {
+        let soloing = self.strips.values().any(|strip| strip.solo);
+        let (mut left, mut right) = (0.0, 0.0);
+
+        for (id, sample) in inputs {
+            let strip = self.strip(id);
+
+            if strip.mute || (soloing && !strip.solo) {
+                continue;
+            }
+
+            let (l, r) = strip.pan_gains();
+            let gain = strip.gain * self.volume(id);
+            left += sample * gain * l;
+            right += sample * gain * r;
+        }
+
+        left *= self.master;
+        right *= self.master;
+
+        if self.limiter {
+            (soft_limit(left), soft_limit(right))
+        } else {
+            (left, right)
+        }
+    }
+
+    /// handle the CCs that belong to the mixer rather than the synth. returns false if `control`
+    /// isn't one of them.
+    pub fn control_change(&mut self, id: &str, control: u8, value: u8) -> bool {
+        let value = value.min(127) as f32;
+
+        match control {
+            VOLUME_CC => self.strip_mut(id).gain = value / 127.0,
+            PAN_CC => self.strip_mut(id).pan = ((value - 64.0) / 63.0).clamp(-1.0, 1.0),
+            _ => return false,
+        }
+
+        true
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if let Some(strip) = self.strips.remove(from) {
+            self.strips.insert(to.to_string(), strip);
+        }
+
+        if let Some(volume) = self.volumes.remove(from) {
+            self.volumes.insert(to.to_string(), volume);
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        self.strips.remove(id);
+        self.volumes.remove(id);
+    }
+}
+
+/// passes quiet signals through untouched and bends loud ones smoothly towards (but never past)
+/// full scale.
+pub fn soft_limit(sample: f32) -> f32 {
+    let level = sample.abs();
+
+    if level <= LIMITER_THRESHOLD {
+        sample
+    } else {
+        let headroom = 1.0 - LIMITER_THRESHOLD;
+        let over = (level - LIMITER_THRESHOLD) / headroom;
+
+        sample.signum() * (LIMITER_THRESHOLD + headroom * over.tanh())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn close(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() < EPSILON && (a.1 - b.1).abs() < EPSILON
+    }
+
+    /// mix a fixed buffer of (synth, sample) frames.
+    fn render(mixer: &Mixer, frames: &[Vec<(&str, f32)>]) -> Vec<(f32, f32)> {
+        frames
+            .iter()
+            .map(|frame| {
+                let ids: Vec<(SynthId, f32)> = frame
+                    .iter()
+                    .map(|(id, sample)| (id.to_string(), *sample))
+                    .collect();
+
+                mixer.mix(ids.iter().map(|(id, sample)| (id, *sample)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unity_is_centered() {
+        let mixer = Mixer::default();
+        let out = render(&mixer, &[vec![("a", 0.5)], vec![("a", -0.25)]]);
+        let center = FRAC_PI_4.cos();
+
+        assert!(close(out[0], (0.5 * center, 0.5 * center)), "{out:?}");
+        assert!(close(out[1], (-0.25 * center, -0.25 * center)), "{out:?}");
+    }
+
+    #[test]
+    fn gain_and_pan() {
+        let mut mixer = Mixer::default();
+        *mixer.strip_mut("left") = ChannelStrip {
+            gain: 0.5,
+            pan: -1.0,
+            ..Default::default()
+        };
+        mixer.strip_mut("right").pan = 1.0;
+        mixer.master = 0.5;
+
+        let out = render(&mixer, &[vec![("left", 0.4), ("right", 0.2)]]);
+
+        assert!(close(out[0], (0.1, 0.1)), "{out:?}");
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let mut mixer = Mixer::default();
+        mixer.strip_mut("a").pan = -1.0;
+        mixer.strip_mut("b").pan = 1.0;
+        mixer.strip_mut("c").pan = -1.0;
+        let frame = vec![vec![("a", 0.1), ("b", 0.2), ("c", 0.3)]];
+
+        mixer.strip_mut("c").mute = true;
+        assert!(close(render(&mixer, &frame)[0], (0.1, 0.2)));
+
+        mixer.strip_mut("b").solo = true;
+        assert!(close(render(&mixer, &frame)[0], (0.0, 0.2)));
+        assert!(!mixer.audible("a"));
+        assert!(mixer.audible("b"));
+
+        // a muted strip stays muted, even when it's soloed.
+        mixer.strip_mut("c").solo = true;
+        assert!(close(render(&mixer, &frame)[0], (0.0, 0.2)));
+    }
+
+    #[test]
+    fn volume_is_on_top_of_the_strip() {
+        let mut mixer = Mixer::default();
+        mixer.strip_mut("a").pan = -1.0;
+        mixer.strip_mut("a").gain = 0.5;
+        mixer.limiter = false;
+        mixer.set_volume("a", 0.5);
+
+        let out = render(&mixer, &[vec![("a", 1.0)]]);
+        assert!(close(out[0], (0.25, 0.0)), "{out:?}");
+        assert_eq!(mixer.strip("a").gain, 0.5);
+
+        mixer.rename("a", "b");
+        assert_eq!(mixer.volume("b"), 0.5);
+        mixer.remove("b");
+        assert_eq!(mixer.volume("b"), 1.0);
+    }
+
+    #[test]
+    fn limiter() {
+        let mut mixer = Mixer::default();
+        mixer.strip_mut("a").pan = -1.0;
+        let frames: Vec<Vec<(&str, f32)>> = [0.5, 0.8, 0.9, 2.0, 100.0, -100.0]
+            .into_iter()
+            .map(|sample| vec![("a", sample)])
+            .collect();
+
+        let out = render(&mixer, &frames);
+
+        assert!(close(out[0], (0.5, 0.0)));
+        assert!(close(out[1], (0.8, 0.0)));
+        assert!(out[2].0 > 0.8 && out[2].0 < 0.9, "{out:?}");
+        assert!(out[3].0 > out[2].0 && out[3].0 <= 1.0, "{out:?}");
+        assert!(out[4].0 <= 1.0 && out[5].0 >= -1.0, "{out:?}");
+
+        mixer.limiter = false;
+        assert!(close(render(&mixer, &frames)[3], (2.0, 0.0)));
+    }
+
+    #[test]
+    fn volume_and_pan_ccs() {
+        let mut mixer = Mixer::default();
+
+        assert!(mixer.control_change("a", VOLUME_CC, 0));
+        assert!(mixer.control_change("b", PAN_CC, 127));
+        assert!(mixer.control_change("c", PAN_CC, 0));
+        assert!(!mixer.control_change("a", 74, 0));
+
+        assert_eq!(mixer.strip("a").gain, 0.0);
+        assert_eq!(mixer.strip("b").pan, 1.0);
+        assert_eq!(mixer.strip("c").pan, -1.0);
+    }
+
+    #[test]
+    fn rename_and_remove() {
+        let mut mixer = Mixer::default();
+        mixer.strip_mut("a").gain = 0.25;
+
+        mixer.rename("a", "b");
+        assert_eq!(mixer.strip("b").gain, 0.25);
+        assert_eq!(mixer.strip("a"), ChannelStrip::default());
+
+        mixer.remove("b");
+        assert!(mixer.strips.is_empty());
+    }
+}
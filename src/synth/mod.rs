@@ -1,5 +1,7 @@
 use core::panic;
-use std::{ops::DerefMut, sync::{Arc, Mutex, RwLock}};
+use std::{fmt::{self, Display, Formatter}, iter, ops::{DerefMut, Range}, path::PathBuf, sync::{Arc, Mutex, PoisonError, RwLock}, time::Instant};
+use midi_control::{Channel, ControlEvent, KeyEvent, MidiMessage};
+use serde::{Deserialize, Serialize};
 use stepper_synth_backend::{
     pygame_coms::SynthEngineType, synth_engines::{
         wave_table::WaveTableEngine,
@@ -8,52 +10,258 @@ use stepper_synth_backend::{
         SynthEngine,
     }, HashMap, SampleGen, CHANNEL_SIZE, SAMPLE_RATE
 };
+use strum::EnumIter;
 use tinyaudio::{run_output_device, OutputDevice, OutputDeviceParameters};
-use crate::SynthId;
+use tracing::*;
+use crate::{playback::{clock::{ClockFollower, ClockMode, ClockMsg}, DEFAULT_LPB, Playhead, SampleTime, SeqEvent, SeqMsg, Sequencer}, record::NotePlayed, tracks::{rename_dev, Sf2Cmd, Track}, SynthId};
+use crossbeam::channel::{bounded, Receiver, Sender};
+use mixer::{ChannelStrip, Mixer};
+use router::{ChannelRoutes, SynthSink};
+use sampler::{Sampler, SamplerConf};
+use sf2::Sf2Engine;
 
+pub mod mixer;
+pub mod router;
+pub mod sampler;
+pub mod sf2;
+pub mod soundfont;
+
+/// the kinds of instrument that a `TabSynth` can host. this is what gets saved in a project,
+/// since the synths themselves can't be serialized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, EnumIter, strum_macros::Display)]
+pub enum EngineKind {
+    #[default]
+    #[strum(to_string = "WAVE")]
+    WaveTable,
+    /// plays a SoundFont, silent until one is loaded with `TabSynth::load_sf2`.
+    #[strum(to_string = "SF2")]
+    Sf2,
+    /// plays wav files, silent until it's given some with `TabSynth::set_sampler`.
+    #[strum(to_string = "SMPL")]
+    Sampler,
+}
+
+/// a synth that a `TabSynth` hosts. one of stepper-synth's engines, a SoundFont, or a sampler.
 #[derive(Debug)]
+pub enum Instrument {
+    Stepper(SynthChannel),
+    Sf2(Sf2Engine),
+    Sampler(Sampler),
+}
+
+impl From<EngineKind> for Instrument {
+    fn from(kind: EngineKind) -> Self {
+        match kind {
+            EngineKind::WaveTable => Self::Stepper(SynthChannel::from(SynthEngineType::WaveTable)),
+            EngineKind::Sf2 => Self::Sf2(Sf2Engine::default()),
+            EngineKind::Sampler => Self::Sampler(Sampler::default()),
+        }
+    }
+}
+
+impl Instrument {
+    pub fn get_sample(&mut self) -> f32 {
+        match self {
+            Self::Stepper(synth) => synth.get_sample(),
+            Self::Sf2(engine) => engine.get_sample(),
+            Self::Sampler(sampler) => sampler.get_sample(),
+        }
+    }
+
+    fn sink(&mut self) -> &mut dyn SynthSink {
+        match self {
+            Self::Stepper(synth) => synth,
+            Self::Sf2(engine) => engine,
+            Self::Sampler(sampler) => sampler,
+        }
+    }
+}
+
+impl SynthSink for Instrument {
+    fn play(&mut self, note: u8, velocity: u8) {
+        self.sink().play(note, velocity)
+    }
+
+    fn stop(&mut self, note: u8) {
+        self.sink().stop(note)
+    }
+
+    fn bend(&mut self, amount: f32) {
+        self.sink().bend(amount)
+    }
+
+    fn unbend(&mut self) {
+        self.sink().unbend()
+    }
+
+    fn knob(&mut self, knob: u8, value: f32) -> bool {
+        self.sink().knob(knob, value)
+    }
+
+    fn volume_swell(&mut self, value: f32) -> bool {
+        self.sink().volume_swell(value)
+    }
+
+    fn raw_midi(&mut self, msg: &MidiMessage) -> bool {
+        self.sink().raw_midi(msg)
+    }
+
+    fn param_knob(&self, param: &Sf2Cmd) -> Option<u8> {
+        match self {
+            Self::Stepper(synth) => synth.param_knob(param),
+            Self::Sf2(engine) => engine.param_knob(param),
+            Self::Sampler(sampler) => sampler.param_knob(param),
+        }
+    }
+
+    fn sample_offset(&mut self, amount: f32) -> bool {
+        self.sink().sample_offset(amount)
+    }
+}
+
+/// the SoundFont that an `EngineKind::Sf2` instrument plays, and which of its presets.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sf2Conf {
+    pub path: PathBuf,
+    pub bank: u16,
+    pub preset: u16,
+}
+
+/// a saved instrument, enough to rebuild the synth when a project is loaded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InstrumentConf {
+    pub id: SynthId,
+    pub engine: EngineKind,
+    #[serde(default)]
+    pub sf2: Option<Sf2Conf>,
+    #[serde(default)]
+    pub sampler: Option<SamplerConf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SynthError {
+    /// there is no synth with this id.
+    Missing(SynthId),
+    /// a synth with this id already exists.
+    Duplicate(SynthId),
+    /// the synth couldn't be loaded, and why.
+    Load(String),
+    /// sections play this synth, and there's no other for them to play.
+    InUse(SynthId),
+}
+
+impl Display for SynthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(id) => write!(f, "no synth by the name: \"{id}\", found in database"),
+            Self::Duplicate(id) => write!(f, "a synth named \"{id}\" already exists"),
+            Self::Load(e) => write!(f, "{e}"),
+            Self::InUse(id) => write!(f, "\"{id}\" is the only synth, and sections still play it"),
+        }
+    }
+}
+
+impl std::error::Error for SynthError {}
+
+/// where to send the midi for each external device, by the name that tracks play it by.
+pub type MidiOutputs = HashMap<SynthId, Sender<SeqMsg>>;
+
+/// where a synth lives in `TabSynth::synths`, and what it was built as.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SynthSlot {
+    index: usize,
+    engine: EngineKind,
+    /// the SoundFont it plays, for `EngineKind::Sf2`.
+    sf2: Option<Sf2Conf>,
+    /// the pads it plays, for `EngineKind::Sampler`.
+    sampler: Option<SamplerConf>,
+}
+
+#[derive(Debug, Clone)]
 pub struct TabSynth {
-    synths: Arc<RwLock<Vec<(SynthId, SynthChannel)>>>,
+    synths: Arc<RwLock<Vec<(SynthId, Instrument)>>>,
     /// maps synth friendly names to index values in self.synths.
     /// I'd store the SynthChannel directly but that crashes the app.
-    db: Arc<RwLock<HashMap<SynthId, usize>>>,
+    ///
+    /// NOTE: always lock `synths` before `db` so that the two stay in step.
+    db: Arc<RwLock<HashMap<SynthId, SynthSlot>>>,
+    /// driven from the audio callback so that steps land on exact samples.
+    sequencer: Arc<Mutex<Sequencer>>,
+    /// which synth plays the midi coming in on each channel.
+    routes: Arc<RwLock<ChannelRoutes>>,
+    mixer: Arc<RwLock<Mixer>>,
+    /// every time the playhead moves, where it moved to (`None` once stopped). bounded so the
+    /// audio thread never blocks or allocates to send, if nobody's keeping up it just drops them.
+    playhead: (Sender<Option<Playhead>>, Receiver<Option<Playhead>>),
+    /// midi devices that tracks can play instead of a synth, by name.
+    outputs: Arc<RwLock<MidiOutputs>>,
+    /// the tempo of the clock coming in, for when the sequencer follows it.
+    follower: Arc<Mutex<ClockFollower>>,
+    /// when the audio thread last ran the sequencer, which places clock ticks on the sample they
+    /// came in on.
+    processed: Arc<Mutex<Instant>>,
+    /// every note that comes in, for recording. bounded like `playhead`.
+    played: (Sender<NotePlayed>, Receiver<NotePlayed>),
 }
 
+/// how many playhead updates can queue up before new ones get dropped.
+const PLAYHEAD_QUEUE: usize = 64;
+/// how many incoming notes can wait to be recorded before new ones get dropped.
+const PLAYED_QUEUE: usize = 64;
+
 impl TabSynth {
+    /// a synth with a single "Default" wave table and no audio output. `new` is this plus an
+    /// output device.
+    pub fn headless() -> Self {
+        let synth = Self {
+            synths: Arc::new(RwLock::new(Vec::new())),
+            db: Arc::new(RwLock::new(HashMap::default())),
+            sequencer: Arc::new(Mutex::new(Sequencer::default())),
+            routes: Arc::new(RwLock::new(ChannelRoutes::default())),
+            mixer: Arc::new(RwLock::new(Mixer::default())),
+            playhead: bounded(PLAYHEAD_QUEUE),
+            outputs: Arc::new(RwLock::new(MidiOutputs::default())),
+            follower: Arc::new(Mutex::new(ClockFollower::default())),
+            processed: Arc::new(Mutex::new(Instant::now())),
+            played: bounded(PLAYED_QUEUE),
+        };
+        let _ = synth.add_synth("Default", EngineKind::WaveTable);
+
+        synth
+    }
+
     pub fn new() -> (Self, OutputDevice) {
-        let synths = Arc::new(RwLock::new(vec![("Default".to_string(), SynthChannel::from(SynthEngineType::WaveTable))]));
+        let synth = Self::headless();
 
         let device = {
-            let synths = synths.clone();
+            let synth = synth.clone();
 
             // move || {
             let params = OutputDeviceParameters {
-                channels_count: 1,
+                channels_count: 2,
                 sample_rate: SAMPLE_RATE as usize,
                 // channel_sample_count: 2048,
                 channel_sample_count: CHANNEL_SIZE,
             };
             // NOTE: must stay in this thread so that it stays in scope
             run_output_device(params, {
+                let mut events = Vec::new();
 
                 move |data| {
-                    for samples in data.chunks_mut(params.channels_count) {
-                        let value = synths.write().map(|synth| synth.iter().map(|(_name, instrument)| instrument.get_sample()).sum()).unwrap_or(0.0);
+                    let channels = params.channels_count;
 
-                        for sample in samples {
-                            *sample = value;
+                    synth.next_frames(data.len() / channels, &mut events, |i, (left, right)| {
+                        if let [l, r] = &mut data[i * channels..(i + 1) * channels] {
+                            *l = left;
+                            *r = right;
                         }
-                    }
+                    });
                 }
             })
         };
 
-        let mut db = HashMap::default();
-        db.insert("Default".to_string(), 0);
-        let db = Arc::new(RwLock::new(db));
-
         match device {
-            Ok(device) => (Self { synths, db }, device),
+            Ok(device) => (synth, device),
             Err(e) => {
                 println!("starting audio playback caused error: {e}");
                 panic!("{e}");
@@ -61,30 +269,661 @@ impl TabSynth {
         }
     }
 
-    // fn do_rename(&self, from: impl ToString, to: impl ToString) -> Result<(), String> {
-    //     let from = from.to_string();
-    //     let to = to.to_string();
+    /// advance the sequencer by a sample, play what it fired, and mix one (left, right) sample
+    /// from every synth. `events` is scratch space, kept by the caller so the audio thread doesn't
+    /// allocate.
+    pub fn next_sample(&self, events: &mut Vec<SeqEvent>) -> (f32, f32) {
+        let mut out = (0.0, 0.0);
+        self.next_frames(1, events, |_, frame| out = frame);
+
+        out
+    }
 
-    //     let synth_id = {
-    //         let Some(i) = self.synths.read().iter().position(|(id, _synth)| { from == id.read().to_owned() }) else {
-    //             return Err(format!("no synth by the name: \"{from}\", found in database"));
-    //         };
+    /// advance the sequencer by `n` samples and hand `frame` each (left, right) sample mixed from
+    /// every synth, in order. the sequencer runs once for the lot and every lock is taken once, then
+    /// each event is played on the sample it's due. `events` is scratch space like in
+    /// `next_sample`.
+    pub fn next_frames(&self, n: usize, events: &mut Vec<SeqEvent>, mut frame: impl FnMut(usize, (f32, f32))) {
+        let start = match self.sequencer.lock() {
+            Ok(mut seq) => {
+                let (start, before) = (seq.now(), seq.playhead());
+                seq.process(n, events);
 
+                if seq.playhead() != before {
+                    let _ = self.playhead.0.try_send(seq.playhead());
+                }
 
-    //     };
+                if let Ok(mut processed) = self.processed.lock() {
+                    *processed = Instant::now();
+                }
 
-    //     Ok(())
-    // }
+                start
+            }
+            Err(_) => 0,
+        };
+
+        let (Ok(mut synths), Ok(mut mixer), Ok(outputs)) =
+            (self.synths.write(), self.mixer.write(), self.outputs.read())
+        else {
+            events.clear();
+            (0..n).for_each(|i| frame(i, (0.0, 0.0)));
+
+            return;
+        };
+
+        let mut due = events.drain(..).peekable();
+
+        for i in 0..n {
+            let now = start + i as SampleTime;
+            dispatch(&mut synths, &mut mixer, &outputs, iter::from_fn(|| due.next_if(|event| event.at <= now)));
+
+            frame(
+                i,
+                mixer.mix(
+                    synths
+                        .iter_mut()
+                        .map(|(name, instrument)| (name as &SynthId, instrument.get_sample())),
+                ),
+            );
+        }
+    }
+
+    /// start the sequencer on `tracks`, all playing in parallel from their first step.
+    pub fn play(&self, tracks: Vec<Track>) {
+        self.play_song(vec![tracks]);
+    }
+
+    /// start the sequencer on a song, one block of parallel tracks per pattern.
+    pub fn play_song(&self, song: Vec<Vec<Track>>) {
+        self.play_song_from(song, 0, 0);
+    }
+
+    /// start the sequencer on a song, from `row` of `block`.
+    pub fn play_song_from(&self, song: Vec<Vec<Track>>, block: usize, row: usize) {
+        self.stop();
+
+        // volume commands from last time round shouldn't carry over
+        if let Ok(mut mixer) = self.mixer.write() {
+            mixer.volumes.clear();
+        }
+
+        if let Ok(mut seq) = self.sequencer.lock() {
+            seq.play_song_from(song, block, row);
+        }
+    }
+
+    /// only play `rows` of each block, over and over, or whole blocks with `None`.
+    pub fn set_loop_range(&self, rows: Option<Range<usize>>) {
+        if let Ok(mut seq) = self.sequencer.lock() {
+            seq.set_loop_range(rows);
+        }
+    }
+
+    /// stop, but keep the place so that `resume` carries on from there.
+    pub fn pause(&self) {
+        let released = self.sequencer.lock().map(|mut seq| seq.pause()).unwrap_or_default();
+
+        if let (Ok(mut synths), Ok(mut mixer), Ok(outputs)) =
+            (self.synths.write(), self.mixer.write(), self.outputs.read())
+        {
+            dispatch(&mut synths, &mut mixer, &outputs, released.into_iter());
+        }
+    }
+
+    pub fn resume(&self) {
+        if let Ok(mut seq) = self.sequencer.lock() {
+            seq.resume();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.sequencer.lock().map(|seq| seq.is_paused()).unwrap_or(false)
+    }
+
+    /// where the playhead moves to, as it moves. every receiver shares the same queue, so there
+    /// should only be one listening.
+    pub fn playhead_updates(&self) -> Receiver<Option<Playhead>> {
+        self.playhead.1.clone()
+    }
+
+    /// the notes that come in through `midi_input`, as they come in, with where the sequencer was
+    /// when they did. like `playhead_updates`, there should only be one listening.
+    pub fn notes_played(&self) -> Receiver<NotePlayed> {
+        self.played.1.clone()
+    }
+
+    /// the uuids of the sections playing in `block` of the song.
+    pub fn block_sections(&self, block: usize) -> Vec<usize> {
+        self.sequencer.lock().map(|seq| seq.block_sections(block)).unwrap_or_default()
+    }
+
+    /// the lines per beat that `block` of the song steps at, which its playhead rows count in.
+    pub fn block_lpb(&self, block: usize) -> usize {
+        self.sequencer.lock().map(|seq| seq.block_lpb(block)).unwrap_or(DEFAULT_LPB)
+    }
+
+    /// change the tempo, from the next step on if a song is playing. when following a clock, its
+    /// tempo wins once there is one.
+    pub fn set_tempo(&self, bpm: f64, lpb: usize) {
+        if let Ok(mut seq) = self.sequencer.lock() {
+            let followed = self.follower.lock().ok().and_then(|follower| follower.bpm());
+            let bpm = match (seq.clock_mode(), followed) {
+                (ClockMode::Follow, Some(followed)) => followed,
+                _ => bpm,
+            };
+
+            seq.set_tempo(bpm, lpb);
+        }
+    }
+
+    pub fn clock_mode(&self) -> ClockMode {
+        self.sequencer.lock().map(|seq| seq.clock_mode()).unwrap_or_default()
+    }
+
+    /// send clock out to every connected device, follow the clock coming in, or neither.
+    pub fn set_clock_mode(&self, mode: ClockMode) {
+        if let Ok(mut seq) = self.sequencer.lock() {
+            seq.set_clock_mode(mode);
+        }
+    }
+
+    /// clock from an external device, which arrived `at`. only listened to when following it.
+    pub fn clock_input(&self, msg: ClockMsg, at: Instant) {
+        let released = {
+            let Ok(mut seq) = self.sequencer.lock() else {
+                return;
+            };
+
+            if seq.clock_mode() != ClockMode::Follow {
+                return;
+            }
+
+            if msg == ClockMsg::Tick {
+                if let Ok(mut follower) = self.follower.lock()
+                    && let Some(bpm) = follower.tick(at)
+                {
+                    let lpb = seq.lpb();
+                    seq.set_tempo(bpm, lpb);
+                }
+
+                // the sequencer is as far as the audio thread got, and the tick came in a little
+                // after (or before) that
+                let since = self.processed.lock().map_or(0.0, |processed| {
+                    match at.checked_duration_since(*processed) {
+                        Some(after) => after.as_secs_f64(),
+                        None => -processed.duration_since(at).as_secs_f64(),
+                    }
+                });
+                let now = seq.now() as f64 + since * SAMPLE_RATE as f64;
+                seq.clock_tick(now.max(0.0).round() as SampleTime);
+
+                return;
+            }
+
+            seq.clock_input(msg)
+        };
+
+        if let (Ok(mut synths), Ok(mut mixer), Ok(outputs)) =
+            (self.synths.write(), self.mixer.write(), self.outputs.read())
+        {
+            dispatch(&mut synths, &mut mixer, &outputs, released.into_iter());
+        }
+    }
+
+    /// whether songs go back to the start when they end, rather than stopping.
+    pub fn set_looping(&self, looping: bool) {
+        if let Ok(mut seq) = self.sequencer.lock() {
+            seq.set_looping(looping);
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.sequencer.lock().map(|seq| seq.is_playing()).unwrap_or(false)
+    }
+
+    /// true when the sequencer has nothing left to play.
+    pub fn is_finished(&self) -> bool {
+        self.sequencer.lock().map(|seq| seq.is_finished()).unwrap_or(true)
+    }
+
+    /// stop the sequencer and release any notes it was holding.
+    pub fn stop(&self) {
+        let (released, moved) = self
+            .sequencer
+            .lock()
+            .map(|mut seq| {
+                let moved = seq.playhead().is_some();
+                (seq.stop(), moved)
+            })
+            .unwrap_or_default();
+
+        if moved {
+            let _ = self.playhead.0.try_send(None);
+        }
+
+        if let (Ok(mut synths), Ok(mut mixer), Ok(outputs)) =
+            (self.synths.write(), self.mixer.write(), self.outputs.read())
+        {
+            dispatch(&mut synths, &mut mixer, &outputs, released.into_iter());
+        }
+    }
+
+    /// play an incoming midi message on the synth assigned to its channel.
+    pub fn midi_input(&self, msg: &MidiMessage) {
+        if let MidiMessage::NoteOn(_, KeyEvent { key, value }) = msg
+            && *value > 0
+        {
+            let _ = self.played.0.try_send(NotePlayed {
+                note: *key,
+                velocity: *value,
+                at: Instant::now(),
+                position: self.sequencer.lock().ok().and_then(|seq| seq.playhead_phase()),
+            });
+        }
+
+        let (Ok(routes), Ok(mut synths)) = (self.routes.read(), self.synths.write()) else {
+            return;
+        };
+
+        // volume and pan belong to the mixer
+        if let MidiMessage::ControlChange(chan, ControlEvent { control, value }) = msg
+            && let Some(dev) = routes.get(*chan)
+            && let Ok(mut mixer) = self.mixer.write()
+            && mixer.control_change(dev, *control, *value)
+        {
+            return;
+        }
+
+        router::route(&routes, &mut synths, msg);
+    }
+
+    pub fn mixer(&self) -> Mixer {
+        self.mixer.read().map(|mixer| mixer.clone()).unwrap_or_default()
+    }
+
+    /// replace the whole mixer, as when loading a project.
+    pub fn set_mixer(&self, mixer: Mixer) {
+        if let Ok(mut old) = self.mixer.write() {
+            *old = mixer;
+        }
+    }
+
+    pub fn set_strip(&self, id: &str, strip: ChannelStrip) {
+        if let Ok(mut mixer) = self.mixer.write() {
+            *mixer.strip_mut(id) = strip;
+        }
+    }
+
+    pub fn set_master(&self, master: f32) {
+        if let Ok(mut mixer) = self.mixer.write() {
+            mixer.master = master;
+        }
+    }
+
+    /// assign a synth to a midi channel, or unassign the channel with `None`.
+    pub fn set_route(&self, chan: Channel, synth: Option<SynthId>) {
+        if let Ok(mut routes) = self.routes.write() {
+            routes.set(chan, synth);
+        }
+    }
+
+    pub fn routes(&self) -> ChannelRoutes {
+        self.routes.read().map(|routes| routes.clone()).unwrap_or_default()
+    }
+
+    /// let tracks whose `dev` is `name` play an external device, by sending their midi to
+    /// `output`. `None` forgets the device.
+    pub fn set_midi_output(&self, name: &str, output: Option<Sender<SeqMsg>>) {
+        if let Ok(mut outputs) = self.outputs.write() {
+            match output {
+                Some(output) => outputs.insert(name.to_string(), output),
+                None => outputs.remove(name),
+            };
+        }
+    }
+
+    /// the names of the external devices that tracks can play, sorted.
+    pub fn midi_outputs(&self) -> Vec<SynthId> {
+        let mut names: Vec<_> = self
+            .outputs
+            .read()
+            .map(|outputs| outputs.keys().cloned().collect())
+            .unwrap_or_default();
+        names.sort();
+
+        names
+    }
+
+    /// every synth and the kind of engine it is, in the order they were added.
+    pub fn list_synths(&self) -> Vec<(SynthId, EngineKind)> {
+        let (Ok(synths), Ok(db)) = (self.synths.read(), self.db.read()) else {
+            return Vec::new();
+        };
+
+        synths
+            .iter()
+            .filter_map(|(id, _synth)| db.get(id).map(|slot| (id.clone(), slot.engine)))
+            .collect()
+    }
+
+    pub fn has_synth(&self, id: &str) -> bool {
+        self.db.read().is_ok_and(|db| db.contains_key(id))
+    }
+
+    /// the kind of engine that the synth named `id` is.
+    pub fn engine(&self, id: &str) -> Option<EngineKind> {
+        self.db.read().ok()?.get(id).map(|slot| slot.engine)
+    }
+
+    pub fn add_synth(&self, id: impl ToString, engine: EngineKind) -> Result<(), SynthError> {
+        let id = id.to_string();
+        let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+        let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+
+        if db.contains_key(&id) {
+            return Err(SynthError::Duplicate(id));
+        }
+
+        db.insert(
+            id.clone(),
+            SynthSlot {
+                index: synths.len(),
+                engine,
+                sf2: None,
+                sampler: None,
+            },
+        );
+        synths.push((id, Instrument::from(engine)));
+
+        Ok(())
+    }
+
+    /// remove a synth, and unassign any midi channels that were playing it. `sections`, and
+    /// whatever the sequencer is playing, move over to the first synth left the way `rename` moves
+    /// them, which is returned. the last synth can't go while sections still play it.
+    pub fn remove_synth(&self, id: &str, sections: &mut [Track]) -> Result<Option<SynthId>, SynthError> {
+        let to = {
+            let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+            let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+
+            if !db.contains_key(id) {
+                return Err(SynthError::Missing(id.into()));
+            }
+
+            let to = synths.iter().map(|(other, _synth)| other).find(|other| *other != id).cloned();
+
+            if to.is_none() && sections.iter().any(|section| section.dev == id) {
+                return Err(SynthError::InUse(id.into()));
+            }
+
+            let Some(slot) = db.remove(id) else {
+                return Err(SynthError::Missing(id.into()));
+            };
+            synths.remove(slot.index);
+            reindex(&synths, &mut db);
+
+            to
+        };
 
-    pub fn rename(&self, from: impl ToString, to: impl ToString) {
+        if let Ok(mut mixer) = self.mixer.write() {
+            mixer.remove(id);
+        }
+
+        if let Ok(mut routes) = self.routes.write() {
+            let chans: Vec<_> = routes
+                .iter()
+                .filter(|(_chan, synth)| *synth == id)
+                .map(|(chan, _synth)| chan)
+                .collect();
+            chans.into_iter().for_each(|chan| routes.set(chan, None));
+        }
+
+        if let Some(to) = &to {
+            if let Ok(mut seq) = self.sequencer.lock() {
+                seq.rename_dev(id, to);
+            }
+
+            rename_dev(sections, id, to);
+        }
+
+        Ok(to)
+    }
+
+    /// swap the engine behind `id` for a freshly built one of kind `engine`. the name, and
+    /// everything pointing at it, stays the same.
+    pub fn set_engine(&self, id: &str, engine: EngineKind) -> Result<(), SynthError> {
+        let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+        let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(slot) = db.get_mut(id) else {
+            return Err(SynthError::Missing(id.into()));
+        };
+
+        synths[slot.index].1 = Instrument::from(engine);
+        slot.engine = engine;
+        slot.sf2 = None;
+        slot.sampler = None;
+
+        Ok(())
+    }
+
+    /// make `id` a SoundFont instrument, playing the file and preset in `conf`.
+    pub fn load_sf2(&self, id: &str, conf: Sf2Conf) -> Result<(), SynthError> {
+        if !self.has_synth(id) {
+            return Err(SynthError::Missing(id.into()));
+        }
+
+        // read the file before locking anything, so the audio thread isn't held up
+        let engine =
+            Sf2Engine::load(&conf.path, conf.bank, conf.preset).map_err(SynthError::Load)?;
+
+        self.set_sf2(id, engine, conf)
+    }
+
+    /// make `id` a SoundFont instrument, playing the first preset of the file at `path`.
+    pub fn open_sf2(&self, id: &str, path: PathBuf) -> Result<Sf2Conf, SynthError> {
+        if !self.has_synth(id) {
+            return Err(SynthError::Missing(id.into()));
+        }
+
+        let engine = Sf2Engine::open(&path).map_err(SynthError::Load)?;
+        let (bank, preset) = engine.selected().unwrap_or_default();
+        let conf = Sf2Conf { path, bank, preset };
+
+        self.set_sf2(id, engine, conf.clone())?;
+
+        Ok(conf)
+    }
+
+    fn set_sf2(&self, id: &str, engine: Sf2Engine, conf: Sf2Conf) -> Result<(), SynthError> {
+        let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+        let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(slot) = db.get_mut(id) else {
+            return Err(SynthError::Missing(id.into()));
+        };
+
+        synths[slot.index].1 = Instrument::Sf2(engine);
+        slot.engine = EngineKind::Sf2;
+        slot.sf2 = Some(conf);
+        slot.sampler = None;
+
+        Ok(())
+    }
+
+    /// switch the SoundFont instrument `id` to another preset of the same file.
+    pub fn select_preset(&self, id: &str, bank: u16, preset: u16) -> Result<(), SynthError> {
+        let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+        let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(slot) = db.get_mut(id) else {
+            return Err(SynthError::Missing(id.into()));
+        };
+        let (Instrument::Sf2(engine), Some(conf)) = (&mut synths[slot.index].1, &mut slot.sf2)
+        else {
+            return Err(SynthError::Load(format!("{id} isn't playing a SoundFont")));
+        };
+
+        engine.select(bank, preset).map_err(SynthError::Load)?;
+        conf.bank = bank;
+        conf.preset = preset;
+
+        Ok(())
+    }
+
+    /// the SoundFont that `id` plays, if it plays one.
+    pub fn sf2(&self, id: &str) -> Option<Sf2Conf> {
+        self.db.read().ok()?.get(id)?.sf2.clone()
+    }
+
+    /// (bank, preset, name) of every preset of the SoundFont that `id` plays.
+    pub fn sf2_presets(&self, id: &str) -> Vec<(u16, u16, String)> {
+        let (Ok(synths), Ok(db)) = (self.synths.read(), self.db.read()) else {
+            return Vec::new();
+        };
+
+        match db.get(id).map(|slot| &synths[slot.index].1) {
+            Some(Instrument::Sf2(engine)) => engine.presets(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// make `id` a sampler, playing the pads in `conf`. files the sampler already had loaded
+    /// aren't read again, so changing the settings of a pad is cheap.
+    pub fn set_sampler(&self, id: &str, conf: SamplerConf) -> Result<(), SynthError> {
+        let loaded = {
+            let synths = self.synths.read().unwrap_or_else(PoisonError::into_inner);
+            let db = self.db.read().unwrap_or_else(PoisonError::into_inner);
+
+            match db.get(id).map(|slot| &synths[slot.index].1) {
+                Some(Instrument::Sampler(sampler)) => sampler.samples().to_vec(),
+                Some(_) => Vec::new(),
+                None => return Err(SynthError::Missing(id.into())),
+            }
+        };
+
+        // read the files without holding any locks, so the audio thread isn't held up
+        let sampler = Sampler::new(conf.clone(), &loaded).map_err(SynthError::Load)?;
+
+        let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+        let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+        let Some(slot) = db.get_mut(id) else {
+            return Err(SynthError::Missing(id.into()));
+        };
+
+        synths[slot.index].1 = Instrument::Sampler(sampler);
+        slot.engine = EngineKind::Sampler;
+        slot.sf2 = None;
+        slot.sampler = Some(conf);
+
+        Ok(())
+    }
+
+    /// the pads that `id` plays, if it's a sampler with any.
+    pub fn sampler(&self, id: &str) -> Option<SamplerConf> {
+        self.db.read().ok()?.get(id)?.sampler.clone()
+    }
+
+    /// the instruments that are loaded, in order.
+    pub fn instruments(&self) -> Vec<InstrumentConf> {
+        self.list_synths()
+            .into_iter()
+            .map(|(id, engine)| InstrumentConf {
+                sf2: self.sf2(&id),
+                sampler: self.sampler(&id),
+                id,
+                engine,
+            })
+            .collect()
+    }
+
+    /// replace every loaded instrument with freshly built ones, as when loading a project.
+    /// instruments that share a name with an earlier one are skipped.
+    pub fn load_instruments(&self, instruments: &[InstrumentConf]) {
+        self.stop();
+
+        {
+            let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+            let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+
+            synths.clear();
+            db.clear();
+        }
+
+        for conf in instruments {
+            if let Err(e) = self.add_synth(&conf.id, conf.engine) {
+                warn!("{e}");
+                continue;
+            }
+
+            let loaded = match (conf.sf2.clone(), conf.sampler.clone()) {
+                (Some(sf2), _) => self.load_sf2(&conf.id, sf2),
+                (None, Some(sampler)) => self.set_sampler(&conf.id, sampler),
+                (None, None) => Ok(()),
+            };
+
+            // still there, so sections that play it keep pointing somewhere, just silent. it
+            // keeps what it should play too, so saving doesn't lose it
+            if let Err(e) = loaded {
+                warn!("{e}");
+                self.remember(conf);
+            }
+        }
+    }
+
+    /// hold on to the SoundFont or pads `conf` says to play, without playing them, for when their
+    /// files couldn't be read.
+    fn remember(&self, conf: &InstrumentConf) {
+        let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(slot) = db.get_mut(&conf.id) {
+            slot.sf2 = conf.sf2.clone();
+            slot.sampler = conf.sampler.clone();
+        }
+    }
+
+    /// rename a synth, and point everything that used the old name at the new one: `sections`,
+    /// the midi channel routes and whatever the sequencer is playing.
+    pub fn rename(
+        &self,
+        from: impl ToString,
+        to: impl ToString,
+        sections: &mut [Track],
+    ) -> Result<(), SynthError> {
         let from = from.to_string();
         let to = to.to_string();
 
-        self.synths.read().iter_mut().for_each(|(id, _synth)| { if id.to_owned() == from {
-            *id = from; 
-        }});
+        {
+            let mut synths = self.synths.write().unwrap_or_else(PoisonError::into_inner);
+            let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+
+            if !db.contains_key(&from) {
+                return Err(SynthError::Missing(from));
+            } else if from == to {
+                return Ok(());
+            } else if db.contains_key(&to) {
+                return Err(SynthError::Duplicate(to));
+            }
+
+            let Some(slot) = db.remove(&from) else {
+                return Err(SynthError::Missing(from));
+            };
+            synths[slot.index].0 = to.clone();
+            db.insert(to.clone(), slot);
+        }
+
+        if let Ok(mut routes) = self.routes.write() {
+            routes.rename(&from, &to);
+        }
+
+        if let Ok(mut mixer) = self.mixer.write() {
+            mixer.rename(&from, &to);
+        }
+
+        if let Ok(mut seq) = self.sequencer.lock() {
+            seq.rename_dev(&from, &to);
+        }
 
+        rename_dev(sections, &from, &to);
 
+        Ok(())
     }
 
     // #[unsafe(no_mangle)]
@@ -114,9 +953,503 @@ impl TabSynth {
     // }
 }
 
+/// points every entry in `db` back at its synth's position in `synths`, after synths have been
+/// removed or moved.
+fn reindex(synths: &[(SynthId, Instrument)], db: &mut HashMap<SynthId, SynthSlot>) {
+    for (i, (id, _synth)) in synths.iter().enumerate() {
+        if let Some(slot) = db.get_mut(id) {
+            slot.index = i;
+        }
+    }
+}
+
+/// hands sequencer events to the synth named by each event's `dev`, or to its mixer strip for
+/// volume and pan. events for an external device are sent to it as they are.
+fn dispatch<S: SynthSink>(
+    synths: &mut [(SynthId, S)],
+    mixer: &mut Mixer,
+    outputs: &MidiOutputs,
+    events: impl Iterator<Item = SeqEvent>,
+) {
+    for SeqEvent { dev, msg, .. } in events {
+        if let SeqMsg::Clock(clock) = msg {
+            for output in outputs.values() {
+                let _ = output.try_send(SeqMsg::Clock(clock));
+            }
+
+            continue;
+        }
+
+        // the gear has its own volume and envelopes, so params have nowhere to go
+        if let Some(output) = outputs.get(&dev) {
+            if let SeqMsg::Midi(_) = msg {
+                let _ = output.try_send(msg);
+            }
+
+            continue;
+        }
+
+        if let SeqMsg::Midi(MidiMessage::ControlChange(_, ControlEvent { control, value })) = msg
+            && mixer.control_change(&dev, control, value)
+        {
+            continue;
+        }
+
+        let Some((_id, synth)) = synths.iter_mut().find(|(id, _synth)| *id == dev) else {
+            continue;
+        };
+
+        match msg {
+            // on top of the strip's gain, so the fader stays where it was put
+            SeqMsg::Param(volume @ Sf2Cmd::Volume(_)) => mixer.set_volume(&dev, volume.amount()),
+            SeqMsg::Midi(msg) => router::apply(synth, &msg),
+            SeqMsg::Param(param) => {
+                if !router::apply_param(synth, &param) {
+                    debug!("{dev} has nothing for {param:?} to set");
+                }
+            }
+            SeqMsg::Clock(_) => {}
+        }
+    }
+}
+
 // #[unsafe(no_mangle)]
 pub fn make_synth() -> (TabSynth, OutputDevice) {
     let (mut synth, dev) = TabSynth::new();
 
     (synth, dev)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playback::{clock::PPQN, samples_per_step, DEFAULT_BPM};
+    use router::tests::FakeSink;
+    use sampler::Pad;
+    use std::time::Duration;
+
+    #[test]
+    fn add_and_list() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Bass", EngineKind::WaveTable).unwrap();
+
+        assert_eq!(
+            synth.list_synths(),
+            vec![
+                ("Default".to_string(), EngineKind::WaveTable),
+                ("Bass".to_string(), EngineKind::WaveTable),
+            ]
+        );
+        assert_eq!(
+            synth.add_synth("Bass", EngineKind::WaveTable),
+            Err(SynthError::Duplicate("Bass".into()))
+        );
+        assert!(synth.has_synth("Bass"));
+        assert_eq!(synth.engine("Lead"), None);
+    }
+
+    #[test]
+    fn remove_keeps_db_in_step() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Bass", EngineKind::WaveTable).unwrap();
+        synth.add_synth("Lead", EngineKind::WaveTable).unwrap();
+        synth.set_route(Channel::Ch2, Some("Bass".into()));
+
+        synth.remove_synth("Bass", &mut []).unwrap();
+
+        assert_eq!(
+            synth.remove_synth("Bass", &mut []),
+            Err(SynthError::Missing("Bass".into()))
+        );
+        assert_eq!(synth.routes().get(Channel::Ch2), None);
+
+        let synths = synth.synths.read().unwrap();
+        let db = synth.db.read().unwrap();
+        assert_eq!(db.len(), synths.len());
+
+        for (i, (id, _synth)) in synths.iter().enumerate() {
+            assert_eq!(db[id].index, i, "{id}");
+        }
+    }
+
+    #[test]
+    fn removed_synths_hand_their_sections_on() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Bass", EngineKind::WaveTable).unwrap();
+        let mut sections = vec![
+            Track::new(None, 0, "Bass".into()),
+            Track::new(None, 1, "Gear".into()),
+        ];
+
+        assert_eq!(synth.remove_synth("Bass", &mut sections), Ok(Some("Default".into())));
+        assert_eq!(
+            sections.iter().map(|section| section.dev.as_str()).collect::<Vec<_>>(),
+            vec!["Default", "Gear"]
+        );
+
+        // with nowhere for them to go, it stays
+        assert_eq!(
+            synth.remove_synth("Default", &mut sections),
+            Err(SynthError::InUse("Default".into()))
+        );
+        assert!(synth.has_synth("Default"));
+        assert_eq!(synth.remove_synth("Default", &mut sections[1..]), Ok(None));
+    }
+
+    #[test]
+    fn rename() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Bass", EngineKind::WaveTable).unwrap();
+        synth.set_route(Channel::Ch3, Some("Default".into()));
+        let mut sections = vec![
+            Track::new(None, 0, "Default".into()),
+            Track::new(None, 1, "Bass".into()),
+            Track::new(None, 2, "Default".into()),
+        ];
+
+        synth.rename("Default", "Lead", &mut sections).unwrap();
+
+        assert_eq!(
+            synth.list_synths(),
+            vec![
+                ("Lead".to_string(), EngineKind::WaveTable),
+                ("Bass".to_string(), EngineKind::WaveTable),
+            ]
+        );
+        assert!(!synth.has_synth("Default"));
+        assert_eq!(synth.db.read().unwrap()["Lead"].index, 0);
+        assert_eq!(
+            sections.iter().map(|section| section.dev.as_str()).collect::<Vec<_>>(),
+            vec!["Lead", "Bass", "Lead"]
+        );
+        assert_eq!(synth.routes().get(Channel::Ch1), Some(&"Lead".to_string()));
+        assert_eq!(synth.routes().get(Channel::Ch3), Some(&"Lead".to_string()));
+    }
+
+    #[test]
+    fn mixer_follows_synths() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Bass", EngineKind::WaveTable).unwrap();
+        synth.set_strip("Bass", ChannelStrip { gain: 0.5, ..Default::default() });
+
+        synth.rename("Bass", "Sub", &mut []).unwrap();
+        assert_eq!(synth.mixer().strip("Sub").gain, 0.5);
+
+        synth.remove_synth("Sub", &mut []).unwrap();
+        assert!(synth.mixer().strips.is_empty());
+    }
+
+    #[test]
+    fn volume_cc_goes_to_the_mixer() {
+        let synth = TabSynth::headless();
+        synth.midi_input(&MidiMessage::ControlChange(
+            Channel::Ch1,
+            ControlEvent {
+                control: mixer::VOLUME_CC,
+                value: 0,
+            },
+        ));
+
+        assert_eq!(synth.mixer().strip("Default").gain, 0.0);
+    }
+
+    #[test]
+    fn rename_errors_leave_everything_alone() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Bass", EngineKind::WaveTable).unwrap();
+        let mut sections = vec![Track::new(None, 0, "Default".into())];
+        let before = synth.list_synths();
+
+        assert_eq!(
+            synth.rename("Lead", "Keys", &mut sections),
+            Err(SynthError::Missing("Lead".into()))
+        );
+        assert_eq!(
+            synth.rename("Default", "Bass", &mut sections),
+            Err(SynthError::Duplicate("Bass".into()))
+        );
+        assert_eq!(synth.rename("Default", "Default", &mut sections), Ok(()));
+        assert_eq!(synth.list_synths(), before);
+        assert_eq!(sections[0].dev, "Default");
+    }
+
+    #[test]
+    fn playhead_updates() {
+        let synth = TabSynth::headless();
+        let updates = synth.playhead_updates();
+        let mut events = Vec::new();
+        synth.play(vec![Track::new(None, 4, "Default".into())]);
+
+        synth.next_sample(&mut events);
+        synth.next_sample(&mut events);
+        assert_eq!(updates.try_recv(), Ok(Some(Playhead { block: 0, row: 0 })));
+        // nothing new until the next step
+        assert!(updates.try_recv().is_err());
+        assert_eq!(synth.block_sections(0), vec![4]);
+
+        synth.stop();
+        assert_eq!(updates.try_iter().last(), Some(None));
+    }
+
+    #[test]
+    fn buffers_play_events_on_their_samples() {
+        let track = || {
+            let mut track = Track::new(None, 0, "Default".into());
+            track.steps[0].note = Some(60);
+            track.steps[1].note = Some(64);
+            track
+        };
+        let (by_sample, by_buffer) = (TabSynth::headless(), TabSynth::headless());
+        by_sample.play(vec![track()]);
+        by_buffer.play(vec![track()]);
+        let n = 3 * samples_per_step(DEFAULT_BPM, DEFAULT_LPB) as usize;
+        let mut events = Vec::new();
+
+        let expected: Vec<_> = (0..n).map(|_| by_sample.next_sample(&mut events)).collect();
+        let mut frames = Vec::new();
+        // buffers that don't line up with the steps
+        for _ in 0..n.div_ceil(1000) {
+            by_buffer.next_frames(1000, &mut events, |_, frame| frames.push(frame));
+        }
+
+        assert_eq!(frames[..n], expected[..]);
+    }
+
+    #[test]
+    fn following_an_external_clock() {
+        let synth = TabSynth::headless();
+        let start = Instant::now();
+        let bpm = || synth.sequencer.lock().unwrap().bpm();
+        synth.set_clock_mode(ClockMode::Follow);
+        synth.play(vec![Track::new(None, 0, "Default".into())]);
+        assert!(synth.is_paused());
+
+        for i in 0..=PPQN * 2 {
+            let tick = Duration::from_secs_f64(60.0 / (90.0 * PPQN as f64));
+            synth.clock_input(ClockMsg::Tick, start + tick * i as u32);
+        }
+        assert_eq!(bpm(), 90.0);
+        // the clock's tempo beats the one set by hand
+        synth.set_tempo(120.0, 4);
+        assert_eq!(bpm(), 90.0);
+
+        synth.clock_input(ClockMsg::Start, Instant::now());
+        assert!(synth.is_playing());
+        synth.clock_input(ClockMsg::Stop, Instant::now());
+        assert!(synth.is_paused());
+
+        // and it's all ignored when not following
+        synth.set_clock_mode(ClockMode::Internal);
+        synth.clock_input(ClockMsg::Continue, Instant::now());
+        assert!(synth.is_paused());
+    }
+
+    #[test]
+    fn notes_played() {
+        let synth = TabSynth::headless();
+        let played = synth.notes_played();
+        let key = |key, value| KeyEvent { key, value };
+
+        synth.midi_input(&MidiMessage::NoteOn(Channel::Ch2, key(60, 90)));
+        // releases, however they're sent, aren't
+        synth.midi_input(&MidiMessage::NoteOn(Channel::Ch2, key(60, 0)));
+        synth.midi_input(&MidiMessage::NoteOff(Channel::Ch2, key(60, 0)));
+        let notes: Vec<_> = played.try_iter().collect();
+        assert_eq!(notes.len(), 1);
+        assert_eq!((notes[0].note, notes[0].velocity, notes[0].position), (60, 90, None));
+
+        // while playing, they know where they landed
+        let mut events = Vec::new();
+        synth.play(vec![Track::new(None, 0, "Default".into())]);
+        synth.next_sample(&mut events);
+        synth.midi_input(&MidiMessage::NoteOn(Channel::Ch1, key(64, 100)));
+        let (playhead, _phase) = played.try_recv().unwrap().position.unwrap();
+        assert_eq!(playhead, Playhead { block: 0, row: 0 });
+    }
+
+    #[test]
+    fn params_reach_the_engine_and_mixer() {
+        let mut synths = vec![("Default".to_string(), FakeSink::default())];
+        let mut mixer = Mixer::default();
+        let param = |dev: &str, param| SeqEvent {
+            at: 0,
+            dev: dev.into(),
+            msg: SeqMsg::Param(param),
+        };
+
+        dispatch(
+            &mut synths,
+            &mut mixer,
+            &MidiOutputs::default(),
+            [
+                param("Default", Sf2Cmd::Atk(127)),
+                param("Default", Sf2Cmd::Sus(64)),
+                param("Default", Sf2Cmd::Volume(0.25)),
+                param("Missing", Sf2Cmd::Rel(0)),
+                param("Missing", Sf2Cmd::Volume(0.5)),
+            ]
+            .into_iter(),
+        );
+
+        // the fake has no sustain knob
+        assert_eq!(synths[0].1.calls, vec!["knob 0 1.00"]);
+        assert_eq!(mixer.volume("Default"), 0.25);
+        // the faders stay put, and no strips appear for synths that aren't there
+        assert_eq!(mixer.strip("Default").gain, 1.0);
+        assert!(mixer.strips.is_empty() && !mixer.volumes.contains_key("Missing"));
+    }
+
+    #[test]
+    fn set_engine() {
+        let synth = TabSynth::headless();
+
+        assert_eq!(synth.set_engine("Default", EngineKind::WaveTable), Ok(()));
+        assert_eq!(
+            synth.set_engine("Lead", EngineKind::WaveTable),
+            Err(SynthError::Missing("Lead".into()))
+        );
+    }
+
+    fn tiny_sf2(bank: u16) -> Sf2Conf {
+        Sf2Conf {
+            path: concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/tiny.sf2").into(),
+            bank,
+            preset: 0,
+        }
+    }
+
+    #[test]
+    fn sf2_instruments() {
+        let synth = TabSynth::headless();
+        synth.add_synth("Kit", EngineKind::Sf2).unwrap();
+
+        assert_eq!(synth.open_sf2("Kit", tiny_sf2(0).path), Ok(tiny_sf2(0)));
+        assert_eq!(synth.load_sf2("Kit", tiny_sf2(128)), Ok(()));
+        assert_eq!(synth.sf2("Kit"), Some(tiny_sf2(128)));
+        assert_eq!(synth.sf2_presets("Kit").len(), 2);
+        assert_eq!(synth.sf2_presets("Default"), vec![]);
+
+        assert_eq!(synth.select_preset("Kit", 0, 0), Ok(()));
+        assert_eq!(synth.sf2("Kit"), Some(tiny_sf2(0)));
+        assert!(synth.select_preset("Kit", 9, 9).is_err());
+        assert!(synth.select_preset("Default", 0, 0).is_err());
+
+        let missing = Sf2Conf {
+            path: "no-such.sf2".into(),
+            ..tiny_sf2(0)
+        };
+        assert!(matches!(synth.load_sf2("Kit", missing), Err(SynthError::Load(_))));
+        assert_eq!(
+            synth.load_sf2("Lead", tiny_sf2(0)),
+            Err(SynthError::Missing("Lead".into()))
+        );
+
+        // the wave table can take its place again, and forgets the SoundFont
+        synth.set_engine("Kit", EngineKind::WaveTable).unwrap();
+        assert_eq!(synth.sf2("Kit"), None);
+    }
+
+    #[test]
+    fn sf2_instruments_come_back_with_a_project() {
+        let synth = TabSynth::headless();
+        let instruments = vec![InstrumentConf {
+            id: "Keys".into(),
+            engine: EngineKind::Sf2,
+            sf2: Some(tiny_sf2(0)),
+            sampler: None,
+        }];
+
+        synth.load_instruments(&instruments);
+
+        assert_eq!(synth.instruments(), instruments);
+        assert_eq!(synth.sf2_presets("Keys").len(), 2);
+    }
+
+    #[test]
+    fn sampler_instruments() {
+        let synth = TabSynth::headless();
+        let kick = sampler::tests::wav("synth-kick", 1, &[0.5; 100]);
+        let conf = SamplerConf {
+            pads: vec![Pad::new(kick.clone(), 36)],
+            ..Default::default()
+        };
+        synth.add_synth("Drums", EngineKind::WaveTable).unwrap();
+
+        assert_eq!(synth.set_sampler("Drums", conf.clone()), Ok(()));
+        assert_eq!(synth.engine("Drums"), Some(EngineKind::Sampler));
+        assert_eq!(synth.sampler("Drums"), Some(conf.clone()));
+        assert_eq!(
+            synth.set_sampler("Lead", conf.clone()),
+            Err(SynthError::Missing("Lead".into()))
+        );
+
+        let missing = SamplerConf {
+            pads: vec![Pad::new("no-such.wav".into(), 36)],
+            ..Default::default()
+        };
+        assert!(matches!(synth.set_sampler("Drums", missing), Err(SynthError::Load(_))));
+        assert_eq!(synth.sampler("Drums"), Some(conf.clone()));
+
+        let instruments = synth.instruments();
+        synth.load_instruments(&instruments);
+        assert_eq!(synth.sampler("Drums"), Some(conf));
+
+        synth.set_engine("Drums", EngineKind::Sf2).unwrap();
+        assert_eq!(synth.sampler("Drums"), None);
+    }
+
+    #[test]
+    fn missing_files_are_kept_in_the_project() {
+        let synth = TabSynth::headless();
+        let instruments = vec![
+            InstrumentConf {
+                id: "Keys".into(),
+                engine: EngineKind::Sf2,
+                sf2: Some(Sf2Conf {
+                    path: "no-such.sf2".into(),
+                    bank: 128,
+                    preset: 3,
+                }),
+                sampler: None,
+            },
+            InstrumentConf {
+                id: "Drums".into(),
+                engine: EngineKind::Sampler,
+                sf2: None,
+                sampler: Some(SamplerConf {
+                    pads: vec![Pad::new("no-such.wav".into(), 36)],
+                    ..Default::default()
+                }),
+            },
+        ];
+
+        synth.load_instruments(&instruments);
+
+        assert_eq!(synth.instruments(), instruments);
+        assert!(synth.sf2_presets("Keys").is_empty());
+    }
+
+    #[test]
+    fn load_instruments_replaces_everything() {
+        let synth = TabSynth::headless();
+        let instruments = vec![
+            InstrumentConf {
+                id: "Bass".into(),
+                engine: EngineKind::WaveTable,
+                sf2: None,
+                sampler: None,
+            },
+            InstrumentConf {
+                id: "Lead".into(),
+                engine: EngineKind::WaveTable,
+                sf2: None,
+                sampler: None,
+            },
+        ];
+
+        synth.load_instruments(&instruments);
+
+        assert_eq!(synth.instruments(), instruments);
+        assert!(!synth.has_synth("Default"));
+    }
+}
@@ -0,0 +1,460 @@
+use crate::{
+    SynthId,
+    playback::ALL_NOTES_OFF_CC,
+    tracks::{CHANNELS, Sf2Cmd, channel_index},
+};
+use crossbeam::channel::Receiver;
+use midi_control::{Channel, ControlEvent, KeyEvent, MidiMessage};
+use std::thread::{JoinHandle, spawn};
+use stepper_synth_backend::{
+    KnobCtrl, MidiControlled,
+    synth_engines::{SynthChannel, SynthEngine, SynthModule},
+};
+use tracing::*;
+
+/// the first of the eight CCs (70-77) that turn the knobs of an engine.
+pub const FIRST_KNOB_CC: u8 = 70;
+pub const N_KNOBS: u8 = 8;
+/// the mod wheel, which swells the volume of engines that support it.
+pub const VOLUME_SWELL_CC: u8 = 1;
+/// pitch bends closer to center than this are treated as no bend at all.
+const BEND_DEAD_ZONE: f32 = 0.02;
+
+/// something that can be played by midi. `SynthChannel` is the real one, tests use a fake.
+pub trait SynthSink {
+    fn play(&mut self, note: u8, velocity: u8);
+    fn stop(&mut self, note: u8);
+    /// `amount` is from -1.0 (full bend down) to 1.0 (full bend up).
+    fn bend(&mut self, amount: f32);
+    fn unbend(&mut self);
+    /// `knob` is zero based and `value` is from 0.0 to 1.0. returns false if the engine has no
+    /// such knob.
+    fn knob(&mut self, knob: u8, value: f32) -> bool;
+    fn volume_swell(&mut self, value: f32) -> bool;
+    /// hands `msg` to engines that read CCs themselves. returns false if the engine wants CCs
+    /// mapped to its knobs instead.
+    fn raw_midi(&mut self, msg: &MidiMessage) -> bool;
+    /// the zero based knob that sets the envelope stage `param` is for, if the engine has one.
+    fn param_knob(&self, param: &Sf2Cmd) -> Option<u8>;
+    /// start the next note `amount` (0.0 to 1.0) of the way into its sample. returns false if
+    /// the engine doesn't play samples.
+    fn sample_offset(&mut self, amount: f32) -> bool;
+}
+
+impl SynthSink for SynthChannel {
+    fn play(&mut self, note: u8, velocity: u8) {
+        self.engine.play(note, velocity)
+    }
+
+    fn stop(&mut self, note: u8) {
+        self.engine.stop(note)
+    }
+
+    fn bend(&mut self, amount: f32) {
+        self.engine.bend(amount)
+    }
+
+    fn unbend(&mut self) {
+        self.engine.unbend()
+    }
+
+    fn knob(&mut self, knob: u8, value: f32) -> bool {
+        match knob {
+            0 => self.engine.knob_1(value),
+            1 => self.engine.knob_2(value),
+            2 => self.engine.knob_3(value),
+            3 => self.engine.knob_4(value),
+            4 => self.engine.knob_5(value),
+            5 => self.engine.knob_6(value),
+            6 => self.engine.knob_7(value),
+            7 => self.engine.knob_8(value),
+            _ => false,
+        }
+    }
+
+    fn volume_swell(&mut self, value: f32) -> bool {
+        self.engine.volume_swell(value)
+    }
+
+    fn raw_midi(&mut self, msg: &MidiMessage) -> bool {
+        match self.engine {
+            SynthModule::WaveTable(ref mut wt) => {
+                wt.synth.midi_input(msg);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn param_knob(&self, param: &Sf2Cmd) -> Option<u8> {
+        match (&self.engine, param) {
+            // the wave table's first four knobs are its ADSR.
+            (SynthModule::WaveTable(_), Sf2Cmd::Atk(_)) => Some(0),
+            (SynthModule::WaveTable(_), Sf2Cmd::Dcy(_)) => Some(1),
+            (SynthModule::WaveTable(_), Sf2Cmd::Sus(_)) => Some(2),
+            (SynthModule::WaveTable(_), Sf2Cmd::Rel(_)) => Some(3),
+            _ => None,
+        }
+    }
+
+    fn sample_offset(&mut self, _amount: f32) -> bool {
+        false
+    }
+}
+
+/// which synth each midi channel plays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelRoutes([Option<SynthId>; 16]);
+
+impl Default for ChannelRoutes {
+    /// channel 1 plays the default synth, everything else is unassigned.
+    fn default() -> Self {
+        let mut routes = Self(Default::default());
+        routes.set(Channel::Ch1, Some("Default".into()));
+
+        routes
+    }
+}
+
+impl ChannelRoutes {
+    pub fn get(&self, chan: Channel) -> Option<&SynthId> {
+        self.0[channel_index(chan) as usize].as_ref()
+    }
+
+    pub fn set(&mut self, chan: Channel, synth: Option<SynthId>) {
+        self.0[channel_index(chan) as usize] = synth;
+    }
+
+    /// every channel and the synth it plays, skipping unassigned channels.
+    pub fn iter(&self) -> impl Iterator<Item = (Channel, &SynthId)> {
+        CHANNELS
+            .into_iter()
+            .zip(self.0.iter())
+            .filter_map(|(chan, synth)| synth.as_ref().map(|synth| (chan, synth)))
+    }
+
+    /// point every channel that played `from` at `to` instead.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        self.0
+            .iter_mut()
+            .flatten()
+            .filter(|synth| *synth == from)
+            .for_each(|synth| *synth = to.to_string());
+    }
+}
+
+/// the channel a message was sent on, if it is a channel message.
+pub fn channel_of(msg: &MidiMessage) -> Option<Channel> {
+    match msg {
+        MidiMessage::NoteOff(chan, _)
+        | MidiMessage::NoteOn(chan, _)
+        | MidiMessage::PolyKeyPressure(chan, _)
+        | MidiMessage::ControlChange(chan, _)
+        | MidiMessage::ProgramChange(chan, _)
+        | MidiMessage::ChannelPressure(chan, _)
+        | MidiMessage::PitchBend(chan, _, _) => Some(*chan),
+        _ => None,
+    }
+}
+
+/// converts the two 7-bit halves of a pitch bend to -1.0..=1.0.
+pub fn bend_amount(lsb: u8, msb: u8) -> f32 {
+    let value = ((msb as u16 & 0x7F) << 7) | (lsb as u16 & 0x7F);
+
+    ((value as f32 - 8192.0) / 8192.0).clamp(-1.0, 1.0)
+}
+
+/// play `msg` on `sink`, whatever channel it came in on.
+pub fn apply(sink: &mut impl SynthSink, msg: &MidiMessage) {
+    match msg {
+        MidiMessage::NoteOn(_, KeyEvent { key, value: 0 }) => sink.stop(*key),
+        MidiMessage::NoteOn(_, KeyEvent { key, value }) => sink.play(*key, *value),
+        MidiMessage::NoteOff(_, KeyEvent { key, value: _ }) => sink.stop(*key),
+        MidiMessage::PitchBend(_, lsb, msb) => {
+            let bend = bend_amount(*lsb, *msb);
+
+            if bend.abs() > BEND_DEAD_ZONE {
+                sink.bend(bend);
+            } else {
+                sink.unbend();
+            }
+        }
+        MidiMessage::ControlChange(_, ControlEvent { control, value: _ })
+            if *control == ALL_NOTES_OFF_CC =>
+        {
+            (0..=127).for_each(|key| sink.stop(key));
+        }
+        MidiMessage::ControlChange(_, ControlEvent { control, value }) => {
+            if sink.raw_midi(msg) {
+                return;
+            }
+
+            let value = *value as f32 / 127.0;
+
+            match *control {
+                knob if (FIRST_KNOB_CC..FIRST_KNOB_CC + N_KNOBS).contains(&knob) => {
+                    sink.knob(knob - FIRST_KNOB_CC, value)
+                }
+                VOLUME_SWELL_CC => sink.volume_swell(value),
+                _ => {
+                    // info!("CC message => {control}-{value}");
+                    false
+                }
+            };
+        }
+        MidiMessage::Invalid => {
+            // error!("system received an invalid MIDI message.");
+        }
+        _ => {}
+    }
+}
+
+/// set an envelope stage of `sink`, by turning the knob it has for it, or where its next sample
+/// starts. returns false if it has no such knob, or doesn't play samples.
+pub fn apply_param(sink: &mut impl SynthSink, param: &Sf2Cmd) -> bool {
+    match param {
+        Sf2Cmd::Offset(_) => sink.sample_offset(param.amount()),
+        _ => sink.param_knob(param).is_some_and(|knob| sink.knob(knob, param.amount())),
+    }
+}
+
+/// play `msg` on the synth that its channel is assigned to. messages on unassigned channels, or
+/// assigned to synths that don't exist, are dropped.
+pub fn route<S: SynthSink>(routes: &ChannelRoutes, synths: &mut [(SynthId, S)], msg: &MidiMessage) {
+    let Some(dev) = channel_of(msg).and_then(|chan| routes.get(chan)) else {
+        return;
+    };
+
+    if let Some((_id, synth)) = synths.iter_mut().find(|(id, _synth)| id == dev) {
+        apply(synth, msg);
+    }
+}
+
+/// drains `recv` on its own thread, handing every message to `handle`. stops when every sender
+/// has been dropped.
+pub fn spawn_router<M: Send + 'static>(
+    recv: Receiver<M>,
+    mut handle: impl FnMut(M) + Send + 'static,
+) -> JoinHandle<()> {
+    spawn(move || {
+        while let Ok(msg) = recv.recv() {
+            handle(msg);
+        }
+
+        info!("midi router stopped");
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::tracks::ENVELOPE_MAX;
+    use crossbeam::channel::unbounded;
+    use std::sync::{Arc, Mutex};
+
+    /// records everything it is asked to do. its first and last knobs are attack and release.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub(crate) struct FakeSink {
+        pub(crate) calls: Vec<String>,
+        pub(crate) takes_raw_midi: bool,
+    }
+
+    impl SynthSink for FakeSink {
+        fn play(&mut self, note: u8, velocity: u8) {
+            self.calls.push(format!("play {note} {velocity}"));
+        }
+
+        fn stop(&mut self, note: u8) {
+            self.calls.push(format!("stop {note}"));
+        }
+
+        fn bend(&mut self, amount: f32) {
+            self.calls.push(format!("bend {amount:.2}"));
+        }
+
+        fn unbend(&mut self) {
+            self.calls.push("unbend".into());
+        }
+
+        fn knob(&mut self, knob: u8, value: f32) -> bool {
+            self.calls.push(format!("knob {knob} {value:.2}"));
+            true
+        }
+
+        fn volume_swell(&mut self, value: f32) -> bool {
+            self.calls.push(format!("swell {value:.2}"));
+            true
+        }
+
+        fn raw_midi(&mut self, msg: &MidiMessage) -> bool {
+            if self.takes_raw_midi {
+                self.calls.push(format!("raw {msg:?}"));
+            }
+
+            self.takes_raw_midi
+        }
+
+        fn param_knob(&self, param: &Sf2Cmd) -> Option<u8> {
+            match param {
+                Sf2Cmd::Atk(_) => Some(0),
+                Sf2Cmd::Rel(_) => Some(N_KNOBS - 1),
+                _ => None,
+            }
+        }
+
+        fn sample_offset(&mut self, amount: f32) -> bool {
+            self.calls.push(format!("offset {amount:.2}"));
+            true
+        }
+    }
+
+    fn note_on(chan: Channel, key: u8, value: u8) -> MidiMessage {
+        MidiMessage::NoteOn(chan, KeyEvent { key, value })
+    }
+
+    fn cc(chan: Channel, control: u8, value: u8) -> MidiMessage {
+        MidiMessage::ControlChange(chan, ControlEvent { control, value })
+    }
+
+    fn synths() -> Vec<(SynthId, FakeSink)> {
+        vec![
+            ("Default".into(), FakeSink::default()),
+            ("Bass".into(), FakeSink::default()),
+        ]
+    }
+
+    #[test]
+    fn routes_by_channel() {
+        let mut routes = ChannelRoutes::default();
+        routes.set(Channel::Ch2, Some("Bass".into()));
+        routes.set(Channel::Ch3, Some("Missing".into()));
+        let mut synths = synths();
+
+        for msg in [
+            note_on(Channel::Ch1, 60, 100),
+            note_on(Channel::Ch2, 36, 90),
+            note_on(Channel::Ch3, 40, 90),
+            note_on(Channel::Ch4, 41, 90),
+            MidiMessage::NoteOff(Channel::Ch2, KeyEvent { key: 36, value: 0 }),
+        ] {
+            route(&routes, &mut synths, &msg);
+        }
+
+        assert_eq!(synths[0].1.calls, vec!["play 60 100"]);
+        assert_eq!(synths[1].1.calls, vec!["play 36 90", "stop 36"]);
+    }
+
+    #[test]
+    fn note_on_without_velocity_stops() {
+        let mut sink = FakeSink::default();
+        apply(&mut sink, &note_on(Channel::Ch1, 60, 0));
+
+        assert_eq!(sink.calls, vec!["stop 60"]);
+    }
+
+    #[test]
+    fn pitch_bend() {
+        let mut sink = FakeSink::default();
+
+        for (lsb, msb) in [(0x00, 0x40), (0x7F, 0x7F), (0x00, 0x00), (0x01, 0x40)] {
+            apply(&mut sink, &MidiMessage::PitchBend(Channel::Ch1, lsb, msb));
+        }
+
+        assert_eq!(sink.calls, vec!["unbend", "bend 1.00", "bend -1.00", "unbend"]);
+    }
+
+    #[test]
+    fn knob_ccs() {
+        let mut sink = FakeSink::default();
+
+        for control in FIRST_KNOB_CC..FIRST_KNOB_CC + N_KNOBS {
+            apply(&mut sink, &cc(Channel::Ch1, control, 127));
+        }
+        apply(&mut sink, &cc(Channel::Ch1, VOLUME_SWELL_CC, 0));
+        apply(&mut sink, &cc(Channel::Ch1, 10, 64));
+
+        assert_eq!(
+            sink.calls,
+            vec![
+                "knob 0 1.00",
+                "knob 1 1.00",
+                "knob 2 1.00",
+                "knob 3 1.00",
+                "knob 4 1.00",
+                "knob 5 1.00",
+                "knob 6 1.00",
+                "knob 7 1.00",
+                "swell 0.00",
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_midi_engines_skip_the_knob_mapping() {
+        let mut sink = FakeSink {
+            takes_raw_midi: true,
+            ..Default::default()
+        };
+        let msg = cc(Channel::Ch1, FIRST_KNOB_CC, 127);
+        apply(&mut sink, &msg);
+
+        assert_eq!(sink.calls, vec![format!("raw {msg:?}")]);
+    }
+
+    #[test]
+    fn params_turn_knobs() {
+        let mut sink = FakeSink::default();
+
+        assert!(apply_param(&mut sink, &Sf2Cmd::Atk(127)));
+        assert!(apply_param(&mut sink, &Sf2Cmd::Rel(0)));
+        assert!(!apply_param(&mut sink, &Sf2Cmd::Dcy2(64)));
+        assert!(apply_param(&mut sink, &Sf2Cmd::Offset(ENVELOPE_MAX)));
+
+        assert_eq!(sink.calls, vec!["knob 0 1.00", "knob 7 0.00", "offset 1.00"]);
+    }
+
+    #[test]
+    fn all_notes_off() {
+        let mut sink = FakeSink::default();
+        apply(&mut sink, &cc(Channel::Ch1, ALL_NOTES_OFF_CC, 0));
+
+        assert_eq!(sink.calls.len(), 128);
+        assert!(sink.calls.iter().all(|call| call.starts_with("stop")));
+    }
+
+    #[test]
+    fn rename_follows_the_synth() {
+        let mut routes = ChannelRoutes::default();
+        routes.set(Channel::Ch5, Some("Default".into()));
+        routes.rename("Default", "Lead");
+
+        assert_eq!(
+            routes.iter().collect::<Vec<_>>(),
+            vec![
+                (Channel::Ch1, &"Lead".to_string()),
+                (Channel::Ch5, &"Lead".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn router_thread_drains_the_channel() {
+        let (send, recv) = unbounded();
+        let synths = Arc::new(Mutex::new(synths()));
+        let routes = ChannelRoutes::default();
+
+        let handle = spawn_router(recv, {
+            let synths = synths.clone();
+
+            move |msg| route(&routes, &mut synths.lock().unwrap(), &msg)
+        });
+
+        send.send(note_on(Channel::Ch1, 48, 90)).unwrap();
+        send.send(MidiMessage::NoteOff(Channel::Ch1, KeyEvent { key: 48, value: 90 }))
+            .unwrap();
+        drop(send);
+        handle.join().unwrap();
+
+        assert_eq!(synths.lock().unwrap()[0].1.calls, vec!["play 48 90", "stop 48"]);
+    }
+}
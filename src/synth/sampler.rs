@@ -0,0 +1,553 @@
+use super::router::SynthSink;
+use crate::tracks::Sf2Cmd;
+use hound::{SampleFormat, WavReader};
+use midi_control::MidiMessage;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use stepper_synth_backend::SAMPLE_RATE;
+
+/// the most pads that play at once. past this, the oldest is cut to make room.
+pub const MAX_VOICES: usize = 32;
+/// how long a choked or released pad takes to fade out, in seconds. just long enough not to click.
+const FADE_SECS: f32 = 0.005;
+/// how far a full pitch bend goes, in semi-tones.
+const BEND_RANGE: f64 = 2.0;
+
+/// how a sampler lays its pads out over the keyboard.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, strum_macros::Display,
+)]
+pub enum SamplerMode {
+    /// every pad has a note of its own, and plays at its own pitch. for drums.
+    #[default]
+    #[strum(to_string = "KIT")]
+    Kit,
+    /// the first pad plays on every note, pitched up or down from its `note`.
+    #[strum(to_string = "CHROM")]
+    Chromatic,
+}
+
+/// one sample, and how to play it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pad {
+    /// a wav file. stereo files are mixed down to mono.
+    pub path: PathBuf,
+    /// the note that plays it in kit mode, or the note it plays at its own pitch in chromatic
+    /// mode.
+    pub note: u8,
+    /// the first frame to play.
+    pub start: usize,
+    /// the frame to stop at, or the end of the file with `None`.
+    pub end: Option<usize>,
+    /// the frames to loop over until the note is released, cut down to fit between `start` and
+    /// `end`. a one-shot with `None`.
+    pub looping: Option<Range<usize>>,
+    /// pads in the same group cut each other off, like open and closed hats.
+    pub choke: Option<u8>,
+    /// linear, applied before the mixer.
+    pub gain: f32,
+}
+
+impl Pad {
+    /// the whole of the file at `path`, played once on `note`.
+    pub fn new(path: PathBuf, note: u8) -> Self {
+        Self {
+            path,
+            note,
+            start: 0,
+            end: None,
+            looping: None,
+            choke: None,
+            gain: 1.0,
+        }
+    }
+
+    /// the frame that playing stops at, in a sample `len` frames long.
+    fn end(&self, len: usize) -> usize {
+        self.end.unwrap_or(len).min(len)
+    }
+
+    /// the frames it loops over, in a sample `len` frames long.
+    fn loop_range(&self, len: usize) -> Option<Range<usize>> {
+        let looping = self.looping.as_ref()?;
+
+        Some(looping.start.max(self.start)..looping.end.min(self.end(len)))
+    }
+}
+
+/// everything a sampler plays, as saved in a project.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SamplerConf {
+    pub mode: SamplerMode,
+    pub pads: Vec<Pad>,
+}
+
+/// a wav file, mixed down to mono.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub path: PathBuf,
+    pub rate: u32,
+    pub frames: Arc<[f32]>,
+}
+
+impl Sample {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let err = |e: hound::Error| format!("failed to read {}: {e}", path.display());
+        let reader = WavReader::open(path).map_err(err)?;
+        let spec = reader.spec();
+        let samples: Vec<f32> = match spec.sample_format {
+            SampleFormat::Float => reader
+                .into_samples::<f32>()
+                .collect::<Result<_, _>>()
+                .map_err(err)?,
+            SampleFormat::Int => {
+                let scale = 1.0 / (1u32 << (spec.bits_per_sample - 1)) as f32;
+
+                reader
+                    .into_samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 * scale))
+                    .collect::<Result<_, _>>()
+                    .map_err(err)?
+            }
+        };
+        let channels = spec.channels.max(1) as usize;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            rate: spec.sample_rate,
+            frames: samples
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+        })
+    }
+}
+
+/// one pad, playing.
+#[derive(Clone, Debug)]
+struct Voice {
+    key: u8,
+    /// index into the pads.
+    pad: usize,
+    /// the frame it's up to.
+    pos: f64,
+    /// how far `pos` moves every sample, before any bend.
+    step: f64,
+    gain: f32,
+    /// how loud it still is, from 1.0 down, once it's fading out.
+    fade: Option<f32>,
+    done: bool,
+}
+
+impl Voice {
+    fn fade_out(&mut self) {
+        self.fade.get_or_insert(1.0);
+    }
+
+    fn next_sample(&mut self, pad: &Pad, sample: &Sample, bend: f64, fade_step: f32) -> f32 {
+        let end = pad.end(sample.frames.len());
+        let i = self.pos as usize;
+
+        if i >= end || self.fade.is_some_and(|fade| fade <= 0.0) {
+            self.done = true;
+            return 0.0;
+        }
+
+        let a = sample.frames[i];
+        let b = sample
+            .frames
+            .get(i + 1)
+            .filter(|_| i + 1 < end)
+            .copied()
+            .unwrap_or_default();
+        let out =
+            (a + (b - a) * (self.pos - i as f64) as f32) * self.gain * self.fade.unwrap_or(1.0);
+
+        self.pos += self.step * bend;
+
+        if let Some(looping) = pad.loop_range(sample.frames.len())
+            && self.pos >= looping.end as f64
+        {
+            self.pos -= (looping.end - looping.start) as f64;
+        }
+
+        if let Some(fade) = self.fade.as_mut() {
+            *fade -= fade_step;
+        }
+
+        out
+    }
+}
+
+/// plays wav files, either as a kit of pads or one sample across the keyboard.
+pub struct Sampler {
+    conf: SamplerConf,
+    /// the sample for each pad, in the same order.
+    samples: Vec<Sample>,
+    voices: Vec<Voice>,
+    /// what pitch is multiplied by, from the pitch bend.
+    bend: f64,
+    /// how far into its sample the next note starts, from 0.0 to 1.0, set by the sample offset
+    /// command. used up by that note.
+    offset: Option<f32>,
+}
+
+impl fmt::Debug for Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sampler")
+            .field("mode", &self.conf.mode)
+            .field("pads", &self.conf.pads.len())
+            .field("voices", &self.voices.len())
+            .finish()
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            conf: SamplerConf::default(),
+            samples: Vec::new(),
+            voices: Vec::with_capacity(MAX_VOICES),
+            bend: 1.0,
+            offset: None,
+        }
+    }
+}
+
+impl Sampler {
+    /// load every pad of `conf`. files that are already in `loaded` aren't read again.
+    pub fn new(conf: SamplerConf, loaded: &[Sample]) -> Result<Self, String> {
+        let samples = conf
+            .pads
+            .iter()
+            .map(|pad| {
+                let sample = match loaded.iter().find(|sample| sample.path == pad.path) {
+                    Some(sample) => sample.clone(),
+                    None => Sample::load(&pad.path)?,
+                };
+                check(pad, &sample)?;
+
+                Ok(sample)
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            conf,
+            samples,
+            ..Default::default()
+        })
+    }
+
+    /// the files it has loaded, to hand to `new` when only the settings change.
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    pub fn get_sample(&mut self) -> f32 {
+        let fade_step = 1.0 / (FADE_SECS * SAMPLE_RATE as f32);
+        let mut out = 0.0;
+
+        for voice in self.voices.iter_mut() {
+            let (pad, sample) = (&self.conf.pads[voice.pad], &self.samples[voice.pad]);
+            out += voice.next_sample(pad, sample, self.bend, fade_step);
+        }
+
+        self.voices.retain(|voice| !voice.done);
+
+        out
+    }
+}
+
+/// whether the start, end and loop points of `pad` fit in `sample`.
+fn check(pad: &Pad, sample: &Sample) -> Result<(), String> {
+    let end = pad.end(sample.frames.len());
+    let name = pad.path.display();
+
+    if pad.start >= end {
+        return Err(format!(
+            "{name}: starts at frame {}, but ends at {end}",
+            pad.start
+        ));
+    }
+
+    if pad
+        .loop_range(sample.frames.len())
+        .is_some_and(|looping| looping.is_empty())
+    {
+        return Err(format!(
+            "{name}: the loop doesn't overlap {}..{end}",
+            pad.start
+        ));
+    }
+
+    Ok(())
+}
+
+impl SynthSink for Sampler {
+    /// in kit mode, plays the pad for `note`. in chromatic mode, plays the first pad at the pitch
+    /// of `note`. velocity scales the volume linearly. choke groups are cut off first.
+    fn play(&mut self, note: u8, velocity: u8) {
+        let offset = self.offset.take().unwrap_or(0.0);
+        let (i, semitones) = match self.conf.mode {
+            SamplerMode::Kit => match self.conf.pads.iter().position(|pad| pad.note == note) {
+                Some(i) => (i, 0.0),
+                None => return,
+            },
+            SamplerMode::Chromatic => match self.conf.pads.first() {
+                Some(pad) => (0, note as f64 - pad.note as f64),
+                None => return,
+            },
+        };
+        let (pad, sample) = (&self.conf.pads[i], &self.samples[i]);
+        let pads = &self.conf.pads;
+
+        if let Some(group) = pad.choke {
+            self.voices
+                .iter_mut()
+                .filter(|voice| pads[voice.pad].choke == Some(group))
+                .for_each(Voice::fade_out);
+        }
+
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+
+        let end = pad.end(sample.frames.len());
+
+        self.voices.push(Voice {
+            key: note,
+            pad: i,
+            pos: pad.start as f64 + (end - pad.start) as f64 * offset as f64,
+            step: 2f64.powf(semitones / 12.0) * sample.rate as f64 / SAMPLE_RATE as f64,
+            gain: pad.gain * velocity as f32 / 127.0,
+            fade: None,
+            done: false,
+        });
+    }
+
+    /// fades out looping pads, and anything in chromatic mode. one-shot kit pads play out.
+    fn stop(&mut self, note: u8) {
+        let (mode, pads) = (self.conf.mode, &self.conf.pads);
+
+        self.voices
+            .iter_mut()
+            .filter(|voice| voice.key == note)
+            .filter(|voice| mode == SamplerMode::Chromatic || pads[voice.pad].looping.is_some())
+            .for_each(Voice::fade_out);
+    }
+
+    fn bend(&mut self, amount: f32) {
+        self.bend = 2f64.powf(amount as f64 * BEND_RANGE / 12.0);
+    }
+
+    fn unbend(&mut self) {
+        self.bend = 1.0;
+    }
+
+    fn knob(&mut self, _knob: u8, _value: f32) -> bool {
+        false
+    }
+
+    fn volume_swell(&mut self, _value: f32) -> bool {
+        false
+    }
+
+    fn raw_midi(&mut self, _msg: &MidiMessage) -> bool {
+        false
+    }
+
+    fn param_knob(&self, _param: &Sf2Cmd) -> Option<u8> {
+        None
+    }
+
+    fn sample_offset(&mut self, amount: f32) -> bool {
+        self.offset = Some(amount.clamp(0.0, 1.0));
+
+        true
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::synth::router::apply_param;
+    use hound::{WavSpec, WavWriter};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// writes a 16 bit wav of `frames` to the temp dir, and returns where. every call gets a file
+    /// of its own, so tests running at the same time don't write over each other's.
+    pub(crate) fn wav(name: &str, channels: u16, frames: &[f32]) -> PathBuf {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let n = FILES.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!(
+            "tracker-synth-test-{}-{name}-{n}.wav",
+            std::process::id()
+        ));
+        let spec = WavSpec {
+            channels,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+
+        for sample in frames {
+            writer
+                .write_sample((sample * i16::MAX as f32) as i16)
+                .unwrap();
+        }
+
+        writer.finalize().unwrap();
+
+        path
+    }
+
+    /// a kit with a kick on 36, and open and closed hats on 46 and 42 that choke each other.
+    fn kit() -> Sampler {
+        let kick = wav("kick", 1, &[0.5; 100]);
+        let hat = wav("hat", 2, &[0.25f32, 0.75].repeat(1000));
+        let choked = |note| Pad {
+            choke: Some(1),
+            ..Pad::new(hat.clone(), note)
+        };
+        let conf = SamplerConf {
+            mode: SamplerMode::Kit,
+            pads: vec![Pad::new(kick, 36), choked(46), choked(42)],
+        };
+
+        Sampler::new(conf, &[]).unwrap()
+    }
+
+    fn render(sampler: &mut Sampler, n: usize) -> Vec<f32> {
+        (0..n).map(|_| sampler.get_sample()).collect()
+    }
+
+    #[test]
+    fn loading() {
+        let stereo = wav("stereo", 2, &[1.0, 0.0, 0.5, 0.5]);
+        let sample = Sample::load(&stereo).unwrap();
+
+        assert_eq!(sample.rate, SAMPLE_RATE);
+        assert_eq!(sample.frames.len(), 2);
+        assert!((sample.frames[0] - 0.5).abs() < 1e-3);
+        assert!(Sample::load(Path::new("no-such.wav")).is_err());
+
+        let bad = |pad: Pad| {
+            Sampler::new(
+                SamplerConf {
+                    pads: vec![pad],
+                    ..Default::default()
+                },
+                &[],
+            )
+        };
+        assert!(
+            bad(Pad {
+                start: 2,
+                ..Pad::new(stereo.clone(), 36)
+            })
+            .is_err()
+        );
+        assert!(
+            bad(Pad {
+                looping: Some(2..3),
+                ..Pad::new(stereo.clone(), 36)
+            })
+            .is_err()
+        );
+        assert!(
+            bad(Pad {
+                looping: Some(1..2),
+                ..Pad::new(stereo, 36)
+            })
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn kit_pads() {
+        let mut sampler = kit();
+
+        sampler.play(36, 127);
+        sampler.stop(36);
+        sampler.play(60, 127);
+        assert_eq!(sampler.voices.len(), 1);
+
+        // one-shots play out after the note off, then stop by themselves
+        let out = render(&mut sampler, 200);
+        assert!((out[50] - 0.5).abs() < 1e-3);
+        assert_eq!(out[150], 0.0);
+        assert!(sampler.voices.is_empty());
+    }
+
+    #[test]
+    fn choke_groups() {
+        let mut sampler = kit();
+
+        sampler.play(36, 127);
+        sampler.play(46, 127);
+        sampler.play(42, 127);
+        assert_eq!(sampler.voices[1].fade, Some(1.0));
+        assert_eq!(sampler.voices[2].fade, None);
+        assert_eq!(sampler.voices[0].fade, None);
+
+        render(&mut sampler, (FADE_SECS * SAMPLE_RATE as f32) as usize + 2);
+        assert_eq!(
+            sampler
+                .voices
+                .iter()
+                .map(|voice| voice.key)
+                .collect::<Vec<_>>(),
+            vec![42]
+        );
+    }
+
+    #[test]
+    fn chromatic_loops_until_released() {
+        let pad = Pad {
+            looping: Some(10..20),
+            ..Pad::new(wav("tone", 1, &[0.5; 30]), 60)
+        };
+        let conf = SamplerConf {
+            mode: SamplerMode::Chromatic,
+            pads: vec![pad],
+        };
+        let mut sampler = Sampler::new(conf, &[]).unwrap();
+
+        sampler.play(72, 127);
+        assert!((sampler.voices[0].step - 2.0).abs() < 1e-9);
+
+        let held = render(&mut sampler, 1000);
+        assert!(held[999] > 0.4);
+
+        sampler.stop(72);
+        render(&mut sampler, (FADE_SECS * SAMPLE_RATE as f32) as usize + 2);
+        assert!(sampler.voices.is_empty());
+
+        // the files are shared with a sampler built from the old one's
+        let reused = Sampler::new(sampler.conf.clone(), sampler.samples()).unwrap();
+        assert!(Arc::ptr_eq(
+            &reused.samples[0].frames,
+            &sampler.samples[0].frames
+        ));
+    }
+
+    #[test]
+    fn sample_offset() {
+        let mut sampler = kit();
+
+        assert!(apply_param(&mut sampler, &Sf2Cmd::Offset(64)));
+        sampler.play(46, 127);
+        sampler.play(36, 127);
+
+        // only the note straight after the command starts part way in
+        let expected = 1000.0 * (64.0 / 127.0f32) as f64;
+        assert!((sampler.voices[0].pos - expected).abs() < 1e-9);
+        assert_eq!(sampler.voices[1].pos, 0.0);
+    }
+}
@@ -0,0 +1,445 @@
+use super::{
+    router::SynthSink,
+    soundfont::{Envelope, LoopMode, Region, SoundFont},
+};
+use crate::tracks::Sf2Cmd;
+use midi_control::MidiMessage;
+use std::{fmt, path::Path, sync::Arc};
+use stepper_synth_backend::SAMPLE_RATE;
+
+/// the most notes that play at once. past this, the oldest note is cut to make room.
+pub const MAX_VOICES: usize = 32;
+/// how long an envelope stage lasts with its knob all the way up, in seconds. knob amounts are
+/// squared first, so most of the travel goes to the short times.
+pub const MAX_ENVELOPE_SECS: f32 = 10.0;
+/// how far a full pitch bend goes, in semi-tones.
+const BEND_RANGE: f64 = 2.0;
+/// the knobs, in order. the same layout as the wave table, so `Sf2Cmd`s do the same thing on both.
+const ATTACK_KNOB: usize = 0;
+const DECAY_KNOB: usize = 1;
+const SUSTAIN_KNOB: usize = 2;
+const RELEASE_KNOB: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+/// one sample of a region, playing.
+#[derive(Clone, Debug)]
+struct Voice {
+    key: u8,
+    region: Region,
+    /// the region's envelope, with the knobs that have been turned taken into account.
+    envelope: Envelope,
+    /// where in `SoundFont::data` the voice is up to.
+    pos: f64,
+    /// how far `pos` moves every sample, before any bend.
+    step: f64,
+    gain: f32,
+    stage: Stage,
+    /// seconds since `stage` started.
+    time: f32,
+    level: f32,
+    /// the level that the release started from.
+    released_from: f32,
+}
+
+impl Voice {
+    fn looping(&self) -> bool {
+        match self.region.looping {
+            LoopMode::None => false,
+            LoopMode::Continuous => true,
+            LoopMode::UntilRelease => self.stage != Stage::Release,
+        }
+    }
+
+    fn release(&mut self) {
+        if !matches!(self.stage, Stage::Release | Stage::Done) {
+            self.released_from = self.level;
+            self.stage = Stage::Release;
+            self.time = 0.0;
+        }
+    }
+
+    /// move the envelope on by `dt` seconds.
+    fn next_level(&mut self, dt: f32) -> f32 {
+        let env = self.envelope;
+        self.time += dt;
+        let progress = |secs: f32| (self.time / secs).min(1.0);
+
+        let (level, done, next) = match self.stage {
+            Stage::Delay => (0.0, self.time >= env.delay, Stage::Attack),
+            Stage::Attack => (progress(env.attack), self.time >= env.attack, Stage::Hold),
+            Stage::Hold => (1.0, self.time >= env.hold, Stage::Decay),
+            Stage::Decay => (
+                1.0 - (1.0 - env.sustain) * progress(env.decay),
+                self.time >= env.decay,
+                Stage::Sustain,
+            ),
+            Stage::Sustain => (env.sustain, false, Stage::Sustain),
+            Stage::Release => (
+                self.released_from * (1.0 - progress(env.release)),
+                self.time >= env.release,
+                Stage::Done,
+            ),
+            Stage::Done => (0.0, false, Stage::Done),
+        };
+
+        if done {
+            self.stage = next;
+            self.time = 0.0;
+        }
+
+        self.level = level;
+
+        level
+    }
+
+    fn next_sample(&mut self, data: &[f32], bend: f64, dt: f32) -> f32 {
+        let level = self.next_level(dt);
+        let region = &self.region;
+        let i = self.pos as usize;
+
+        if i >= region.end || self.stage == Stage::Done {
+            self.stage = Stage::Done;
+            return 0.0;
+        }
+
+        let looping = self.looping();
+        let next = if looping && i + 1 >= region.loop_end {
+            region.loop_start
+        } else {
+            i + 1
+        };
+        let a = data.get(i).copied().unwrap_or_default();
+        let b = data
+            .get(next)
+            .filter(|_| next < region.end)
+            .copied()
+            .unwrap_or_default();
+        let sample = a + (b - a) * (self.pos - i as f64) as f32;
+
+        self.pos += self.step * bend;
+
+        if looping && self.pos >= region.loop_end as f64 {
+            self.pos -= (region.loop_end - region.loop_start) as f64;
+        }
+
+        sample * level * self.gain
+    }
+}
+
+/// plays a preset of a SoundFont.
+pub struct Sf2Engine {
+    /// nothing plays until a font is loaded.
+    font: Option<Arc<SoundFont>>,
+    /// index into the font's presets.
+    preset: usize,
+    voices: Vec<Voice>,
+    /// what pitch is multiplied by, from the pitch bend.
+    bend: f64,
+    /// attack, decay, sustain and release, for the knobs that have been turned.
+    knobs: [Option<f32>; 4],
+}
+
+impl fmt::Debug for Sf2Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sf2Engine")
+            .field("font", &self.font.as_ref().map(|font| &font.name))
+            .field("preset", &self.preset)
+            .field("voices", &self.voices.len())
+            .finish()
+    }
+}
+
+impl Default for Sf2Engine {
+    fn default() -> Self {
+        Self {
+            font: None,
+            preset: 0,
+            voices: Vec::with_capacity(MAX_VOICES),
+            bend: 1.0,
+            knobs: [None; 4],
+        }
+    }
+}
+
+impl Sf2Engine {
+    /// load the SoundFont at `path`, playing `preset` from `bank`.
+    pub fn load(path: &Path, bank: u16, preset: u16) -> Result<Self, String> {
+        Self::new(Arc::new(SoundFont::load(path)?), bank, preset)
+    }
+
+    /// load the SoundFont at `path`, playing its first preset.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let font = SoundFont::load(path)?;
+        let (bank, preset) = font
+            .presets
+            .first()
+            .map(|p| (p.bank, p.preset))
+            .ok_or_else(|| format!("{} has no presets", path.display()))?;
+
+        Self::new(Arc::new(font), bank, preset)
+    }
+
+    pub fn new(font: Arc<SoundFont>, bank: u16, preset: u16) -> Result<Self, String> {
+        let mut engine = Self {
+            font: Some(font),
+            ..Default::default()
+        };
+        engine.select(bank, preset)?;
+
+        Ok(engine)
+    }
+
+    /// switch to `preset` from `bank`. notes that are already playing carry on as they were.
+    pub fn select(&mut self, bank: u16, preset: u16) -> Result<(), String> {
+        let font = self.font.as_ref().ok_or("no SoundFont is loaded")?;
+
+        self.preset = font
+            .find_preset(bank, preset)
+            .ok_or_else(|| format!("{} has no preset {preset} in bank {bank}", font.name))?;
+
+        Ok(())
+    }
+
+    /// the (bank, preset) that's playing.
+    pub fn selected(&self) -> Option<(u16, u16)> {
+        let preset = self.font.as_ref()?.presets.get(self.preset)?;
+
+        Some((preset.bank, preset.preset))
+    }
+
+    /// (bank, preset, name) of every preset in the font.
+    pub fn presets(&self) -> Vec<(u16, u16, String)> {
+        self.font
+            .iter()
+            .flat_map(|font| font.presets.iter())
+            .map(|p| (p.bank, p.preset, p.name.clone()))
+            .collect()
+    }
+
+    pub fn get_sample(&mut self) -> f32 {
+        let Some(font) = self.font.as_deref() else {
+            return 0.0;
+        };
+        let dt = 1.0 / SAMPLE_RATE as f32;
+        let sample = self
+            .voices
+            .iter_mut()
+            .map(|voice| voice.next_sample(&font.data, self.bend, dt))
+            .sum();
+
+        self.voices.retain(|voice| voice.stage != Stage::Done);
+
+        sample
+    }
+}
+
+/// `zone`, with the envelope `knobs` that have been turned laid over it.
+fn envelope(knobs: &[Option<f32>; 4], zone: &Envelope) -> Envelope {
+    let time = |knob: usize, secs: f32| {
+        knobs[knob].map_or(secs, |amount| amount * amount * MAX_ENVELOPE_SECS)
+    };
+
+    Envelope {
+        attack: time(ATTACK_KNOB, zone.attack),
+        decay: time(DECAY_KNOB, zone.decay),
+        sustain: knobs[SUSTAIN_KNOB].unwrap_or(zone.sustain),
+        release: time(RELEASE_KNOB, zone.release),
+        ..*zone
+    }
+}
+
+impl SynthSink for Sf2Engine {
+    /// plays every region of the preset that covers `note` at `velocity`. velocity scales the
+    /// volume linearly.
+    fn play(&mut self, note: u8, velocity: u8) {
+        // a handle on the font, rather than a copy of the preset, so the audio thread doesn't
+        // allocate
+        let Some(font) = self.font.clone() else {
+            return;
+        };
+        let Some(preset) = font.presets.get(self.preset) else {
+            return;
+        };
+
+        for region in preset.regions.iter().filter(|r| r.plays(note, velocity)) {
+            if self.voices.len() >= MAX_VOICES {
+                self.voices.remove(0);
+            }
+
+            let cents = (note as f32 - region.root_key as f32) * region.scale_tuning + region.tune;
+
+            self.voices.push(Voice {
+                key: note,
+                envelope: envelope(&self.knobs, &region.envelope),
+                pos: region.start as f64,
+                step: 2f64.powf(cents as f64 / 1200.0) * region.sample_rate as f64
+                    / SAMPLE_RATE as f64,
+                gain: region.gain * velocity as f32 / 127.0,
+                stage: Stage::Delay,
+                time: 0.0,
+                level: 0.0,
+                released_from: 0.0,
+                region: region.clone(),
+            });
+        }
+    }
+
+    fn stop(&mut self, note: u8) {
+        self.voices
+            .iter_mut()
+            .filter(|voice| voice.key == note)
+            .for_each(Voice::release);
+    }
+
+    fn bend(&mut self, amount: f32) {
+        self.bend = 2f64.powf(amount as f64 * BEND_RANGE / 12.0);
+    }
+
+    fn unbend(&mut self) {
+        self.bend = 1.0;
+    }
+
+    /// the first four knobs are attack, decay, sustain and release. they apply to the notes that
+    /// are already playing as well as new ones.
+    fn knob(&mut self, knob: u8, value: f32) -> bool {
+        let Some(slot) = self.knobs.get_mut(knob as usize) else {
+            return false;
+        };
+        *slot = Some(value.clamp(0.0, 1.0));
+
+        for voice in self.voices.iter_mut() {
+            voice.envelope = envelope(&self.knobs, &voice.region.envelope);
+        }
+
+        true
+    }
+
+    fn volume_swell(&mut self, _value: f32) -> bool {
+        false
+    }
+
+    fn raw_midi(&mut self, _msg: &MidiMessage) -> bool {
+        false
+    }
+
+    fn param_knob(&self, param: &Sf2Cmd) -> Option<u8> {
+        let knob = match param {
+            Sf2Cmd::Atk(_) => ATTACK_KNOB,
+            Sf2Cmd::Dcy(_) => DECAY_KNOB,
+            Sf2Cmd::Sus(_) => SUSTAIN_KNOB,
+            Sf2Cmd::Rel(_) => RELEASE_KNOB,
+            // a SoundFont envelope only has the one decay
+            Sf2Cmd::Dcy2(_) | Sf2Cmd::Volume(_) | Sf2Cmd::Offset(_) => return None,
+        };
+
+        Some(knob as u8)
+    }
+
+    fn sample_offset(&mut self, _amount: f32) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{router::apply_param, soundfont::tests::TINY};
+
+    fn keys() -> Sf2Engine {
+        Sf2Engine::new(Arc::new(SoundFont::parse(TINY).unwrap()), 0, 0).unwrap()
+    }
+
+    fn render(engine: &mut Sf2Engine, secs: f32) -> Vec<f32> {
+        (0..(secs * SAMPLE_RATE as f32) as usize)
+            .map(|_| engine.get_sample())
+            .collect()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn presets() {
+        let mut engine = keys();
+
+        assert_eq!(
+            engine.presets(),
+            vec![(0, 0, "Keys".to_string()), (128, 0, "Kit".to_string())]
+        );
+        assert!(engine.select(128, 0).is_ok());
+        assert!(engine.select(3, 3).is_err());
+        assert!(Sf2Engine::default().select(0, 0).is_err());
+        assert_eq!(engine.selected(), Some((128, 0)));
+        assert!(Sf2Engine::load(Path::new("no-such.sf2"), 0, 0).is_err());
+        assert!(Sf2Engine::open(Path::new("no-such.sf2")).is_err());
+    }
+
+    #[test]
+    fn key_and_velocity_pick_the_zone() {
+        let mut engine = keys();
+        let regions = engine.font.clone().unwrap().presets[0].regions.clone();
+
+        engine.play(84, 100);
+        engine.play(84, 10);
+        engine.play(40, 127);
+
+        let played: Vec<_> = engine.voices.iter().map(|v| &v.region).collect();
+        assert_eq!(played, vec![&regions[1], &regions[2], &regions[0]]);
+        // 3 semi-tones above the root, of a sample recorded at 22050Hz
+        let expected = 2f64.powf(3.0 / 12.0) * 22050.0 / SAMPLE_RATE as f64;
+        assert!((engine.voices[1].step - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn loops_until_released() {
+        let mut engine = keys();
+        engine.play(69, 127);
+
+        // far longer than the sample itself, so only the loop keeps it going
+        let held = render(&mut engine, 0.5);
+        assert!(peak(&held[held.len() - 1000..]) > 0.1);
+
+        engine.stop(69);
+        let released = render(&mut engine, 0.2);
+        assert_eq!(peak(&released[released.len() - 100..]), 0.0);
+        assert!(engine.voices.is_empty());
+    }
+
+    #[test]
+    fn one_shots_end_by_themselves() {
+        let mut engine = keys();
+        engine.select(128, 0).unwrap();
+        engine.play(60, 127);
+
+        let out = render(&mut engine, 0.05);
+        assert!(peak(&out) > 0.0);
+        assert!(engine.voices.is_empty());
+    }
+
+    #[test]
+    fn envelope_commands() {
+        let mut engine = keys();
+        engine.play(60, 127);
+
+        assert!(apply_param(&mut engine, &Sf2Cmd::Atk(127)));
+        assert!(apply_param(&mut engine, &Sf2Cmd::Sus(0)));
+        assert!(!apply_param(&mut engine, &Sf2Cmd::Dcy2(10)));
+        assert_eq!(engine.voices[0].envelope.attack, MAX_ENVELOPE_SECS);
+        assert_eq!(engine.voices[0].envelope.sustain, 0.0);
+
+        // a ten second attack is still very quiet a moment in
+        let out = render(&mut engine, 0.01);
+        assert!(peak(&out) < 0.01);
+    }
+}
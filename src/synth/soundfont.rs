@@ -0,0 +1,512 @@
+use std::{
+    fs,
+    ops::{Range, RangeInclusive},
+    path::Path,
+};
+
+/// generator numbers, from the SoundFont 2.04 spec. only the ones that get played are here.
+mod gen_id {
+    pub const START_OFFSET: u16 = 0;
+    pub const END_OFFSET: u16 = 1;
+    pub const LOOP_START_OFFSET: u16 = 2;
+    pub const LOOP_END_OFFSET: u16 = 3;
+    pub const START_COARSE_OFFSET: u16 = 4;
+    pub const END_COARSE_OFFSET: u16 = 12;
+    pub const DELAY: u16 = 33;
+    pub const ATTACK: u16 = 34;
+    pub const HOLD: u16 = 35;
+    pub const DECAY: u16 = 36;
+    pub const SUSTAIN: u16 = 37;
+    pub const RELEASE: u16 = 38;
+    pub const INSTRUMENT: u16 = 41;
+    pub const KEY_RANGE: u16 = 43;
+    pub const VEL_RANGE: u16 = 44;
+    pub const LOOP_START_COARSE_OFFSET: u16 = 45;
+    pub const ATTENUATION: u16 = 48;
+    pub const LOOP_END_COARSE_OFFSET: u16 = 50;
+    pub const COARSE_TUNE: u16 = 51;
+    pub const FINE_TUNE: u16 = 52;
+    pub const SAMPLE_ID: u16 = 53;
+    pub const SAMPLE_MODES: u16 = 54;
+    pub const SCALE_TUNING: u16 = 56;
+    pub const ROOT_KEY: u16 = 58;
+    pub const COUNT: usize = 61;
+}
+
+/// the envelope time the spec defaults to, in timecents. (about 1ms)
+const DEFAULT_TIMECENTS: i16 = -12000;
+
+/// what happens when a note gets to the end of its sample's loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    /// play the sample once through.
+    #[default]
+    None,
+    /// go round the loop for as long as the note sounds, release included.
+    Continuous,
+    /// go round the loop until the note is released, then play out the rest of the sample.
+    UntilRelease,
+}
+
+/// a volume envelope. times are in seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    pub delay: f32,
+    pub attack: f32,
+    pub hold: f32,
+    pub decay: f32,
+    /// the level, from 0.0 to 1.0, held until release.
+    pub sustain: f32,
+    pub release: f32,
+}
+
+/// one sample, and the keys and velocities that play it, with everything the preset and
+/// instrument zones said about how.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    pub keys: RangeInclusive<u8>,
+    pub velocities: RangeInclusive<u8>,
+    /// where the sample starts and ends, as indices into `SoundFont::data`.
+    pub start: usize,
+    pub end: usize,
+    pub loop_start: usize,
+    pub loop_end: usize,
+    pub looping: LoopMode,
+    pub sample_rate: u32,
+    /// the key that plays the sample at the pitch it was recorded at.
+    pub root_key: u8,
+    /// tuning, in cents, on top of the root key.
+    pub tune: f32,
+    /// how many cents each key is from the next, normally 100.
+    pub scale_tuning: f32,
+    /// linear gain.
+    pub gain: f32,
+    pub envelope: Envelope,
+}
+
+impl Region {
+    pub fn plays(&self, key: u8, velocity: u8) -> bool {
+        self.keys.contains(&key) && self.velocities.contains(&velocity)
+    }
+}
+
+/// a bank and preset number, and the regions that make it up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub bank: u16,
+    pub preset: u16,
+    pub regions: Vec<Region>,
+}
+
+/// a parsed SoundFont 2 file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoundFont {
+    pub name: String,
+    pub presets: Vec<Preset>,
+    /// every sample in the file, one after the other, from -1.0 to 1.0.
+    pub data: Vec<f32>,
+}
+
+/// the generators of a zone, by number. ranges are packed as (lo, hi) bytes like in the file.
+#[derive(Clone, Copy, Debug)]
+struct Gens([Option<i16>; gen_id::COUNT]);
+
+impl Gens {
+    fn empty() -> Self {
+        Self([None; gen_id::COUNT])
+    }
+
+    fn get(&self, id: u16) -> Option<i16> {
+        self.0.get(id as usize).copied().flatten()
+    }
+
+    fn or(&self, id: u16, default: i16) -> i16 {
+        self.get(id).unwrap_or(default)
+    }
+
+    /// `self`, with whatever `zone` sets laid over the top.
+    fn with(&self, zone: &Self) -> Self {
+        let mut gens = *self;
+
+        for (slot, set) in gens.0.iter_mut().zip(zone.0.iter()) {
+            if set.is_some() {
+                *slot = *set;
+            }
+        }
+
+        gens
+    }
+
+    fn range(&self, id: u16) -> RangeInclusive<u8> {
+        match self.get(id) {
+            Some(range) => {
+                let [lo, hi] = range.to_le_bytes();
+                lo..=hi
+            }
+            None => 0..=127,
+        }
+    }
+}
+
+struct SampleHeader {
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    root_key: u8,
+    correction: i8,
+}
+
+/// the header of a preset or an instrument: a name, and where its zones start.
+struct ZoneList {
+    name: String,
+    bank: u16,
+    preset: u16,
+    first_bag: usize,
+}
+
+impl SoundFont {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes =
+            fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+
+        Self::parse(&bytes).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let Some((id, body)) = chunks(bytes).next() else {
+            return Err("not a SoundFont file".into());
+        };
+
+        if id != *b"RIFF" || !body.starts_with(b"sfbk") {
+            return Err("not a SoundFont file".into());
+        }
+
+        let (mut info, mut sdta, mut pdta) = (None, None, None);
+
+        for (id, body) in chunks(&body[4..]) {
+            match (&id, body.get(..4)) {
+                (b"LIST", Some(b"INFO")) => info = Some(&body[4..]),
+                (b"LIST", Some(b"sdta")) => sdta = Some(&body[4..]),
+                (b"LIST", Some(b"pdta")) => pdta = Some(&body[4..]),
+                _ => {}
+            }
+        }
+
+        let name = info
+            .and_then(|info| find(info, b"INAM"))
+            .map(text)
+            .unwrap_or_default();
+        let data: Vec<f32> = sdta
+            .and_then(|sdta| find(sdta, b"smpl"))
+            .ok_or("the SoundFont has no samples")?
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]) as f32 / 32768.0)
+            .collect();
+        let pdta = pdta.ok_or("the SoundFont has no presets")?;
+        let sub = |id: &[u8; 4]| {
+            find(pdta, id).ok_or_else(|| format!("the SoundFont has no {} chunk", text(id)))
+        };
+
+        let presets = records(sub(b"phdr")?, 38, |r| ZoneList {
+            name: text(&r[..20]),
+            preset: u16_at(r, 20),
+            bank: u16_at(r, 22),
+            first_bag: u16_at(r, 24) as usize,
+        });
+        let preset_zones = zones(sub(b"pbag")?, sub(b"pgen")?);
+        let instruments = records(sub(b"inst")?, 22, |r| ZoneList {
+            name: text(&r[..20]),
+            bank: 0,
+            preset: 0,
+            first_bag: u16_at(r, 20) as usize,
+        });
+        let instrument_zones = zones(sub(b"ibag")?, sub(b"igen")?);
+        let samples = records(sub(b"shdr")?, 46, |r| SampleHeader {
+            start: u32_at(r, 20),
+            end: u32_at(r, 24),
+            loop_start: u32_at(r, 28),
+            loop_end: u32_at(r, 32),
+            sample_rate: u32_at(r, 36),
+            root_key: r[40],
+            correction: r[41] as i8,
+        });
+
+        // the last record of each list only marks where the one before it ends
+        let presets = presets
+            .windows(2)
+            .map(|pair| {
+                let (preset, next) = (&pair[0], &pair[1]);
+                let mut regions = Vec::new();
+
+                let bags = preset.first_bag..next.first_bag;
+
+                for pzone in global_and_zones(&preset_zones, bags, gen_id::INSTRUMENT) {
+                    let Some((inst, next_inst)) = pzone
+                        .get(gen_id::INSTRUMENT)
+                        .map(|i| i as u16 as usize)
+                        .and_then(|i| instruments.get(i).zip(instruments.get(i + 1)))
+                    else {
+                        continue;
+                    };
+                    let bags = inst.first_bag..next_inst.first_bag;
+
+                    for izone in global_and_zones(&instrument_zones, bags, gen_id::SAMPLE_ID) {
+                        let Some(sample) = izone
+                            .get(gen_id::SAMPLE_ID)
+                            .and_then(|i| samples.get(i as u16 as usize))
+                        else {
+                            continue;
+                        };
+
+                        regions.extend(region(&pzone, &izone, sample, data.len()));
+                    }
+                }
+
+                Preset {
+                    name: preset.name.clone(),
+                    bank: preset.bank,
+                    preset: preset.preset,
+                    regions,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            name,
+            presets,
+            data,
+        })
+    }
+
+    /// the index of the preset `preset` in `bank`.
+    pub fn find_preset(&self, bank: u16, preset: u16) -> Option<usize> {
+        self.presets
+            .iter()
+            .position(|p| p.bank == bank && p.preset == preset)
+    }
+}
+
+/// the RIFF chunks in `bytes`, as (id, body).
+fn chunks(bytes: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut rest = bytes;
+
+    std::iter::from_fn(move || {
+        let (id, after) = rest.split_first_chunk::<4>()?;
+        let (len, after) = after.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        let body = after.get(..len)?;
+        // chunks are padded out to an even length
+        rest = after.get(len + len % 2..).unwrap_or_default();
+
+        Some((*id, body))
+    })
+}
+
+fn find<'a>(bytes: &'a [u8], id: &[u8; 4]) -> Option<&'a [u8]> {
+    chunks(bytes)
+        .find(|(chunk, _)| chunk == id)
+        .map(|(_, body)| body)
+}
+
+/// a zero padded string.
+fn text(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+
+    String::from_utf8_lossy(&bytes[..len]).trim().to_string()
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn records<T>(bytes: &[u8], size: usize, read: impl Fn(&[u8]) -> T) -> Vec<T> {
+    bytes.chunks_exact(size).map(read).collect()
+}
+
+/// the generators of every zone (bag), in order.
+fn zones(bags: &[u8], gens: &[u8]) -> Vec<Gens> {
+    let gens: Vec<(u16, i16)> = records(gens, 4, |r| (u16_at(r, 0), u16_at(r, 2) as i16));
+    let starts: Vec<usize> = records(bags, 4, |r| u16_at(r, 0) as usize);
+
+    starts
+        .windows(2)
+        .map(|pair| {
+            let mut zone = Gens::empty();
+
+            for (id, amount) in gens.get(pair[0]..pair[1]).unwrap_or_default() {
+                if let Some(slot) = zone.0.get_mut(*id as usize) {
+                    *slot = Some(*amount);
+                }
+            }
+
+            zone
+        })
+        .collect()
+}
+
+/// the zones in `bags`, each with the global zone (the first one, if it doesn't have `last`, the
+/// generator every other zone ends with) laid under it.
+fn global_and_zones(zones: &[Gens], bags: Range<usize>, last: u16) -> Vec<Gens> {
+    let zones = zones.get(bags).unwrap_or_default();
+    let (global, zones) = match zones.split_first() {
+        Some((first, rest)) if first.get(last).is_none() => (*first, rest),
+        _ => (Gens::empty(), zones),
+    };
+
+    zones.iter().map(|zone| global.with(zone)).collect()
+}
+
+/// seconds, from timecents.
+fn secs(timecents: i32) -> f32 {
+    2f32.powf(timecents as f32 / 1200.0)
+}
+
+/// linear gain, from centibels of attenuation.
+fn gain(centibels: i32) -> f32 {
+    10f32.powf(-centibels.max(0) as f32 / 200.0)
+}
+
+/// put a preset zone and an instrument zone together into something that can be played. the
+/// instrument zone sets most things, and the preset zone adds to it.
+fn region(pzone: &Gens, izone: &Gens, sample: &SampleHeader, len: usize) -> Option<Region> {
+    let keys = intersect(
+        pzone.range(gen_id::KEY_RANGE),
+        izone.range(gen_id::KEY_RANGE),
+    )?;
+    let velocities = intersect(
+        pzone.range(gen_id::VEL_RANGE),
+        izone.range(gen_id::VEL_RANGE),
+    )?;
+    // instrument value plus preset value
+    let sum = |id, default| izone.or(id, default) as i32 + pzone.or(id, 0) as i32;
+    let offset = |fine, coarse| izone.or(fine, 0) as i64 + izone.or(coarse, 0) as i64 * 32768;
+    let at = |base: u32, offset: i64| (base as i64 + offset).clamp(0, len as i64) as usize;
+
+    let start = at(
+        sample.start,
+        offset(gen_id::START_OFFSET, gen_id::START_COARSE_OFFSET),
+    );
+    let end = at(
+        sample.end,
+        offset(gen_id::END_OFFSET, gen_id::END_COARSE_OFFSET),
+    )
+    .max(start);
+    let loop_start = at(
+        sample.loop_start,
+        offset(gen_id::LOOP_START_OFFSET, gen_id::LOOP_START_COARSE_OFFSET),
+    )
+    .clamp(start, end);
+    let loop_end = at(
+        sample.loop_end,
+        offset(gen_id::LOOP_END_OFFSET, gen_id::LOOP_END_COARSE_OFFSET),
+    )
+    .clamp(loop_start, end);
+    let looping = match izone.or(gen_id::SAMPLE_MODES, 0) & 3 {
+        _ if loop_end <= loop_start => LoopMode::None,
+        1 => LoopMode::Continuous,
+        3 => LoopMode::UntilRelease,
+        _ => LoopMode::None,
+    };
+    let root_key = match izone.or(gen_id::ROOT_KEY, -1) {
+        key @ 0..=127 => key as u8,
+        _ => sample.root_key.min(127),
+    };
+
+    Some(Region {
+        keys,
+        velocities,
+        start,
+        end,
+        loop_start,
+        loop_end,
+        looping,
+        sample_rate: sample.sample_rate.max(1),
+        root_key,
+        tune: (sum(gen_id::COARSE_TUNE, 0) * 100 + sum(gen_id::FINE_TUNE, 0)) as f32
+            + sample.correction as f32,
+        scale_tuning: sum(gen_id::SCALE_TUNING, 100) as f32,
+        gain: gain(sum(gen_id::ATTENUATION, 0)),
+        envelope: Envelope {
+            delay: secs(sum(gen_id::DELAY, DEFAULT_TIMECENTS)),
+            attack: secs(sum(gen_id::ATTACK, DEFAULT_TIMECENTS)),
+            hold: secs(sum(gen_id::HOLD, DEFAULT_TIMECENTS)),
+            decay: secs(sum(gen_id::DECAY, DEFAULT_TIMECENTS)),
+            sustain: gain(sum(gen_id::SUSTAIN, 0)),
+            release: secs(sum(gen_id::RELEASE, DEFAULT_TIMECENTS)),
+        },
+    })
+}
+
+fn intersect(a: RangeInclusive<u8>, b: RangeInclusive<u8>) -> Option<RangeInclusive<u8>> {
+    let range = *a.start().max(b.start())..=*a.end().min(b.end());
+
+    (!range.is_empty()).then_some(range)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// made by `test-data/make_tiny_sf2.py`, which says what's in it.
+    pub(crate) const TINY: &[u8] = include_bytes!("../../test-data/tiny.sf2");
+
+    #[test]
+    fn presets() {
+        let font = SoundFont::parse(TINY).unwrap();
+        let names: Vec<_> = font
+            .presets
+            .iter()
+            .map(|p| (p.name.as_str(), p.bank, p.preset, p.regions.len()))
+            .collect();
+
+        assert_eq!(font.name, "tiny");
+        assert_eq!(names, vec![("Keys", 0, 0, 3), ("Kit", 128, 0, 1)]);
+        assert_eq!(font.find_preset(128, 0), Some(1));
+        assert_eq!(font.find_preset(1, 0), None);
+    }
+
+    #[test]
+    fn zones() {
+        let font = SoundFont::parse(TINY).unwrap();
+        let keys = &font.presets[0].regions;
+
+        // the global zone is under every other zone
+        assert!(keys.iter().all(|r| r.looping == LoopMode::Continuous));
+        assert!((keys[0].envelope.attack - 0.01).abs() < 0.001);
+        assert!((keys[0].envelope.sustain - gain(60)).abs() < 0.001);
+
+        let low = &keys[0];
+        assert_eq!((low.keys.clone(), low.root_key), (0..=71, 69));
+        assert_eq!(
+            (low.loop_start - low.start, low.loop_end - low.start),
+            (100, 150)
+        );
+
+        // keys 72 and up pick a sample by how hard they're hit
+        let hard: Vec<_> = keys.iter().filter(|r| r.plays(84, 100)).collect();
+        let soft: Vec<_> = keys.iter().filter(|r| r.plays(84, 10)).collect();
+        assert_eq!(hard.len(), 1);
+        assert_eq!(soft.len(), 1);
+        assert_eq!(hard[0].start, keys[1].start);
+        assert_eq!((soft[0].start, soft[0].root_key), (low.start, 81));
+
+        let click = &font.presets[1].regions[0];
+        assert_eq!(click.looping, LoopMode::None);
+        assert_eq!(click.end - click.start, 32);
+        assert!((click.gain - gain(60)).abs() < 0.001);
+    }
+
+    #[test]
+    fn not_a_soundfont() {
+        assert!(SoundFont::parse(b"").is_err());
+        assert!(SoundFont::parse(b"RIFF\x04\0\0\0WAVE").is_err());
+        assert!(SoundFont::parse(&TINY[..TINY.len() / 2]).is_err());
+    }
+}
@@ -1,17 +1,100 @@
 use midi_control::Channel;
+use serde::{Deserialize, Serialize};
+use std::mem::discriminant;
 use strum::EnumString;
 use tracing::*;
-use crate::{SynthId, N_STEPS, less_then::UsizeLessThan};
+use crate::{
+    MAX_STEPS, SynthId, N_STEPS,
+    less_then::UsizeLessThan,
+    playback::{
+        DEFAULT_LPB,
+        groove::{Groove, swing_delay},
+        tempo::{MAX_BPM, MIN_BPM},
+    },
+};
 
 pub type MidiNote = u8;
 
-#[derive(Clone, Debug, PartialEq)]
+/// every midi channel, in order. `CHANNELS[0]` is channel 1.
+pub const CHANNELS: [Channel; 16] = [
+    Channel::Ch1,
+    Channel::Ch2,
+    Channel::Ch3,
+    Channel::Ch4,
+    Channel::Ch5,
+    Channel::Ch6,
+    Channel::Ch7,
+    Channel::Ch8,
+    Channel::Ch9,
+    Channel::Ch10,
+    Channel::Ch11,
+    Channel::Ch12,
+    Channel::Ch13,
+    Channel::Ch14,
+    Channel::Ch15,
+    Channel::Ch16,
+];
+
+/// the zero based index of a midi channel, as it is sent over the wire.
+pub fn channel_index(chan: Channel) -> u8 {
+    CHANNELS.iter().position(|other| *other == chan).unwrap_or(0) as u8
+}
+
+/// the channel for a zero based index, the inverse of `channel_index`.
+pub fn channel_from_index(i: u8) -> Option<Channel> {
+    CHANNELS.get(i as usize).copied()
+}
+
+/// (de)serializes a midi channel as its number (1-16), since `Channel` doesn't derive serde.
+pub(crate) mod channel_serde {
+    use super::{channel_from_index, channel_index};
+    use midi_control::Channel;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(chan: &Channel, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(channel_index(*chan) + 1)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Channel, D::Error> {
+        let n = u8::deserialize(deserializer)?;
+
+        n.checked_sub(1)
+            .and_then(channel_from_index)
+            .ok_or_else(|| D::Error::custom(format!("{n} is not a midi channel (1-16)")))
+    }
+}
+
+/// how many beats make a bar when a section doesn't say. (4/4)
+pub const DEFAULT_BEATS: usize = 4;
+
+fn default_lpb() -> usize {
+    DEFAULT_LPB
+}
+
+fn default_beats() -> usize {
+    DEFAULT_BEATS
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Track {
+    /// one per row, between 1 and `MAX_STEPS` of them.
     pub steps: Vec<Step>,
     pub dev: SynthId,
+    #[serde(with = "channel_serde")]
     pub chan: Channel,
     pub name: String,
     pub uuid: usize,
+    /// how many rows make a beat, for the grid. (how fast rows play is up to the project's tempo)
+    #[serde(default = "default_lpb")]
+    pub lpb: usize,
+    /// how many beats make a bar.
+    #[serde(default = "default_beats")]
+    pub beats: usize,
+    /// how late every other row plays, as a percentage of half a step. 0 is straight.
+    #[serde(default)]
+    pub swing: u8,
+    #[serde(default)]
+    pub groove: Groove,
 }
 
 impl Default for Track {
@@ -23,6 +106,10 @@ impl Default for Track {
             // dev: SynthId::default(),
             chan: Channel::Ch1,
             uuid: 0,
+            lpb: DEFAULT_LPB,
+            beats: DEFAULT_BEATS,
+            swing: 0,
+            groove: Groove::None,
         }
     }
 }
@@ -37,11 +124,178 @@ impl Track {
             dev,
             chan: Channel::Ch1,
             uuid,
+            lpb: DEFAULT_LPB,
+            beats: DEFAULT_BEATS,
+            swing: 0,
+            groove: Groove::None,
+        }
+    }
+
+    /// make the track `len` steps long (clamped to 1..=`MAX_STEPS`), adding empty steps to the end
+    /// or cutting them off. commands that would reach past the new end are cut short too.
+    pub fn set_len(&mut self, len: usize) {
+        let len = len.clamp(1, MAX_STEPS);
+        self.steps.resize(len, Step::default());
+
+        for step in self.steps.iter_mut() {
+            step.cmds.0.clamp_to(len);
+            step.cmds.1.clamp_to(len);
+        }
+    }
+
+    /// whether `row` starts a beat.
+    pub fn is_beat(&self, row: usize) -> bool {
+        row % self.lpb.max(1) == 0
+    }
+
+    /// whether `row` starts a bar.
+    pub fn is_bar(&self, row: usize) -> bool {
+        row % (self.lpb.max(1) * self.beats.max(1)) == 0
+    }
+
+    /// how late `row` plays, in steps, from the swing and groove put together.
+    pub fn delay(&self, row: usize) -> f64 {
+        swing_delay(self.swing, row) + self.groove.delay(row)
+    }
+
+    /// what the velocity of `row` is multiplied by.
+    pub fn accent(&self, row: usize) -> f32 {
+        self.groove.accent(row)
+    }
+
+    /// make sure a loaded track makes sense: a length in range, a grid to draw, and no command
+    /// counting past the end of the track.
+    pub fn check(&self) -> Result<(), String> {
+        let len = self.steps.len();
+
+        if !(1..=MAX_STEPS).contains(&len) {
+            return Err(format!(
+                "section {} is {len} steps long, but has to be 1-{MAX_STEPS}",
+                self.name
+            ));
+        }
+
+        if self.lpb == 0 || self.beats == 0 {
+            return Err(format!(
+                "section {} has {} lines per beat and {} beats per bar, neither can be 0",
+                self.name, self.lpb, self.beats
+            ));
+        }
+
+        for (row, step) in self.steps.iter().enumerate() {
+            for cmd in [&step.cmds.0, &step.cmds.1] {
+                if !cmd.fits(len) {
+                    return Err(format!(
+                        "{cmd} on line {} of section {} reaches past its {len} steps",
+                        row + 1,
+                        self.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// a uuid that no section has, and that no pattern still points at, so a new section can't be
+/// mistaken for one that was deleted (and might come back with undo).
+pub fn next_section_uuid(sections: &[Track], patterns: &[Pattern]) -> usize {
+    sections
+        .iter()
+        .map(|section| section.uuid)
+        .chain(patterns.iter().flat_map(|pattern| pattern.columns.iter().flatten().copied()))
+        .max()
+        .map_or(0, |uuid| uuid + 1)
+}
+
+/// a uuid that no pattern has, and that the song doesn't still play.
+pub fn next_pattern_uuid(patterns: &[Pattern], song: &Song) -> usize {
+    patterns
+        .iter()
+        .map(|pattern| pattern.uuid)
+        .chain(song.chain.iter().copied())
+        .max()
+        .map_or(0, |uuid| uuid + 1)
+}
+
+/// where the section with `uuid` is in `sections`. falls back to the first section when it's gone,
+/// like right after it was deleted.
+pub fn section_index(sections: &[Track], uuid: usize) -> usize {
+    sections
+        .iter()
+        .position(|section| section.uuid == uuid)
+        .unwrap_or(0)
+}
+
+/// where the pattern with `uuid` is in `patterns`, or the first pattern if there's no such pattern.
+pub fn pattern_index(patterns: &[Pattern], uuid: usize) -> usize {
+    patterns
+        .iter()
+        .position(|pattern| pattern.uuid == uuid)
+        .unwrap_or(0)
+}
+
+/// point every track that played through the synth `from` at `to` instead.
+pub fn rename_dev(tracks: &mut [Track], from: &str, to: &str) {
+    tracks
+        .iter_mut()
+        .filter(|track| track.dev == from)
+        .for_each(|track| track.dev = to.to_string());
+}
+
+/// a set of sections that play side by side, one column per device/channel, like an M8 chain
+/// row or an LSDJ song row.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pattern {
+    pub name: String,
+    pub uuid: usize,
+    /// the uuid of the section playing in each column, `None` leaves that column silent.
+    pub columns: Vec<Option<usize>>,
+}
+
+impl Pattern {
+    pub fn new(name: Option<String>, uuid: usize) -> Self {
+        let name = name.unwrap_or(format!("PATTERN-{uuid}"));
+
+        Self {
+            name,
+            uuid,
+            columns: vec![None],
         }
     }
+
+    /// the sections that this pattern plays, in column order. columns that point at a section that
+    /// no longer exists are skipped.
+    pub fn tracks(&self, sections: &[Track]) -> Vec<Track> {
+        self.columns
+            .iter()
+            .flatten()
+            .filter_map(|uuid| sections.iter().find(|section| section.uuid == *uuid))
+            .cloned()
+            .collect()
+    }
 }
 
-#[derive(Clone, Default, Debug, PartialEq, PartialOrd)]
+/// the arrangement of a whole song, the order that patterns are played in.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Song {
+    /// pattern uuids, played top to bottom. a pattern can appear any number of times.
+    pub chain: Vec<usize>,
+}
+
+impl Song {
+    /// the sections to play for each entry in the chain, ready to hand to the sequencer.
+    pub fn blocks(&self, patterns: &[Pattern], sections: &[Track]) -> Vec<Vec<Track>> {
+        self.chain
+            .iter()
+            .filter_map(|uuid| patterns.iter().find(|pattern| pattern.uuid == *uuid))
+            .map(|pattern| pattern.tracks(sections))
+            .collect()
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Step
 {
     pub note: Option<MidiNote>,
@@ -49,7 +303,7 @@ pub struct Step
     pub cmds: (TrackerCmd, TrackerCmd),
 }
 
-#[derive(Clone, Copy, Default, Debug, PartialEq, PartialOrd, Eq, Hash)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, PartialOrd, Eq, Hash, Serialize, Deserialize)]
 pub enum Intervals {
     #[default]
     Root,
@@ -63,14 +317,24 @@ pub enum Intervals {
     SharpSeventh,
 }
 
-#[derive(Clone, Default, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display)]
+#[derive(Clone, Default, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display, Serialize, Deserialize)]
 pub enum RepeatConf {
     #[default]
     HalfStep,
     Step,
 }
 
-#[derive(Clone, Default, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display)]
+impl RepeatConf {
+    /// the distance between two repeats, in steps.
+    pub fn steps(&self) -> f64 {
+        match self {
+            Self::HalfStep => 0.5,
+            Self::Step => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display, Serialize, Deserialize)]
 pub enum TrackerCmd {
     #[default]
     #[strum(to_string = "----")]
@@ -86,14 +350,14 @@ pub enum TrackerCmd {
     Roll {
         /// how many extra times to "roll" what ever is being played. a value of 1 would produce
         /// two 32th notes.
-        times: UsizeLessThan<{ N_STEPS * 2 - 1 }>,
+        times: UsizeLessThan<{ MAX_STEPS * 2 - 1 }>,
         // times: UsizeLessThan<5>,
     },
     /// Repeat every step.
     #[strum(to_string = "RPET")]
     Repeat {
         /// how many times to "repeat" what ever is being played.
-        times: UsizeLessThan<{ N_STEPS - 1 }>,
+        times: UsizeLessThan<{ MAX_STEPS - 1 }>,
     },
     // // NOTE: maybe remove Swing
     // #[strum(to_string = "SWNG")]
@@ -102,7 +366,7 @@ pub enum TrackerCmd {
     //     amt: UsizeLessThan<128>,
     // },
     #[strum(to_string = "HOLD")]
-    HoldFor { notes: UsizeLessThan<{ N_STEPS }> },
+    HoldFor { notes: UsizeLessThan<{ MAX_STEPS }> },
     /// stop all notes on device
     #[strum(to_string = "STOP")]
     Panic,
@@ -111,11 +375,100 @@ pub enum TrackerCmd {
         cc_param: u8,
         arg: u8,
     },
+    /// change the tempo of the whole song, from the next step on.
+    #[strum(to_string = "T{bpm:03}")]
+    Tempo { bpm: u16 },
     #[strum(transparent)]
     Custom(Sf2Cmd),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display)]
+impl TrackerCmd {
+    /// one of each command, with default args, and every `Sf2Cmd` listed on its own. these are
+    /// what the command picker offers.
+    pub fn kinds() -> Vec<Self> {
+        vec![
+            Self::None,
+            Self::Chord { chord: vec![4, 7] },
+            Self::Roll {
+                times: UsizeLessThan(1),
+            },
+            Self::Repeat {
+                times: UsizeLessThan(1),
+            },
+            Self::HoldFor {
+                notes: UsizeLessThan(1),
+            },
+            Self::Panic,
+            Self::MidiCmd {
+                cc_param: 74,
+                arg: 64,
+            },
+            Self::Tempo { bpm: 120 },
+            Self::Custom(Sf2Cmd::Atk(0)),
+            Self::Custom(Sf2Cmd::Dcy(0)),
+            Self::Custom(Sf2Cmd::Dcy2(0)),
+            Self::Custom(Sf2Cmd::Sus(ENVELOPE_MAX)),
+            Self::Custom(Sf2Cmd::Rel(0)),
+            Self::Custom(Sf2Cmd::Volume(1.0)),
+            Self::Custom(Sf2Cmd::Offset(0)),
+        ]
+    }
+
+    /// the biggest arg this command can take in a track `len` steps long, for the commands that
+    /// count steps.
+    pub fn max_steps(&self, len: usize) -> Option<usize> {
+        match self {
+            Self::Roll { .. } => Some((len * 2).saturating_sub(2)),
+            Self::Repeat { .. } => Some(len.saturating_sub(2)),
+            Self::HoldFor { .. } => Some(len.saturating_sub(1)),
+            _ => None,
+        }
+    }
+
+    /// whether this command's args are in range, and fit in a track `len` steps long.
+    pub fn fits(&self, len: usize) -> bool {
+        let arg = match self {
+            Self::Tempo { bpm } => return (MIN_BPM..=MAX_BPM).contains(&(*bpm as usize)),
+            Self::Custom(cmd) => return cmd.in_range(),
+            Self::Roll { times } => times.0,
+            Self::Repeat { times } => times.0,
+            Self::HoldFor { notes } => notes.0,
+            _ => return true,
+        };
+
+        self.max_steps(len).is_some_and(|max| arg <= max)
+    }
+
+    /// cut the args down to fit a track `len` steps long.
+    pub fn clamp_to(&mut self, len: usize) {
+        let max = self.max_steps(len).unwrap_or(0);
+
+        match self {
+            Self::Roll { times } => times.0 = times.0.min(max),
+            Self::Repeat { times } => times.0 = times.0.min(max),
+            Self::HoldFor { notes } => notes.0 = notes.0.min(max),
+            _ => {}
+        }
+    }
+
+    /// whether `self` and `other` are the same command, whatever their args are.
+    pub fn same_kind(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Custom(a), Self::Custom(b)) => discriminant(a) == discriminant(b),
+            (a, b) => discriminant(a) == discriminant(b),
+        }
+    }
+}
+
+/// the biggest value an envelope or offset `Sf2Cmd` takes.
+pub const ENVELOPE_MAX: usize = 127;
+
+/// sets a parameter of the synth a track plays.
+///
+/// the envelope commands take a raw 0-`ENVELOPE_MAX`, like a CC, rather than a time in ms. it
+/// turns the engine's knob for that stage from all the way down to all the way up, so how long
+/// that is depends on the engine.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, EnumString, strum_macros::Display, Serialize, Deserialize)]
 pub enum Sf2Cmd {
     #[strum(to_string = "Atk-")]
     Atk(usize),
@@ -123,12 +476,50 @@ pub enum Sf2Cmd {
     Dcy(usize),
     #[strum(to_string = "Dcy2")]
     Dcy2(usize),
+    /// a level rather than a time.
     #[strum(to_string = "Sus-")]
     Sus(usize),
     #[strum(to_string = "Rel-")]
     Rel(usize),
+    /// linear gain from 0.0 to 1.0, which sets the mixer strip of the synth.
     #[strum(to_string = "Vol-")]
     Volume(f32),
+    /// start the note on the same step part way into its sample, from 0 (the start) to
+    /// `ENVELOPE_MAX` (the end). only samplers have samples to start in.
+    #[strum(to_string = "Ofs-")]
+    Offset(usize),
+}
+
+impl Sf2Cmd {
+    /// how far to turn the knob for this, from 0.0 to 1.0.
+    pub fn amount(&self) -> f32 {
+        match self {
+            Self::Atk(value)
+            | Self::Dcy(value)
+            | Self::Dcy2(value)
+            | Self::Sus(value)
+            | Self::Rel(value)
+            | Self::Offset(value) => {
+                (*value).min(ENVELOPE_MAX) as f32 / ENVELOPE_MAX as f32
+            }
+            Self::Volume(volume) => volume.clamp(0.0, 1.0),
+        }
+    }
+
+    /// whether the value is in range.
+    pub fn in_range(&self) -> bool {
+        match self {
+            Self::Atk(value)
+            | Self::Dcy(value)
+            | Self::Dcy2(value)
+            | Self::Sus(value)
+            | Self::Rel(value)
+            | Self::Offset(value) => {
+                *value <= ENVELOPE_MAX
+            }
+            Self::Volume(volume) => (0.0..=1.0).contains(volume),
+        }
+    }
 }
 
 impl Default for Sf2Cmd {
@@ -141,6 +532,131 @@ impl Default for Sf2Cmd {
 mod tests {
     use super::*;
 
+    #[test]
+    fn length_and_grid() {
+        let mut track = Track::new(None, 0, "Default".into());
+        track.steps[3].cmds = (
+            TrackerCmd::HoldFor {
+                notes: UsizeLessThan(100),
+            },
+            TrackerCmd::Roll {
+                times: UsizeLessThan(200),
+            },
+        );
+        assert_eq!(track.check(), Ok(()));
+
+        track.set_len(1000);
+        assert_eq!(track.steps.len(), MAX_STEPS);
+        track.set_len(0);
+        assert_eq!(track.steps.len(), 1);
+
+        track.set_len(32);
+        assert_eq!(
+            track.steps[3].cmds,
+            (
+                TrackerCmd::HoldFor {
+                    notes: UsizeLessThan(31)
+                },
+                TrackerCmd::Roll {
+                    times: UsizeLessThan(62)
+                },
+            )
+        );
+        assert_eq!(track.check(), Ok(()));
+
+        track.steps[3].cmds.1 = TrackerCmd::Repeat {
+            times: UsizeLessThan(31),
+        };
+        assert!(track.check().is_err());
+
+        // 3/4 at 4 lines per beat
+        track.beats = 3;
+        assert!(track.is_bar(0) && track.is_bar(12) && !track.is_bar(16));
+        assert!(track.is_beat(4) && !track.is_beat(5));
+    }
+
+    #[test]
+    fn uuids() {
+        let mut sections = vec![
+            Track::new(None, 0, "Default".into()),
+            Track::new(None, 3, "Default".into()),
+        ];
+        let mut pattern = Pattern::new(None, 0);
+        pattern.columns = vec![Some(5), None];
+
+        assert_eq!(next_section_uuid(&[], &[]), 0);
+        assert_eq!(next_section_uuid(&sections, &[]), 4);
+        // 5 was deleted, but the pattern still wants it back
+        assert_eq!(next_section_uuid(&sections, &[pattern]), 6);
+
+        sections.remove(0);
+        assert_eq!(section_index(&sections, 3), 0);
+        sections.insert(0, Track::new(None, 9, "Default".into()));
+        assert_eq!(section_index(&sections, 3), 1);
+        assert_eq!(section_index(&sections, 0), 0);
+
+        let song = Song { chain: vec![7] };
+        assert_eq!(next_pattern_uuid(&[], &Song::default()), 0);
+        assert_eq!(next_pattern_uuid(&[Pattern::new(None, 2)], &Song::default()), 3);
+        assert_eq!(next_pattern_uuid(&[Pattern::new(None, 2)], &song), 8);
+    }
+
+    #[test]
+    fn song_blocks() {
+        let sections = vec![
+            Track::new(None, 0, "Default".into()),
+            Track::new(None, 1, "Bass".into()),
+        ];
+        let mut intro = Pattern::new(None, 0);
+        intro.columns = vec![Some(0), None];
+        let mut verse = Pattern::new(None, 1);
+        verse.columns = vec![Some(0), Some(1), Some(7)];
+        let song = Song {
+            chain: vec![0, 1, 0, 3],
+        };
+
+        let blocks = song.blocks(&[intro, verse], &sections);
+
+        assert_eq!(
+            blocks,
+            vec![
+                vec![sections[0].clone()],
+                vec![sections[0].clone(), sections[1].clone()],
+                vec![sections[0].clone()],
+            ]
+        );
+    }
+
+    #[test]
+    fn cmd_kinds() {
+        let kinds = TrackerCmd::kinds();
+
+        for (i, kind) in kinds.iter().enumerate() {
+            for (j, other) in kinds.iter().enumerate() {
+                assert_eq!(kind.same_kind(other), i == j, "{kind:?} vs {other:?}");
+            }
+        }
+
+        assert!(TrackerCmd::Chord { chord: vec![] }.same_kind(&kinds[1]));
+        assert!(TrackerCmd::Custom(Sf2Cmd::Rel(99)).same_kind(&kinds[12]));
+        assert_eq!(UsizeLessThan::<{ N_STEPS }>::saturating(1000).0, N_STEPS - 1);
+    }
+
+    #[test]
+    fn sf2_cmd_ranges() {
+        assert_eq!(Sf2Cmd::Atk(0).amount(), 0.0);
+        assert_eq!(Sf2Cmd::Rel(ENVELOPE_MAX).amount(), 1.0);
+        assert_eq!(Sf2Cmd::Sus(1000).amount(), 1.0);
+        assert_eq!(Sf2Cmd::Volume(-1.0).amount(), 0.0);
+
+        assert!(TrackerCmd::Custom(Sf2Cmd::Dcy(ENVELOPE_MAX)).fits(1));
+        assert!(!TrackerCmd::Custom(Sf2Cmd::Dcy2(ENVELOPE_MAX + 1)).fits(1));
+        assert!(!TrackerCmd::Custom(Sf2Cmd::Volume(1.5)).fits(1));
+        assert!(!TrackerCmd::Custom(Sf2Cmd::Volume(f32::NAN)).fits(1));
+        assert!(!TrackerCmd::Custom(Sf2Cmd::Offset(ENVELOPE_MAX + 1)).fits(1));
+        assert_eq!(Sf2Cmd::Offset(ENVELOPE_MAX).amount(), 1.0);
+    }
+
     #[test]
     fn tracker_cmd_display() {
         struct MidiCmd<'a>(TrackerCmd, &'a str);
@@ -208,6 +724,8 @@ mod tests {
                 },
                 "CCFF",
             ),
+            MidiCmd(TrackerCmd::Tempo { bpm: 98 }, "T098"),
+            MidiCmd(TrackerCmd::Tempo { bpm: 140 }, "T140"),
         ] {
             let cmd = format!("{cmd}");
             assert_eq!(
@@ -0,0 +1,140 @@
+#!/usr/bin/env python3
+"""writes tiny.sf2, the SoundFont the unit tests load.
+
+presets:
+- bank 0, preset 0, "Keys": a looped sine. keys 0-71 play "SineLow" (root 69), keys 72-127 play
+  "SineHigh" (root 81) when hit hard (velocity 64-127) and "SineLow" when hit softly.
+- bank 128, preset 0, "Kit": "Click", a one shot with no loop.
+"""
+import math
+import struct
+import sys
+
+RATE = 22050
+PADDING = 46  # zeros the spec wants after every sample
+
+
+def sine(period, periods):
+    return [int(16000 * math.sin(2 * math.pi * i / period)) for i in range(period * periods)]
+
+
+def chunk(tag, data):
+    data = bytes(data)
+    pad = b"\0" if len(data) % 2 else b""
+    return tag + struct.pack("<I", len(data)) + data + pad
+
+
+def list_chunk(tag, chunks):
+    return chunk(b"LIST", tag + b"".join(chunks))
+
+
+def name(text):
+    return text.encode()[:20].ljust(20, b"\0")
+
+
+def gen(oper, amount):
+    return struct.pack("<Hh", oper, amount)
+
+
+def gen_range(oper, lo, hi):
+    return struct.pack("<HBB", oper, lo, hi)
+
+
+KEY_RANGE, VEL_RANGE, INSTRUMENT, SAMPLE_ID = 43, 44, 41, 53
+ATTACK, HOLD, DECAY, SUSTAIN, RELEASE = 34, 35, 36, 37, 38
+SAMPLE_MODES, ROOT_KEY, ATTENUATION = 54, 58, 48
+
+samples = [
+    # name, frames, loop, root
+    ("SineLow", sine(50, 4), (100, 150), 69),
+    ("SineHigh", sine(25, 8), (100, 125), 81),
+    ("Click", [int(16000 * (1 - i / 32)) for i in range(32)], (0, 0), 60),
+]
+
+smpl = b""
+shdr = b""
+at = 0
+for sample_name, frames, (loop_start, loop_end), root in samples:
+    smpl += struct.pack(f"<{len(frames) + PADDING}h", *frames, *[0] * PADDING)
+    shdr += name(sample_name) + struct.pack(
+        "<IIIIIBbHH", at, at + len(frames), at + loop_start, at + loop_end, RATE, root, 0, 0, 1
+    )
+    at += len(frames) + PADDING
+shdr += name("EOS") + struct.pack("<IIIIIBbHH", 0, 0, 0, 0, 0, 0, 0, 0, 0)
+
+# instrument zones, each a list of generators in the order the spec wants them
+instruments = [
+    (
+        "Keys",
+        [
+            # global zone
+            [gen(ATTACK, -7973), gen(HOLD, -12000), gen(DECAY, -1200), gen(SUSTAIN, 60),
+             gen(RELEASE, -3986), gen(SAMPLE_MODES, 1)],
+            [gen_range(KEY_RANGE, 0, 71), gen(SAMPLE_ID, 0)],
+            [gen_range(KEY_RANGE, 72, 127), gen_range(VEL_RANGE, 64, 127), gen(SAMPLE_ID, 1)],
+            [gen_range(KEY_RANGE, 72, 127), gen_range(VEL_RANGE, 0, 63), gen(ROOT_KEY, 81),
+             gen(SAMPLE_ID, 0)],
+        ],
+    ),
+    ("Click", [[gen(ATTENUATION, 60), gen(SAMPLE_MODES, 0), gen(SAMPLE_ID, 2)]]),
+]
+presets = [
+    ("Keys", 0, 0, [[gen(INSTRUMENT, 0)]]),
+    ("Kit", 0, 128, [[gen(INSTRUMENT, 1)]]),
+]
+
+
+def zones(records, terminal):
+    """the header, bag and generator chunks' bodies for instruments or presets."""
+    headers, bags, gens = b"", b"", b""
+    n_bags = n_gens = 0
+    for header, zone_list in records:
+        headers += header(n_bags)
+        for zone in zone_list:
+            bags += struct.pack("<HH", n_gens, 0)
+            gens += b"".join(zone)
+            n_bags += 1
+            n_gens += len(zone)
+    headers += terminal(n_bags)
+    bags += struct.pack("<HH", n_gens, 0)
+    gens += gen(0, 0)
+    return headers, bags, gens
+
+
+inst, ibag, igen = zones(
+    [(lambda bag, n=n: name(n) + struct.pack("<H", bag), z) for n, z in instruments],
+    lambda bag: name("EOI") + struct.pack("<H", bag),
+)
+phdr, pbag, pgen = zones(
+    [
+        (lambda bag, n=n, p=p, b=b: name(n) + struct.pack("<HHHIII", p, b, bag, 0, 0, 0), z)
+        for n, p, b, z in presets
+    ],
+    lambda bag: name("EOP") + struct.pack("<HHHIII", 0, 0, bag, 0, 0, 0),
+)
+empty_mod = b"\0" * 10
+
+body = b"sfbk" + b"".join(
+    [
+        list_chunk(b"INFO", [chunk(b"ifil", struct.pack("<HH", 2, 1)), chunk(b"INAM", b"tiny\0\0")]),
+        list_chunk(b"sdta", [chunk(b"smpl", smpl)]),
+        list_chunk(
+            b"pdta",
+            [
+                chunk(b"phdr", phdr),
+                chunk(b"pbag", pbag),
+                chunk(b"pmod", empty_mod),
+                chunk(b"pgen", pgen),
+                chunk(b"inst", inst),
+                chunk(b"ibag", ibag),
+                chunk(b"imod", empty_mod),
+                chunk(b"igen", igen),
+                chunk(b"shdr", shdr),
+            ],
+        ),
+    ]
+)
+
+out = sys.argv[1] if len(sys.argv) > 1 else "tiny.sf2"
+with open(out, "wb") as f:
+    f.write(chunk(b"RIFF", body))